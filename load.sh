@@ -13,10 +13,15 @@ then
     echo 'INFO: loading siblings to dev'
     BUCKET="xai-cfg"
     SIBLINGS_FILE="siblings-dev.json"
+elif [[ "$BUILD" = 'staging' ]]
+then
+    echo 'INFO: loading siblings to staging'
+    BUCKET="xai-cfg"
+    SIBLINGS_FILE="siblings-staging.json"
 else
-    echo "Invalid `build`. Allowed [prod | dev]"
+    echo "Invalid `build`. Allowed [prod | dev | staging]"
     exit
 fi
 
 # gcloud storage cp gs://$BUCKET/$SIBLINGS_FILE $SIBLINGS_FILE
-X_ENV=$BUILD RUST_LOG=info cargo run --bin siblings-cli --release -- --nocapture
\ No newline at end of file
+X_ENV=$BUILD RUST_LOG=info cargo run --bin siblings-cli --release -- load
\ No newline at end of file
@@ -0,0 +1,457 @@
+//! Node-local sidecar that owns the Redis connection on behalf of every process on the node.
+//!
+//! Clients (see [`Siblings::sidecar`](crate::Siblings::sidecar)) send one JSON line per lookup,
+//! `{"env":"dev","sibling":"k9"}` for a record (`"pinned":3` for an archived version) or
+//! `{"key":"dev-webhook-k9"}` for any other record key, and get back `{"value":{...}}`,
+//! `{"value":null}` when nothing is set, or `{"error":"..."}`. Records are read the way
+//! [`RedisSource`] reads them, in either [`Layout`](crate::Layout).
+//!
+//! Values are kept in memory for the agent's ttl, up to [`Agent::with_cache_size`] bytes, the
+//! oldest dropped first. [`Agent::with_updates_from`] follows the `siblings:updated` channel and
+//! drops a record as soon as a publish or prune announces it changed.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use futures_util::StreamExt;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::RwLock,
+};
+
+use crate::{
+    keyspace::{subscribe, RESUBSCRIBE_AFTER},
+    ChangeEvent, Env, KeyScheme, RedisSource,
+};
+
+pub const DEFAULT_SOCKET: &str = "/var/run/siblings-agent.sock";
+
+/// Bytes of keys and values the agent keeps in memory, unless set with [`Agent::with_cache_size`]
+pub const DEFAULT_CACHE_SIZE: usize = 16 << 20;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Request {
+    Record {
+        env: String,
+        sibling: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pinned: Option<u64>,
+    },
+    Key {
+        key: String,
+    },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Response {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Client side: the record of `sibling` in `env`, or its archived `pinned` version, read through
+/// the agent; empty when there is none
+pub(crate) async fn read_record(
+    socket: &Path,
+    env: &Env,
+    sibling: &str,
+    pinned: Option<u64>,
+) -> Result<Vec<u8>> {
+    let req = Request::Record {
+        env: env.name().to_owned(),
+        sibling: sibling.to_owned(),
+        pinned,
+    };
+    ask(socket, &req).await
+}
+
+/// Client side: reads `key` through the agent, same contract as `Db::get_cache_for_pool`
+/// (an empty body means the key is not set)
+pub(crate) async fn get_cache(socket: &Path, key: &str) -> Result<Vec<u8>> {
+    ask(
+        socket,
+        &Request::Key {
+            key: key.to_string(),
+        },
+    )
+    .await
+}
+
+async fn ask(socket: &Path, req: &Request) -> Result<Vec<u8>> {
+    let stream = UnixStream::connect(socket).await?;
+    let (r, mut w) = stream.into_split();
+
+    let mut line = serde_json::to_vec(req)?;
+    line.push(b'\n');
+    w.write_all(&line).await?;
+
+    let mut line = String::new();
+    BufReader::new(r).read_line(&mut line).await?;
+    let resp: Response = serde_json::from_str(&line)?;
+
+    if let Some(e) = resp.error {
+        bail!("siblings-agent: {e}");
+    }
+
+    match resp.value {
+        Some(v) => Ok(serde_json::to_vec(&v)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub struct Agent {
+    redis: RedisSource,
+    ttl: Duration,
+    cache_size: usize,
+    updates: Option<String>,
+    cache: RwLock<Cache>,
+}
+
+/// Values by the key they're stored at, and how many bytes they take together
+#[derive(Default)]
+struct Cache {
+    values: HashMap<String, Cached>,
+    bytes: usize,
+    inserted: u64,
+}
+
+struct Cached {
+    at: Instant,
+    /// Insertion order, the lowest is dropped first
+    order: u64,
+    value: Option<Value>,
+    size: usize,
+}
+
+impl Cache {
+    /// Keeps `value` as read from `size` bytes, dropping the oldest values while over `max`
+    fn insert(&mut self, key: &str, value: Option<Value>, size: usize, max: usize) {
+        self.remove(key);
+        self.inserted += 1;
+        let cached = Cached {
+            at: Instant::now(),
+            order: self.inserted,
+            value,
+            size: key.len() + size,
+        };
+        self.bytes += cached.size;
+        self.values.insert(key.to_owned(), cached);
+
+        while self.bytes > max
+            && let Some(oldest) = self
+                .values
+                .iter()
+                .min_by_key(|(_, cached)| cached.order)
+                .map(|(key, _)| key.clone())
+        {
+            self.remove(&oldest);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(cached) = self.values.remove(key) {
+            self.bytes -= cached.size;
+        }
+    }
+}
+
+impl Agent {
+    /// Values are served from memory for `ttl` before being re-read from Redis
+    #[cfg(feature = "db")]
+    pub fn new(db: Arc<db::RedisPool>, ttl: Duration) -> Self {
+        Self::with_redis(RedisSource::pool(db), ttl)
+    }
+
+    /// [`Self::new`] reading the Redis at `url` instead of the `db` crate's
+    pub async fn connect(url: &str, ttl: Duration) -> Result<Self> {
+        Ok(Self::with_redis(RedisSource::connect(url).await?, ttl))
+    }
+
+    fn with_redis(redis: RedisSource, ttl: Duration) -> Self {
+        Self {
+            redis,
+            ttl,
+            cache_size: DEFAULT_CACHE_SIZE,
+            updates: None,
+            cache: Default::default(),
+        }
+    }
+
+    /// Key scheme of the clients, to find their records and tell them from anything else in Redis
+    pub fn with_key_scheme(mut self, keys: KeyScheme) -> Self {
+        self.redis.keys = keys;
+        self
+    }
+
+    /// Keeps at most `bytes` of keys and values in memory instead of [`DEFAULT_CACHE_SIZE`]
+    pub fn with_cache_size(mut self, bytes: usize) -> Self {
+        self.cache_size = bytes;
+        self
+    }
+
+    /// Subscribes to `siblings:updated` on the Redis at `url` while serving, dropping each
+    /// record announced changed so the next lookup reads it again
+    pub fn with_updates_from(mut self, url: impl Into<String>) -> Self {
+        self.updates = Some(url.into());
+        self
+    }
+
+    pub async fn serve(self, socket: impl AsRef<Path>) -> Result<()> {
+        let socket = socket.as_ref();
+        // a previous agent may have left its socket behind
+        if socket.exists() {
+            std::fs::remove_file(socket)?;
+        }
+
+        let listener = UnixListener::bind(socket)?;
+        info!("siblings-agent: listening on {socket:?}");
+
+        let agent = Arc::new(self);
+        if let Some(url) = &agent.updates {
+            let client = redis::Client::open(url.as_str())?;
+            let pubsub = subscribe(&client, &[agent.redis.keys.channel()]).await?;
+            tokio::spawn(agent.clone().follow(client, pubsub));
+        }
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let agent = agent.clone();
+            tokio::spawn(async move {
+                if let Err(e) = agent.handle(stream).await {
+                    warn!("siblings-agent: connection closed: {e}");
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, stream: UnixStream) -> Result<()> {
+        let (r, mut w) = stream.into_split();
+        let mut lines = BufReader::new(r).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            let resp = match serde_json::from_str::<Request>(&line) {
+                Ok(req) => match self.lookup(&req).await {
+                    Ok(value) => Response {
+                        value,
+                        ..Default::default()
+                    },
+                    Err(e) => Response {
+                        error: Some(e.to_string()),
+                        ..Default::default()
+                    },
+                },
+                Err(e) => Response {
+                    error: Some(format!("bad request: {e}")),
+                    ..Default::default()
+                },
+            };
+
+            let mut out = serde_json::to_vec(&resp)?;
+            out.push(b'\n');
+            w.write_all(&out).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn lookup(&self, req: &Request) -> Result<Option<Value>> {
+        let keys = &self.redis.keys;
+        let key = match req {
+            Request::Record {
+                env,
+                sibling,
+                pinned,
+            } => {
+                let env = Env::from_name(env);
+                match pinned {
+                    Some(version) => keys.key(&env, &keys.archive(sibling, *version)),
+                    None => keys.key(&env, &keys.endpoint(sibling)),
+                }
+            }
+            // the agent only proxies endpoint and webhook records, never arbitrary keys
+            Request::Key { key } if !keys.is_record(key) => {
+                bail!("key {key} is not an endpoint key")
+            }
+            Request::Key { key } => key.clone(),
+        };
+
+        if let Some(cached) = self.cache.read().await.values.get(&key)
+            && cached.at.elapsed() < self.ttl
+        {
+            return Ok(cached.value.clone());
+        }
+
+        let read = match req {
+            Request::Record {
+                env,
+                sibling,
+                pinned,
+            } => {
+                self.redis
+                    .read(&Env::from_name(env), sibling, *pinned)
+                    .await
+            }
+            Request::Key { key } => self.redis.get(key).await,
+        };
+        match read {
+            Ok(data) => {
+                let value = if data.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::from_slice::<Value>(&data)?)
+                };
+                self.cache
+                    .write()
+                    .await
+                    .insert(&key, value.clone(), data.len(), self.cache_size);
+
+                Ok(value)
+            }
+            Err(e) => {
+                // serve stale rather than failing every process on the node
+                if let Some(cached) = self.cache.read().await.values.get(&key) {
+                    warn!("siblings-agent: redis read for {key} failed, serving stale: {e}");
+                    return Ok(cached.value.clone());
+                }
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Drops the record of every change announced on `pubsub`, resubscribing through `client`
+    /// every [`RESUBSCRIBE_AFTER`] when the subscription drops
+    async fn follow(self: Arc<Self>, client: redis::Client, mut pubsub: redis::aio::PubSub) {
+        let channel = self.redis.keys.channel();
+        loop {
+            let mut messages = pubsub.into_on_message();
+            while let Some(msg) = messages.next().await {
+                match serde_json::from_slice::<ChangeEvent>(msg.get_payload_bytes()) {
+                    Ok(event) => self.changed(&event).await,
+                    Err(e) => warn!("siblings-agent: unreadable announcement: {e}"),
+                }
+            }
+
+            warn!("siblings-agent: {channel} subscription dropped, resubscribing");
+            pubsub = loop {
+                tokio::time::sleep(RESUBSCRIBE_AFTER).await;
+                match subscribe(&client, std::slice::from_ref(&channel)).await {
+                    Ok(pubsub) => break pubsub,
+                    Err(e) => warn!("siblings-agent: resubscribing failed: {e}"),
+                }
+            };
+            // changes may have been missed meanwhile
+            *self.cache.write().await = Cache::default();
+        }
+    }
+
+    async fn changed(&self, event: &ChangeEvent) {
+        let keys = &self.redis.keys;
+        let key = keys.key(&Env::from_name(&event.env), &keys.endpoint(&event.sibling));
+        debug!("siblings-agent: {key} {:?}, dropped", event.kind);
+        self.cache.write().await.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::{KeyScheme, Layout};
+
+    /// A Redis answering GET and HGET from `records`, by key and by `hash field`, and OK to
+    /// anything else
+    async fn fake_redis(records: &'static [(&'static str, &'static str)]) -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("redis://{}", listener.local_addr()?);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 4096];
+                    while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                        // whole commands per read, pipelined ones (the handshake's) included
+                        let text = String::from_utf8_lossy(&buf[..n]).to_string();
+                        let mut lines = text.split("\r\n");
+                        let mut reply = String::new();
+                        while let Some(count) = lines.next().and_then(|l| l.strip_prefix('*')) {
+                            let count = count.parse().unwrap_or(0);
+                            let args = lines.by_ref().take(2 * count).skip(1).step_by(2);
+                            reply += &match args.collect::<Vec<_>>().as_slice() {
+                                ["GET", key] => value(records, key),
+                                ["HGET", hash, field] => value(records, &format!("{hash} {field}")),
+                                _ => "+OK\r\n".to_string(),
+                            };
+                        }
+                        if stream.write_all(reply.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(url)
+    }
+
+    fn value(records: &[(&str, &str)], key: &str) -> String {
+        match records.iter().find(|(k, _)| *k == key) {
+            Some((_, v)) => format!("${}\r\n{v}\r\n", v.len()),
+            None => "$-1\r\n".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_json_lines_over_the_socket() -> anyhow::Result<()> {
+        let url = fake_redis(&[
+            ("siblings:dev k9", r#"{"default":"https://k9.dev"}"#),
+            ("dev-ep-matrix@3", r#"{"default":"https://matrix.v3"}"#),
+        ])
+        .await?;
+        let socket =
+            std::env::temp_dir().join(format!("siblings-agent-{}.sock", std::process::id()));
+        let agent = Agent::connect(&url, Duration::from_secs(30))
+            .await?
+            .with_key_scheme(KeyScheme::default().with_layout(Layout::Hash));
+        tokio::spawn(agent.serve(socket.clone()));
+        while !socket.exists() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let record = read_record(&socket, &Env::Dev, "k9", None).await?;
+        assert_eq!(record, br#"{"default":"https://k9.dev"}"#);
+        let pinned = read_record(&socket, &Env::Dev, "matrix", Some(3)).await?;
+        assert_eq!(pinned, br#"{"default":"https://matrix.v3"}"#);
+        assert!(read_record(&socket, &Env::Dev, "bureau", None)
+            .await?
+            .is_empty());
+        assert!(get_cache(&socket, "usage-k9").await.is_err());
+
+        std::fs::remove_file(&socket)?;
+        Ok(())
+    }
+
+    #[test]
+    fn cache_stays_under_its_size() {
+        let mut cache = Cache::default();
+        cache.insert("dev-ep-k9", None, 20, 64);
+        cache.insert("dev-ep-matrix", None, 20, 64);
+        assert_eq!(cache.values.len(), 2);
+
+        cache.insert("dev-ep-bureau", None, 20, 64);
+        assert!(!cache.values.contains_key("dev-ep-k9"));
+        assert!(cache.bytes <= 64);
+    }
+}
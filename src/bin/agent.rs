@@ -0,0 +1,54 @@
+use std::{env, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use log::info;
+use siblings::{
+    agent::{Agent, DEFAULT_CACHE_SIZE, DEFAULT_SOCKET},
+    duration_from_env, parse_size, Env, KeyScheme,
+};
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+
+    serve().await.unwrap();
+}
+
+async fn serve() -> Result<()> {
+    let socket = env::var("SIBLINGS_AGENT_SOCKET").unwrap_or_else(|_| DEFAULT_SOCKET.to_string());
+    // SIBLINGS_AGENT_TTL_SECS is the older name of the knob
+    let ttl = match duration_from_env("SIBLINGS_AGENT_TTL")? {
+        Some(ttl) => Some(ttl),
+        None => duration_from_env("SIBLINGS_AGENT_TTL_SECS")?,
+    }
+    .unwrap_or(Duration::from_secs(30));
+    let cache_size = match env::var("SIBLINGS_AGENT_CACHE_SIZE") {
+        Ok(size) => usize::try_from(parse_size("SIBLINGS_AGENT_CACHE_SIZE", &size)?)?,
+        Err(_) => DEFAULT_CACHE_SIZE,
+    };
+
+    let env = Env::new_from_env();
+    let keys = KeyScheme::from_env()?;
+    info!("Starting siblings-agent for {env:?} on {socket}");
+
+    let db = Arc::new(db::Db::connect_redis(!env.is_prod()).await?);
+    let mut agent = Agent::new(db.clone(), ttl)
+        .with_key_scheme(keys.clone())
+        .with_cache_size(cache_size);
+    if let Ok(url) = env::var("SIBLINGS_AGENT_REDIS_URL") {
+        agent = agent.with_updates_from(url);
+    }
+
+    #[cfg(feature = "server")]
+    if let Ok(addr) = env::var("SIBLINGS_AGENT_HTTP") {
+        let siblings = siblings::Siblings::new(db, None)
+            .await
+            .with_key_scheme(keys);
+        let http = siblings::server::serve(siblings, addr.parse()?, ttl);
+
+        tokio::try_join!(agent.serve(socket), http)?;
+        return Ok(());
+    }
+
+    agent.serve(socket).await
+}
@@ -0,0 +1,50 @@
+use std::{env, fs, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use log::info;
+use siblings::{parse_duration, Env, KeyScheme, Siblings};
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    serve(&args).await.unwrap();
+}
+
+/// Serves the status page of `X_ENV` on `--addr` (default `127.0.0.1:8090`), reloading every
+/// `--refresh` (default 30s); with `--out status.html`, writes the page once and exits
+async fn serve(args: &[String]) -> Result<()> {
+    let refresh = match flag(args, "--refresh") {
+        Some(refresh) => parse_duration("--refresh", refresh)?,
+        None => Duration::from_secs(30),
+    };
+
+    let env = env::var("X_ENV").map_or(Env::Prod, |e| Env::from_name(&e));
+    let db = Arc::new(db::Db::connect_redis(!env.is_prod()).await?);
+    let siblings = Siblings::new(db, None)
+        .await
+        .with_env(env)
+        .with_key_scheme(KeyScheme::from_env()?);
+
+    if let Some(out) = flag(args, "--out") {
+        let status = siblings.platform_status().await?;
+        fs::write(out, status.to_html(refresh))?;
+        info!(
+            "siblings-status: wrote {} siblings to {out}",
+            status.siblings.len()
+        );
+        return Ok(());
+    }
+
+    let addr = flag(args, "--addr").unwrap_or("127.0.0.1:8090").parse()?;
+    siblings::server::serve_status(siblings, addr, refresh).await
+}
+
+/// Value of `--flag value`
+fn flag<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
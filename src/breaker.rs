@@ -0,0 +1,203 @@
+//! Circuit breaker on Redis reads.
+//!
+//! After [`DEFAULT_BREAKER_THRESHOLD`] consecutive reads find Redis unreachable or time out, the
+//! circuit opens: lookups stop reading Redis and fail fast with [`SiblingsError::CircuitOpen`],
+//! which [`Siblings::endpoint`] answers from an expired record in memory when it has one. A
+//! background task probes Redis after [`MIN_PROBE_BACKOFF`], doubling the wait after every failed
+//! probe up to [`MAX_PROBE_BACKOFF`], and closes the circuit once a probe succeeds.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{cache, Siblings, SiblingsError};
+
+/// Consecutive failed reads that open the circuit
+pub const DEFAULT_BREAKER_THRESHOLD: u32 = 5;
+pub const MIN_PROBE_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_PROBE_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub(crate) struct Breaker {
+    /// Failures that open the circuit, 0 never opens it
+    threshold: u32,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    failures: u32,
+    /// When the circuit opened, `None` while closed
+    opened_at: Option<Instant>,
+}
+
+impl Breaker {
+    pub(crate) fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            state: Mutex::default(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        self.state().opened_at.is_some()
+    }
+
+    /// `Err` while the circuit is open
+    fn allow(&self, now: Instant) -> Result<(), SiblingsError> {
+        match self.state().opened_at {
+            Some(at) => Err(SiblingsError::CircuitOpen(
+                now.saturating_duration_since(at),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a read done at `now`; `true` when this failure just opened the circuit
+    fn observe<T>(&self, read: &Result<T, SiblingsError>, now: Instant) -> bool {
+        let mut state = self.state();
+        match read {
+            // a Redis too slow to answer is as good as down
+            Err(SiblingsError::RedisUnreachable(_) | SiblingsError::Timeout(_)) => {
+                state.failures += 1;
+                let opens = self.threshold > 0
+                    && state.failures >= self.threshold
+                    && state.opened_at.is_none();
+                if opens {
+                    state.opened_at = Some(now);
+                }
+                opens
+            }
+            // throttled, deserialize and other errors say nothing about Redis being up
+            Err(_) => false,
+            Ok(_) => {
+                state.failures = 0;
+                false
+            }
+        }
+    }
+
+    fn close(&self) {
+        *self.state() = State::default();
+    }
+}
+
+impl Siblings {
+    /// Opens the circuit after `threshold` consecutive unreachable reads instead of
+    /// [`DEFAULT_BREAKER_THRESHOLD`]; 0 keeps reading Redis whatever happens
+    pub fn with_circuit_breaker(mut self, threshold: u32) -> Self {
+        self.breaker = std::sync::Arc::new(Breaker::new(threshold));
+        self
+    }
+
+    /// Whether reads are failing fast while Redis is probed
+    pub fn circuit_open(&self) -> bool {
+        self.breaker.is_open()
+    }
+
+    /// Runs the Redis read `read` through the breaker, failing it once it takes longer than the
+    /// read timeout
+    pub(crate) async fn guarded<T>(
+        &self,
+        read: impl std::future::Future<Output = Result<T, SiblingsError>>,
+    ) -> Result<T, SiblingsError> {
+        self.breaker.allow(self.clock.now())?;
+
+        let result = tokio::time::timeout(self.read_timeout, read)
+            .await
+            .unwrap_or(Err(SiblingsError::Timeout(self.read_timeout)));
+        if self.breaker.observe(&result, self.clock.now()) {
+            log_to!(
+                Health,
+                Warn,
+                "breaker: redis unreachable {} times in a row, failing reads fast while probing",
+                self.breaker.threshold
+            );
+            let slf = self.clone();
+            tokio::spawn(async move { slf.probe_until_up().await });
+        }
+
+        result
+    }
+
+    async fn probe_until_up(&self) {
+        let mut backoff = MIN_PROBE_BACKOFF;
+        loop {
+            tokio::time::sleep(backoff).await;
+
+            match self.probe().await {
+                Ok(()) => {
+                    self.breaker.close();
+                    log_to!(Health, Info, "breaker: redis is back, closing the circuit");
+                    return;
+                }
+                Err(e) => {
+                    backoff = (backoff * 2).min(MAX_PROBE_BACKOFF);
+                    log_to!(
+                        Health,
+                        Warn,
+                        "breaker: probe failed, next in {backoff:?}: {e}"
+                    );
+                }
+            }
+        }
+    }
+
+    /// PING, or a read through the sidecar agent
+    async fn probe(&self) -> Result<(), SiblingsError> {
+        match self.backend.conn() {
+            Some(conn) => cache::ping(conn).await.map_err(SiblingsError::unreachable),
+            None => self
+                .get_cache(&self.keys.endpoint("__probe__"))
+                .await
+                .map(drop),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unreachable() -> Result<(), SiblingsError> {
+        Err(SiblingsError::unreachable(anyhow::anyhow!("refused")))
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let now = Instant::now();
+        let breaker = Breaker::new(3);
+        assert!(!breaker.observe(&unreachable(), now));
+        assert!(!breaker.observe(&Ok(()), now));
+        assert!(!breaker.observe(&unreachable(), now));
+        assert!(!breaker.observe(&unreachable(), now));
+        assert!(breaker.allow(now).is_ok());
+
+        assert!(breaker.observe(&unreachable(), now));
+        assert!(matches!(
+            breaker.allow(now),
+            Err(SiblingsError::CircuitOpen(_))
+        ));
+
+        breaker.close();
+        assert!(breaker.allow(now).is_ok());
+        assert!(!Breaker::new(0).observe(&unreachable(), now));
+    }
+
+    #[tokio::test]
+    async fn slow_reads_time_out() {
+        let sib = Siblings::sidecar("/nonexistent.sock", None)
+            .with_read_timeout(Duration::from_millis(10));
+        let read = sib
+            .guarded(std::future::pending::<Result<(), SiblingsError>>())
+            .await;
+
+        assert!(matches!(read, Err(SiblingsError::Timeout(_))));
+        assert_eq!(sib.breaker.state().failures, 1);
+    }
+}
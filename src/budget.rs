@@ -0,0 +1,139 @@
+//! Process-wide budget on discovery Redis traffic.
+//!
+//! A token bucket refilled at `ops_per_sec`; once it runs dry every read fails fast for a backoff
+//! window that doubles on each consecutive exhaustion and halves again as traffic calms down, so a
+//! cache-miss loop in one service can't take the shared Redis down with it.
+
+use std::{
+    env,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::SiblingsError;
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// The budget in place, or the value of `X_SIBLINGS_REDIS_OPS` that isn't a number of ops
+static BUDGET: OnceLock<Result<Option<RedisBudget>, String>> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct RedisBudget {
+    ops_per_sec: f64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    tokens: f64,
+    refilled_at: Instant,
+    backoff: Duration,
+    blocked_until: Option<Instant>,
+}
+
+impl RedisBudget {
+    pub fn new(ops_per_sec: u32) -> Self {
+        let ops_per_sec = ops_per_sec.max(1) as f64;
+        Self {
+            ops_per_sec,
+            state: Mutex::new(State {
+                tokens: ops_per_sec,
+                refilled_at: Instant::now(),
+                backoff: MIN_BACKOFF,
+                blocked_until: None,
+            }),
+        }
+    }
+
+    /// Takes one op from the budget, or returns how long until the next attempt is allowed
+    fn try_acquire(&self) -> Result<(), Duration> {
+        let mut s = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+
+        if let Some(until) = s.blocked_until {
+            if now < until {
+                return Err(until - now);
+            }
+            s.blocked_until = None;
+        }
+
+        let elapsed = now.duration_since(s.refilled_at).as_secs_f64();
+        s.tokens = (s.tokens + elapsed * self.ops_per_sec).min(self.ops_per_sec);
+        s.refilled_at = now;
+
+        if s.tokens >= 1.0 {
+            s.tokens -= 1.0;
+            s.backoff = (s.backoff / 2).max(MIN_BACKOFF);
+            return Ok(());
+        }
+
+        let backoff = s.backoff;
+        s.blocked_until = Some(now + backoff);
+        s.backoff = (backoff * 2).min(MAX_BACKOFF);
+
+        Err(backoff)
+    }
+}
+
+/// Installs the process-wide budget. Only the first call (or `X_SIBLINGS_REDIS_OPS`, read on the
+/// first Redis read) takes effect; returns `false` if a budget was already in place.
+pub fn set_redis_budget(ops_per_sec: u32) -> bool {
+    BUDGET.set(Ok(Some(RedisBudget::new(ops_per_sec)))).is_ok()
+}
+
+/// Gate in front of every discovery read from Redis: [`SiblingsError::Throttled`] with the time
+/// left in the backoff, or [`SiblingsError::InvalidKnob`] while `X_SIBLINGS_REDIS_OPS` is invalid
+pub(crate) fn acquire(key: &str) -> Result<(), SiblingsError> {
+    let budget = BUDGET.get_or_init(|| match env::var("X_SIBLINGS_REDIS_OPS") {
+        Ok(v) => v
+            .trim()
+            .parse()
+            .map(|ops| Some(RedisBudget::new(ops)))
+            .map_err(|_| v),
+        Err(_) => Ok(None),
+    });
+
+    match budget {
+        Ok(Some(budget)) => {
+            if let Err(retry_after) = budget.try_acquire() {
+                log_to!(
+                    Health,
+                    Warn,
+                    "budget: redis ops budget exceeded, skipping read of {key} for {retry_after:?}"
+                );
+                return Err(SiblingsError::Throttled(retry_after));
+            }
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(value) => Err(SiblingsError::InvalidKnob {
+            knob: "X_SIBLINGS_REDIS_OPS".to_owned(),
+            value: value.clone(),
+            expected: "a whole number of ops per second, e.g. 200",
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_when_exhausted() {
+        let budget = RedisBudget::new(2);
+
+        assert!(budget.try_acquire().is_ok());
+        assert!(budget.try_acquire().is_ok());
+
+        let first = budget.try_acquire().unwrap_err();
+        assert!(first <= MIN_BACKOFF);
+        // still inside the backoff window
+        assert!(budget.try_acquire().is_err());
+
+        budget.state.lock().unwrap().blocked_until = None;
+        budget.state.lock().unwrap().tokens = 0.0;
+        let second = budget.try_acquire().unwrap_err();
+        assert!(second > first);
+    }
+}
@@ -0,0 +1,310 @@
+//! Loading many records in a few round trips.
+//!
+//! Deployments with hundreds of dynamic siblings shouldn't resolve them with a GET each:
+//! [`Siblings::hydrate`] loads a list with MGET (HMGET in the hash layout), [`Siblings::hydrate_all`] every published
+//! record, and with [`Siblings::with_lazy_hydration`] the first lookup that misses memory loads
+//! them all at once. [`Siblings::eager`] loads them all before the instance is handed out, and
+//! [`Siblings::resolve_many`] resolves the few urls a request fans out to with one MGET.
+//! Warm up, the refresher and generations read their lists the same way, in one pipelined round
+//! trip.
+
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::OnceCell;
+
+use crate::{budget, cache, update, Layout, RegionEndpoint, Siblings, SiblingsError};
+
+/// Keys per MGET
+const CHUNK: usize = 500;
+
+impl Siblings {
+    /// On the first lookup missing memory, loads every published record with a few MGETs
+    /// instead of fetching siblings one by one
+    pub fn with_lazy_hydration(mut self) -> Self {
+        self.hydration = Some(Arc::new(OnceCell::new()));
+        self
+    }
+
+    /// Loads those of `siblings` not in memory yet; returns how many were found
+    pub async fn hydrate(&self, siblings: &[&str]) -> Result<usize, SiblingsError> {
+        let missing = {
+            let endpoints = self.endpoints.load();
+            siblings
+                .iter()
+                .filter(|s| endpoints.held(s).is_none())
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let fetched = self.fetch_many(&missing).await?;
+        let found = fetched.len();
+        self.keep(&fetched);
+        log_to!(
+            Refresh,
+            Info,
+            "hydrate: loaded {found} of {} siblings missing from memory",
+            missing.len()
+        );
+
+        Ok(found)
+    }
+
+    /// Urls of several siblings in one call, by sibling: the records missing from memory or
+    /// expired are read with one MGET, then each is looked up like [`Self::try_sibling`].
+    /// Fails when any lookup does. Urls are keyed by sibling alone: a sibling listed in several
+    /// regions maps to the url of its last lookup, so resolve those one region per call.
+    pub async fn resolve_many(
+        &self,
+        lookups: &[(&str, Option<&str>)],
+    ) -> Result<HashMap<String, Option<String>>, SiblingsError> {
+        let misses = {
+            let endpoints = self.endpoints.load();
+            let mut misses = lookups
+                .iter()
+                .filter_map(|&(sibling, region)| self.before_lookup(sibling, region).ok())
+                .map(|lookup| lookup.sibling.into_owned())
+                .filter(|sibling| {
+                    endpoints
+                        .get(sibling)
+                        .is_none_or(|_| self.expired(&endpoints, sibling))
+                        && !endpoints.missed_within(sibling, self.negative_ttl)
+                })
+                .collect::<Vec<_>>();
+            misses.sort();
+            misses.dedup();
+            misses
+        };
+
+        if !misses.is_empty() {
+            match self.fetch_many(&misses).await {
+                Ok(fetched) => self.keep(&fetched),
+                Err(e) => log_to!(
+                    Resolve,
+                    Debug,
+                    "resolve_many: bulk read failed, looking up one by one: {e}"
+                ),
+            }
+        }
+
+        let mut urls = HashMap::with_capacity(lookups.len());
+        for &(sibling, region) in lookups {
+            urls.insert(sibling.to_owned(), self.try_sibling(sibling, region).await?);
+        }
+
+        Ok(urls)
+    }
+
+    /// Puts `fetched` in memory and tells watchers
+    fn keep(&self, fetched: &HashMap<String, RegionEndpoint>) {
+        update(&self.endpoints, |endpoints| {
+            for (sibling, ep) in fetched {
+                endpoints.insert(sibling, ep.clone());
+            }
+        });
+        for (sibling, ep) in fetched {
+            self.record_read(sibling, Some(ep));
+        }
+    }
+
+    /// [`Self::hydrate`] with every sibling published in the current env
+    pub async fn hydrate_all(&self) -> Result<usize, SiblingsError> {
+        let published = self.published().await?;
+        self.hydrate(&published.iter().map(String::as_str).collect::<Vec<_>>())
+            .await
+    }
+
+    /// Loads every published record before the instance serves anything, with
+    /// [`Self::hydrate_all`], and fails with [`SiblingsError::Missing`] when any of `required`
+    /// can't be resolved. For services that prefer paying at startup to paying on first request:
+    /// `Siblings::new(db, me).await.eager(&["k9", "matrix"]).await?`. Services that would rather
+    /// start and wait for late records use [`Self::retry_missing`].
+    pub async fn eager(self, required: &[&str]) -> Result<Self, SiblingsError> {
+        let loaded = self.hydrate_all().await?;
+        log_to!(Health, Info, "eager: loaded {loaded} siblings");
+
+        let mut missing = Vec::new();
+        for &sibling in required {
+            // hydrated records are in memory; a pin or the prod fallback may still resolve the rest
+            if self.endpoint(sibling).await?.is_none() {
+                missing.push(sibling.to_owned());
+            }
+        }
+        if !missing.is_empty() {
+            return Err(SiblingsError::Missing(missing));
+        }
+
+        Ok(self)
+    }
+
+    /// Runs [`Self::hydrate_all`] once when lazy hydration is on; `true` if it has run.
+    /// A failed hydration is logged and lookups go on fetching one by one.
+    pub(crate) async fn hydrate_lazily(&self) -> bool {
+        let Some(hydration) = &self.hydration else {
+            return false;
+        };
+
+        hydration
+            .get_or_init(|| async {
+                if let Err(e) = self.hydrate_all().await {
+                    log_to!(Refresh, Warn, "hydrate: lazy hydration failed: {e}");
+                }
+            })
+            .await;
+
+        true
+    }
+
+    /// Records of `siblings` as [`Self::fetch`] would return them. Siblings with nothing
+    /// published are left out.
+    pub(crate) async fn fetch_many(
+        &self,
+        siblings: &[String],
+    ) -> Result<HashMap<String, RegionEndpoint>, SiblingsError> {
+        let pins = self.pins.read().await.clone();
+        let found = self.guarded(self.read_many(siblings, &pins)).await?;
+
+        let mut fetched = HashMap::with_capacity(found.len());
+        for (sibling, data) in found {
+            match self.parse(&sibling, data) {
+                Ok(ep) => {
+                    fetched.insert(sibling, ep);
+                }
+                Err(e) => log_to!(Refresh, Warn, "hydrate: sibling[{sibling}] skipped: {e}"),
+            }
+        }
+
+        Ok(fetched)
+    }
+
+    /// [`Self::fetch`] of each of `siblings`, read together in one round trip. Siblings absent
+    /// from the env but maybe published in a fallback (sandbox base, prod) and every sibling
+    /// of a backend without Redis are fetched one by one.
+    pub(crate) async fn fetch_each(
+        &self,
+        siblings: &[String],
+    ) -> Result<HashMap<String, Result<Option<RegionEndpoint>, SiblingsError>>, SiblingsError> {
+        let pins = self.pins.read().await.clone();
+        let mut found = match self.guarded(self.read_many(siblings, &pins)).await {
+            Ok(found) => Some(found),
+            Err(SiblingsError::Unsupported { .. }) => None,
+            Err(e) => return Err(e),
+        };
+        let falls_back = self.base_env.is_some() || (self.prod_fallback && !self.env.is_prod());
+
+        let mut fetched = HashMap::with_capacity(siblings.len());
+        for sibling in siblings {
+            let ep = match found.as_mut().map(|found| found.remove(sibling)) {
+                Some(Some(data)) => self.parse(sibling, data).map(Some),
+                Some(None) if !falls_back => Ok(None),
+                _ => self.fetch(sibling).await,
+            };
+            fetched.insert(sibling.clone(), ep);
+        }
+
+        Ok(fetched)
+    }
+
+    /// Raw records of `siblings`, the archived version for those in `pins`, looking in both
+    /// layouts with one pipelined round trip each, [`CHUNK`] keys per MGET or HMGET
+    pub(crate) async fn read_many(
+        &self,
+        siblings: &[String],
+        pins: &HashMap<String, u64>,
+    ) -> Result<HashMap<String, Vec<u8>>, SiblingsError> {
+        let conn = self.backend.redis("bulk reads")?;
+
+        let mut found = HashMap::with_capacity(siblings.len());
+        for layout in self.keys.read_order() {
+            let wanted = siblings
+                .iter()
+                // pinned versions are only archived as keys
+                .filter(|s| {
+                    !found.contains_key(*s) && (layout == Layout::Keys || !pins.contains_key(*s))
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if wanted.is_empty() {
+                continue;
+            }
+
+            let values = match layout {
+                Layout::Keys => {
+                    let keys = wanted
+                        .iter()
+                        .map(|sibling| {
+                            self.cache_key(&match pins.get(sibling) {
+                                Some(version) => self.keys.archive(sibling, *version),
+                                None => self.keys.endpoint(sibling),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    for chunk in keys.chunks(CHUNK) {
+                        budget::acquire(&chunk[0])?;
+                    }
+                    cache::mget(conn, &keys, CHUNK).await
+                }
+                Layout::Hash => {
+                    let hash = self.keys.hash(&self.env);
+                    for _ in wanted.chunks(CHUNK) {
+                        budget::acquire(&hash)?;
+                    }
+                    cache::hmget(conn, &hash, &wanted, CHUNK).await
+                }
+            }
+            .map_err(SiblingsError::unreachable)?;
+
+            for (sibling, data) in wanted.into_iter().zip(values) {
+                if let Some(data) = data.filter(|d| !d.is_empty()) {
+                    found.insert(sibling, data);
+                }
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolve_many_from_memory() -> Result<(), SiblingsError> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let ep =
+            Siblings::deserialize(br#"{"default":"https://k9","in":"https://k9.in"}"#.to_vec())?;
+        update(&sib.endpoints, |e| {
+            e.insert("k9", ep.clone());
+            e.missed("credit");
+        });
+
+        let urls = sib
+            .resolve_many(&[("k9", Some("IN")), ("credit", None)])
+            .await?;
+        assert_eq!(urls["k9"].as_deref(), Some("https://k9.in"));
+        assert_eq!(urls["credit"], None);
+        assert!(sib.resolve_many(&[("matrix", None)]).await.is_err());
+
+        let urls = sib.resolve_many(&[("k9", Some("IN")), ("k9", None)]).await?;
+        assert_eq!(urls.len(), 1);
+        assert_eq!(urls["k9"].as_deref(), Some("https://k9"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn batches_behind_the_agent_read_one_by_one() {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let siblings = ["k9".to_string(), "credit".to_string()];
+
+        let fetched = sib
+            .fetch_each(&siblings)
+            .await
+            .expect("falls back, not fails");
+        assert_eq!(fetched.len(), 2);
+        assert!(fetched
+            .values()
+            .all(|ep| matches!(ep, Err(SiblingsError::RedisUnreachable(_)))));
+    }
+}
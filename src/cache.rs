@@ -0,0 +1,312 @@
+//! Redis operations the `db` crate doesn't expose, run on a connection from the shared pool, on
+//! a Redis reached directly by url or on a Redis Cluster.
+//!
+//! On a cluster, commands go to the node owning their key and follow `MOVED`/`ASK` redirects. MGET
+//! chunks are sent one by one rather than pipelined, so each is split by slot, and SCAN walks
+//! every primary, see [`scan_targets`]. The publish script reads and writes three keys at once,
+//! which a cluster only allows within one slot: keep an env's keys together with a hash tag in
+//! the namespace, e.g. `KeyScheme::default().with_namespace("{siblings}")`.
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Result;
+#[cfg(feature = "db")]
+use db::Db;
+#[cfg(feature = "cluster")]
+use redis::cluster_async::ClusterConnection;
+use redis::{aio::MultiplexedConnection, FromRedisValue};
+
+/// Where a cache operation runs
+#[derive(Clone, Copy)]
+pub(crate) enum Conn<'a> {
+    #[cfg(feature = "db")]
+    Pool(&'a std::sync::Arc<db::RedisPool>),
+    Direct(&'a MultiplexedConnection),
+    #[cfg(feature = "cluster")]
+    Cluster(&'a ClusterConnection),
+    /// The primary owning a slot, for commands without a key like SCAN
+    #[cfg(feature = "cluster")]
+    ClusterNode(&'a ClusterConnection, u16),
+}
+
+impl Conn<'_> {
+    async fn query<T: FromRedisValue>(self, cmd: &redis::Cmd) -> Result<T> {
+        let value = match self {
+            #[cfg(feature = "db")]
+            Self::Pool(pool) => cmd.query_async(&mut pool.get().await?).await?,
+            Self::Direct(conn) => cmd.query_async(&mut conn.clone()).await?,
+            #[cfg(feature = "cluster")]
+            Self::Cluster(conn) => cmd.query_async(&mut conn.clone()).await?,
+            #[cfg(feature = "cluster")]
+            Self::ClusterNode(conn, slot) => {
+                use redis::cluster_routing::{Route, RoutingInfo, SingleNodeRoutingInfo, SlotAddr};
+
+                let route = Route::new(slot, SlotAddr::Master);
+                let routing = RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(route));
+                let value = conn.clone().route_command(cmd, routing).await?;
+                T::from_redis_value(&value)?
+            }
+        };
+
+        Ok(value)
+    }
+
+    async fn query_pipe<T: FromRedisValue>(self, pipe: &redis::Pipeline) -> Result<T> {
+        let value = match self {
+            #[cfg(feature = "db")]
+            Self::Pool(pool) => pipe.query_async(&mut pool.get().await?).await?,
+            Self::Direct(conn) => pipe.query_async(&mut conn.clone()).await?,
+            #[cfg(feature = "cluster")]
+            Self::Cluster(conn) | Self::ClusterNode(conn, _) => {
+                pipe.query_async(&mut conn.clone()).await?
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+/// Runs a Lua script, loading it first if Redis doesn't have it cached
+pub(crate) async fn invoke<T: FromRedisValue>(
+    conn: Conn<'_>,
+    script: &redis::ScriptInvocation<'_>,
+) -> Result<T> {
+    let value = match conn {
+        #[cfg(feature = "db")]
+        Conn::Pool(pool) => script.invoke_async(&mut pool.get().await?).await?,
+        Conn::Direct(conn) => script.invoke_async(&mut conn.clone()).await?,
+        #[cfg(feature = "cluster")]
+        Conn::Cluster(conn) | Conn::ClusterNode(conn, _) => {
+            script.invoke_async(&mut conn.clone()).await?
+        }
+    };
+
+    Ok(value)
+}
+
+/// Same contract as `Db::get_cache_for_pool`: an empty value means the key is not set
+pub(crate) async fn get(conn: Conn<'_>, key: &str) -> Result<Vec<u8>> {
+    match conn {
+        #[cfg(feature = "db")]
+        Conn::Pool(pool) => Db::get_cache_for_pool(pool.clone(), key).await,
+        _ => Ok(conn
+            .query::<Option<Vec<u8>>>(redis::cmd("GET").arg(key))
+            .await?
+            .unwrap_or_default()),
+    }
+}
+
+pub(crate) async fn set(conn: Conn<'_>, key: &str, value: &[u8]) -> Result<()> {
+    conn.query(redis::cmd("SET").arg(key).arg(value)).await
+}
+
+/// [`set`] expiring after `ttl`
+pub(crate) async fn set_ex(conn: Conn<'_>, key: &str, value: &[u8], ttl: Duration) -> Result<()> {
+    let secs = ttl.as_secs().max(1);
+    conn.query(redis::cmd("SET").arg(key).arg(value).arg("EX").arg(secs))
+        .await
+}
+
+/// Values of `keys` in order, `None` where a key is not set. One MGET per `chunk` keys, all
+/// pipelined in a single round trip.
+pub(crate) async fn mget(
+    conn: Conn<'_>,
+    keys: &[String],
+    chunk: usize,
+) -> Result<Vec<Option<Vec<u8>>>> {
+    #[cfg(feature = "cluster")]
+    if let Conn::Cluster(_) = conn {
+        // a pipeline goes to one node, a lone MGET is split by slot
+        let mut values = Vec::with_capacity(keys.len());
+        for keys in keys.chunks(chunk) {
+            values.extend(
+                conn.query::<Vec<Option<Vec<u8>>>>(redis::cmd("MGET").arg(keys))
+                    .await?,
+            );
+        }
+        return Ok(values);
+    }
+
+    let mut pipe = redis::pipe();
+    for keys in keys.chunks(chunk) {
+        pipe.cmd("MGET").arg(keys);
+    }
+    pipelined(conn, &pipe).await
+}
+
+pub(crate) async fn hset(
+    conn: Conn<'_>,
+    key: &str,
+    field: &str,
+    value: impl redis::ToRedisArgs,
+) -> Result<()> {
+    conn.query(redis::cmd("HSET").arg(key).arg(field).arg(value))
+        .await
+}
+
+pub(crate) async fn publish(conn: Conn<'_>, channel: &str, message: &[u8]) -> Result<()> {
+    conn.query(redis::cmd("PUBLISH").arg(channel).arg(message))
+        .await
+}
+
+pub(crate) async fn hget(conn: Conn<'_>, key: &str, field: &str) -> Result<Option<Vec<u8>>> {
+    conn.query(redis::cmd("HGET").arg(key).arg(field)).await
+}
+
+/// [`mget`] for the fields of hash `key`
+pub(crate) async fn hmget(
+    conn: Conn<'_>,
+    key: &str,
+    fields: &[String],
+    chunk: usize,
+) -> Result<Vec<Option<Vec<u8>>>> {
+    let mut pipe = redis::pipe();
+    for fields in fields.chunks(chunk) {
+        pipe.cmd("HMGET").arg(key).arg(fields);
+    }
+    pipelined(conn, &pipe).await
+}
+
+/// Replies of a pipeline of multi-value reads, concatenated
+async fn pipelined(conn: Conn<'_>, pipe: &redis::Pipeline) -> Result<Vec<Option<Vec<u8>>>> {
+    if pipe.cmd_iter().next().is_none() {
+        return Ok(Vec::new());
+    }
+    let replies = conn.query_pipe::<Vec<Vec<Option<Vec<u8>>>>>(pipe).await?;
+
+    Ok(replies.into_iter().flatten().collect())
+}
+
+pub(crate) async fn hdel(conn: Conn<'_>, key: &str, field: &str) -> Result<()> {
+    conn.query(redis::cmd("HDEL").arg(key).arg(field)).await
+}
+
+pub(crate) async fn hgetall(conn: Conn<'_>, key: &str) -> Result<HashMap<String, String>> {
+    conn.query(redis::cmd("HGETALL").arg(key)).await
+}
+
+/// One page of keys matching `pattern` from `cursor`, about `count` keys, and the next cursor;
+/// `0` once the walk is done
+pub(crate) async fn scan(
+    conn: Conn<'_>,
+    pattern: &str,
+    cursor: u64,
+    count: usize,
+) -> Result<(u64, Vec<String>)> {
+    conn.query(
+        redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(count),
+    )
+    .await
+}
+
+/// Where a SCAN has to run to see every key: `conn` itself, or on a cluster each primary
+pub(crate) async fn scan_targets(conn: Conn<'_>) -> Result<Vec<Conn<'_>>> {
+    #[cfg(feature = "cluster")]
+    if let Conn::Cluster(cluster) = conn {
+        let ranges = conn
+            .query::<Vec<Vec<redis::Value>>>(redis::cmd("CLUSTER").arg("SLOTS"))
+            .await?;
+
+        return Ok(primary_slots(&ranges)?
+            .into_iter()
+            .map(|slot| Conn::ClusterNode(cluster, slot))
+            .collect());
+    }
+
+    Ok(vec![conn])
+}
+
+/// One slot owned by each primary in a CLUSTER SLOTS reply, whose ranges read
+/// `[start, end, [ip, port, id], replicas..]`
+#[cfg(feature = "cluster")]
+fn primary_slots(ranges: &[Vec<redis::Value>]) -> Result<Vec<u16>> {
+    let mut primaries = HashMap::new();
+    for range in ranges {
+        let (Some(start), Some(primary)) = (range.first(), range.get(2)) else {
+            continue;
+        };
+        let start = u16::from_redis_value(start)?;
+        let addr = match Vec::<redis::Value>::from_redis_value(primary)?.as_slice() {
+            [ip, port, ..] => (String::from_redis_value(ip)?, u16::from_redis_value(port)?),
+            _ => continue,
+        };
+        primaries.entry(addr).or_insert(start);
+    }
+
+    let mut slots = primaries.into_values().collect::<Vec<_>>();
+    slots.sort_unstable();
+    Ok(slots)
+}
+
+/// [`scan`] for the fields of hash `key`
+pub(crate) async fn hscan(
+    conn: Conn<'_>,
+    key: &str,
+    cursor: u64,
+    count: usize,
+) -> Result<(u64, Vec<String>)> {
+    let (next, pairs) = conn
+        .query::<(u64, Vec<Vec<u8>>)>(
+            redis::cmd("HSCAN")
+                .arg(key)
+                .arg(cursor)
+                .arg("COUNT")
+                .arg(count),
+        )
+        .await?;
+    // field, value, field, value..
+    let fields = pairs
+        .into_iter()
+        .step_by(2)
+        .map(|f| String::from_utf8_lossy(&f).into_owned())
+        .collect();
+
+    Ok((next, fields))
+}
+
+pub(crate) async fn del(conn: Conn<'_>, key: &str) -> Result<()> {
+    conn.query(redis::cmd("DEL").arg(key)).await
+}
+
+pub(crate) async fn ping(conn: Conn<'_>) -> Result<()> {
+    conn.query(&redis::cmd("PING")).await
+}
+
+/// Redis' clock as unix seconds and microseconds
+pub(crate) async fn time(conn: Conn<'_>) -> Result<(u64, u64)> {
+    conn.query(&redis::cmd("TIME")).await
+}
+
+#[cfg(all(test, feature = "cluster"))]
+mod tests {
+    use redis::Value;
+
+    use super::*;
+
+    fn range(start: i64, end: i64, ip: &str, port: i64) -> Vec<Value> {
+        let node = |ip: &str, port| Value::Bulk(vec![Value::Data(ip.into()), Value::Int(port)]);
+        vec![
+            Value::Int(start),
+            Value::Int(end),
+            node(ip, port),
+            node("10.0.0.9", 7000),
+        ]
+    }
+
+    #[test]
+    fn one_slot_per_primary() {
+        let ranges = [
+            range(0, 5460, "10.0.0.1", 6379),
+            range(5461, 10922, "10.0.0.2", 6379),
+            range(10923, 12000, "10.0.0.3", 6379),
+            range(12001, 16383, "10.0.0.1", 6379),
+        ];
+
+        assert_eq!(primary_slots(&ranges).unwrap(), [0, 5461, 10923]);
+    }
+}
@@ -0,0 +1,163 @@
+//! Where time comes from.
+//!
+//! Record ttls, the negative cache, the degraded window, the circuit breaker and the usage and
+//! version reports all read time through a [`Clock`]. [`SystemClock`] is the default;
+//! [`Siblings::with_clock`] swaps in another one, e.g. a [`MockClock`] that only moves when told
+//! to, so time-dependent behavior can be tested without sleeping.
+
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use arc_swap::ArcSwap;
+
+use crate::{Endpoints, Siblings};
+
+pub trait Clock: Send + Sync {
+    /// Monotonic time, for ttls and windows
+    fn now(&self) -> Instant;
+
+    /// Wall time, for timestamps shared with other processes
+    fn system_now(&self) -> SystemTime;
+}
+
+/// The clock of the OS
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock standing still until [`Self::advance`]d. Clones share the time, so keep one to move the
+/// clock of the instance it was handed to.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    system_start: SystemTime,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    /// Stopped at the current time
+    pub fn new() -> Self {
+        Self::at(SystemTime::now())
+    }
+
+    /// Stopped at wall time `system`
+    pub fn at(system: SystemTime) -> Self {
+        Self {
+            start: Instant::now(),
+            system_start: system,
+            elapsed: Arc::default(),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.system_start + self.elapsed()
+    }
+}
+
+/// The [`Clock`] an instance and its [`Endpoints`] read
+#[derive(Clone)]
+pub(crate) struct SharedClock(Arc<dyn Clock>);
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self(Arc::new(SystemClock))
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedClock")
+    }
+}
+
+impl Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl Siblings {
+    /// Reads time from `clock` instead of the OS. Set it before anything is loaded: records in
+    /// memory are dropped, overrides and the local file stay.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = SharedClock(Arc::new(clock));
+        let endpoints = self.endpoints.load().layers_only(self.clock.clone());
+        self.endpoints = Arc::new(ArcSwap::from_pointee(endpoints));
+        self.stale = Arc::new(ArcSwap::from_pointee(Endpoints::with_clock(
+            self.clock.clone(),
+        )));
+        self
+    }
+
+    /// Seconds since the unix epoch
+    pub(crate) fn unix_now(&self) -> u64 {
+        self.clock
+            .system_now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RegionEndpoint;
+
+    #[tokio::test]
+    async fn time_moves_only_when_advanced() -> anyhow::Result<()> {
+        let clock = MockClock::at(UNIX_EPOCH + Duration::from_secs(1_000));
+        let sib = Siblings::sidecar("/nonexistent.sock", None)
+            .with_clock(clock.clone())
+            .with_ttl(Duration::from_secs(60));
+        assert_eq!(sib.unix_now(), 1_000);
+
+        crate::update(&sib.endpoints, |endpoints| {
+            endpoints.insert("k9", RegionEndpoint::default());
+            endpoints.missed("credit");
+        });
+        assert!(!sib.expired(&sib.endpoints.load(), "k9"));
+        assert_eq!(sib.endpoint("credit").await?, None);
+
+        clock.advance(Duration::from_secs(60));
+        assert!(sib.expired(&sib.endpoints.load(), "k9"));
+        assert!(sib.endpoint("credit").await.is_err());
+        assert_eq!(sib.unix_now(), 1_060);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,524 @@
+//! Resolving regions through AWS Cloud Map, behind the `aws` feature.
+//!
+//! [`CloudMapSource`] is an [`EndpointSource`] answering lookups in the regions it's given from
+//! the healthy instances Cloud Map's `DiscoverInstances` returns for the service of the same name,
+//! on top of the records of a fallback source, usually Redis through
+//! [`RedisSource`](crate::RedisSource). `sibling("k9", Some("US"))` gets a Cloud Map instance;
+//! other regions, and the US when Cloud Map has no healthy instance or can't be reached, get the
+//! published record. Records are held in memory for the ttl like any other, so Cloud Map is asked
+//! once per sibling and ttl, not per lookup.
+//!
+//! Requests are signed with SigV4, using [`CloudMapSource::with_credentials`] if given, else
+//! `AWS_ACCESS_KEY_ID`/`AWS_SECRET_ACCESS_KEY`/`AWS_SESSION_TOKEN`, else the ECS task role from
+//! the container credentials endpoint.
+
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, header, Request};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{https, EndpointSource, Env, FetchFuture, RegionEndpoint, Regions};
+
+/// Base of `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` on ECS
+const ECS_CREDENTIALS_HOST: &str = "http://169.254.170.2";
+
+/// How long before they expire role credentials are renewed
+const CREDENTIALS_MARGIN: Duration = Duration::from_secs(300);
+
+/// Access keys signing Cloud Map requests
+#[derive(Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl std::fmt::Debug for AwsCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AwsCredentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Cloud Map namespaces answering for regions, over a fallback source
+#[derive(Clone)]
+pub struct CloudMapSource {
+    /// region code -> (AWS region, namespace)
+    regions: Vec<(String, String, String)>,
+    fallback: Option<Arc<dyn EndpointSource>>,
+    credentials: Option<AwsCredentials>,
+    /// Role credentials and the unix second they expire at
+    fetched: Arc<Mutex<Option<(AwsCredentials, u64)>>>,
+    scheme: String,
+    endpoint: Option<String>,
+    client: https::HttpsClient<Full<Bytes>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DiscoverInstances<'a> {
+    namespace_name: &'a str,
+    service_name: &'a str,
+    health_status: &'a str,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Discovered {
+    #[serde(default)]
+    instances: Vec<Instance>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Instance {
+    #[serde(default)]
+    attributes: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RoleCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: String,
+    expiration: String,
+}
+
+impl Instance {
+    /// `{address}:{port}` from the attributes Cloud Map registers, `None` without an address
+    fn address(&self) -> Option<String> {
+        let port = self
+            .attributes
+            .get("AWS_INSTANCE_PORT")
+            .map_or("80", String::as_str);
+        let attr = |name: &str| self.attributes.get(name).filter(|v| !v.is_empty());
+        match (
+            attr("AWS_INSTANCE_IPV4"),
+            attr("AWS_INSTANCE_IPV6"),
+            attr("AWS_INSTANCE_CNAME"),
+        ) {
+            (Some(ip), _, _) | (None, None, Some(ip)) => Some(format!("{ip}:{port}")),
+            (None, Some(ip), _) => Some(format!("[{ip}]:{port}")),
+            (None, None, None) => None,
+        }
+    }
+}
+
+impl CloudMapSource {
+    /// Answers lookups in `region` from the services of `namespace` in the AWS region `aws_region`
+    pub fn new(region: Regions, aws_region: &str, namespace: &str) -> Result<Self> {
+        let source = Self {
+            regions: Vec::new(),
+            fallback: None,
+            credentials: None,
+            fetched: Default::default(),
+            scheme: "http".to_string(),
+            endpoint: None,
+            client: https::client()?,
+        };
+
+        Ok(source.with_region(region, aws_region, namespace))
+    }
+
+    /// Also answers lookups in `region` from `namespace` in `aws_region`
+    pub fn with_region(mut self, region: Regions, aws_region: &str, namespace: &str) -> Self {
+        self.regions.retain(|(code, _, _)| code != region.code());
+        self.regions.push((
+            region.code().to_owned(),
+            aws_region.to_owned(),
+            namespace.to_owned(),
+        ));
+        self
+    }
+
+    /// Records everything not found in Cloud Map is answered from, e.g. Redis
+    pub fn with_fallback(mut self, fallback: impl EndpointSource + 'static) -> Self {
+        self.fallback = Some(Arc::new(fallback));
+        self
+    }
+
+    /// Signs with these keys instead of the environment's
+    pub fn with_credentials(mut self, credentials: AwsCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Scheme of the urls built from instances, `http` unless set
+    pub fn with_scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.scheme = scheme.into();
+        self
+    }
+
+    /// Sends requests to `endpoint` instead of `data-servicediscovery.{region}.amazonaws.com`,
+    /// for VPC endpoints or a test double
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    async fn record(
+        &self,
+        env: &Env,
+        sibling: &str,
+        pinned: Option<u64>,
+    ) -> Result<Option<RegionEndpoint>> {
+        let published = match &self.fallback {
+            Some(fallback) => fallback.fetch(env, sibling, pinned).await?,
+            None => None,
+        };
+        // Cloud Map only knows the instances up now
+        if pinned.is_some() {
+            return Ok(published);
+        }
+
+        let mut record = published;
+        for (region, aws_region, namespace) in &self.regions {
+            let url = match self.discover(aws_region, namespace, sibling).await {
+                Ok(Some(url)) => url,
+                Ok(None) => continue,
+                Err(e) => {
+                    log_to!(
+                        Resolve,
+                        Warn,
+                        "cloudmap: sibling[{sibling}] in {region} from the published record: {e:#}"
+                    );
+                    continue;
+                }
+            };
+            let record = record.get_or_insert_with(|| RegionEndpoint {
+                default: url.clone().into(),
+                ..Default::default()
+            });
+            record.regions.insert(region.clone(), url.into());
+        }
+
+        Ok(record)
+    }
+
+    /// Url of the first healthy instance of `service`, by address
+    async fn discover(
+        &self,
+        aws_region: &str,
+        namespace: &str,
+        service: &str,
+    ) -> Result<Option<String>> {
+        let body = serde_json::to_vec(&DiscoverInstances {
+            namespace_name: namespace,
+            service_name: service,
+            health_status: "HEALTHY",
+        })?;
+        let host = format!("data-servicediscovery.{aws_region}.amazonaws.com");
+        let url = match &self.endpoint {
+            Some(endpoint) => format!("{endpoint}/"),
+            None => format!("https://{host}/"),
+        };
+        let credentials = self.credentials().await?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        let mut headers = vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("host", host),
+            (
+                "x-amz-target",
+                "Route53AutoNaming_v20170314.DiscoverInstances".to_string(),
+            ),
+            ("x-amz-date", amz_date(now).1),
+        ];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let authorization = sign(
+            &credentials,
+            aws_region,
+            "servicediscovery",
+            now,
+            &headers,
+            &body,
+        );
+
+        let mut req = Request::post(url).header(header::AUTHORIZATION, authorization);
+        for (name, value) in &headers {
+            req = req.header(*name, value);
+        }
+        let resp = self
+            .client
+            .request(req.body(Full::new(Bytes::from(body)))?)
+            .await?;
+        let status = resp.status();
+        let body = resp.into_body().collect().await?.to_bytes();
+        if !status.is_success() {
+            bail!(
+                "cloud map answered {status}: {}",
+                String::from_utf8_lossy(&body)
+            );
+        }
+
+        let mut addresses = serde_json::from_slice::<Discovered>(&body)?
+            .instances
+            .iter()
+            .filter_map(Instance::address)
+            .collect::<Vec<_>>();
+        addresses.sort();
+        Ok(addresses
+            .first()
+            .map(|address| format!("{}://{address}", self.scheme)))
+    }
+
+    /// The keys given, else the environment's, else the ECS task role's while valid for
+    /// [`CREDENTIALS_MARGIN`]
+    async fn credentials(&self) -> Result<AwsCredentials> {
+        if let Some(credentials) = &self.credentials {
+            return Ok(credentials.clone());
+        }
+        if let (Ok(access_key_id), Ok(secret_access_key)) = (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            return Ok(AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let cached = self
+            .fetched
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if let Some((credentials, expires)) = cached
+            && now + CREDENTIALS_MARGIN.as_secs() < expires
+        {
+            return Ok(credentials);
+        }
+
+        let url = match (
+            env::var("AWS_CONTAINER_CREDENTIALS_FULL_URI"),
+            env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI"),
+        ) {
+            (Ok(full), _) => full,
+            (_, Ok(relative)) => format!("{ECS_CREDENTIALS_HOST}{relative}"),
+            _ => bail!("no AWS credentials: set AWS_ACCESS_KEY_ID or run with a task role"),
+        };
+        let mut req = Request::get(url);
+        if let Ok(token) = env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN") {
+            req = req.header(header::AUTHORIZATION, token);
+        }
+        let resp = self.client.request(req.body(Full::default())?).await?;
+        let role = serde_json::from_slice::<RoleCredentials>(
+            &resp.into_body().collect().await?.to_bytes(),
+        )?;
+        let expires = parse_amz_time(&role.expiration)
+            .with_context(|| format!("credentials expiring at {:?}", role.expiration))?;
+        let credentials = AwsCredentials {
+            access_key_id: role.access_key_id,
+            secret_access_key: role.secret_access_key,
+            session_token: Some(role.token),
+        };
+        *self.fetched.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((credentials.clone(), expires));
+
+        Ok(credentials)
+    }
+}
+
+impl EndpointSource for CloudMapSource {
+    fn fetch<'a>(&'a self, env: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        Box::pin(self.record(env, sibling, pinned))
+    }
+}
+
+/// The `Authorization` header signing a POST of `body` to `/` with `headers` (lowercase names)
+fn sign(
+    credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    now: u64,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> String {
+    let (date, time) = amz_date(now);
+    let mut headers = headers.to_vec();
+    headers.sort();
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect::<String>();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{canonical_headers}\n{signed_headers}\n{}",
+        hex(&Sha256::digest(body))
+    );
+
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{time}\n{scope}\n{}",
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(&credentials.secret_access_key, &date, region, service);
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={}",
+        credentials.access_key_id,
+        hex(&hmac(&key, &string_to_sign))
+    )
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{secret}").as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    hmac(&key, "aws4_request")
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    // HMAC takes keys of any length, new_from_slice never fails
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac key");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// `20150830` and `20150830T123600Z` of unix seconds
+fn amz_date(secs: u64) -> (String, String) {
+    let (days, rest) = (secs / 86400, secs % 86400);
+    // days to a civil date, from Howard Hinnant's date algorithms
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let date = format!("{year:04}{month:02}{day:02}");
+    let time = format!(
+        "{date}T{:02}{:02}{:02}Z",
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    );
+    (date, time)
+}
+
+/// Unix seconds of `2015-08-30T12:36:00Z`
+fn parse_amz_time(time: &str) -> Option<u64> {
+    let num = |range: std::ops::Range<usize>| time.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+
+    // a civil date to days, the inverse of amz_date's
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fake_http, Siblings};
+
+    #[test]
+    fn signs_like_aws() {
+        // the example of deriving a signing key in AWS's SigV4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+
+        assert_eq!(
+            amz_date(1_440_938_160),
+            ("20150830".to_string(), "20150830T123600Z".to_string())
+        );
+        assert_eq!(parse_amz_time("2015-08-30T12:36:00Z"), Some(1_440_938_160));
+        assert_eq!(parse_amz_time("2024-02-29T00:00:00Z"), Some(1_709_164_800));
+    }
+
+    #[tokio::test]
+    async fn regions_from_cloud_map_over_the_published_record() -> anyhow::Result<()> {
+        let endpoint = fake_http::serve(|request| {
+            let body = match request.contains(r#""ServiceName":"k9""#)
+                && request.contains("authorization: AWS4-HMAC-SHA256 Credential=AKID/")
+            {
+                true => {
+                    r#"{"Instances":[
+                    {"InstanceId":"b","Attributes":{"AWS_INSTANCE_IPV4":"10.2.0.9","AWS_INSTANCE_PORT":"9000"}},
+                    {"InstanceId":"a","Attributes":{"AWS_INSTANCE_IPV4":"10.2.0.5","AWS_INSTANCE_PORT":"9000"}}]}"#
+                }
+                false => r#"{"Instances":[]}"#,
+            };
+            fake_http::respond("200 OK", body)
+        })
+        .await?;
+
+        let published = HashMap::from([
+            (
+                "k9".to_string(),
+                Siblings::deserialize(
+                    br#"{"default":"https://k9","in":"https://k9.in"}"#.to_vec(),
+                )?,
+            ),
+            (
+                "matrix".to_string(),
+                Siblings::deserialize(br#"{"default":"https://matrix"}"#.to_vec())?,
+            ),
+        ]);
+        let cloudmap = CloudMapSource::new(Regions::US, "us-east-1", "platform.local")?
+            .with_endpoint(endpoint)
+            .with_fallback(published)
+            .with_credentials(AwsCredentials {
+                access_key_id: "AKID".to_string(),
+                secret_access_key: "secret".to_string(),
+                session_token: None,
+            });
+        let sib = Siblings::with_source(cloudmap, None).with_env(Env::Dev);
+
+        assert_eq!(
+            sib.try_sibling("k9", Some("US")).await?.as_deref(),
+            Some("http://10.2.0.5:9000")
+        );
+        assert_eq!(
+            sib.try_sibling("k9", Some("IN")).await?.as_deref(),
+            Some("https://k9.in")
+        );
+        assert_eq!(
+            sib.try_sibling("matrix", Some("US")).await?.as_deref(),
+            Some("https://matrix")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+//! The `Option` returning accessors, kept behind the default `compat` feature while the fleet
+//! moves to the `try_` ones. A service that builds without warnings under them can drop the
+//! feature (`default-features = false`) and they're gone; failed reads only show up in its logs
+//! until then.
+
+use crate::{Regions, Siblings, SiblingsError};
+
+impl Siblings {
+    #[deprecated(note = "use `try_sibling`, it tells a missing record from a failed read")]
+    pub async fn sibling(&self, sibling: &str, region: Option<&str>) -> Option<String> {
+        self.lookup(sibling, region, "siblings").await
+    }
+
+    #[deprecated(note = "use `try_me`, it tells a missing record from a failed read")]
+    pub async fn me(&self, region: Option<&str>) -> Option<String> {
+        let me = self.me.as_deref()?;
+        self.lookup(me, region, "me").await
+    }
+
+    #[deprecated(note = "use `try_sibling_in`, it tells a missing record from a failed read")]
+    pub async fn sibling_in(&self, sibling: &str, region: Regions) -> Option<String> {
+        self.lookup(sibling, Some(region.code()), "sibling_in")
+            .await
+    }
+
+    #[deprecated(note = "use `try_me`, it tells a missing record from a failed read")]
+    pub async fn me_in(&self, region: Regions) -> Option<String> {
+        let me = self.me.as_deref()?;
+        self.lookup(me, Some(region.code()), "me_in").await
+    }
+
+    /// What every `Option` returning accessor resolves through: the record in memory, else
+    /// fetched and kept in memory, then the url for `region`, through the interceptors
+    pub(crate) async fn lookup(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+        caller: &str,
+    ) -> Option<String> {
+        let vetoed = |e: SiblingsError| {
+            log_to!(
+                Resolve,
+                Warn,
+                "{caller}: sibling[{sibling}] stopped by an interceptor: {e}"
+            );
+        };
+
+        let lookup = self.before_lookup(sibling, region).map_err(vetoed).ok()?;
+        let record = self.record(&lookup.sibling, caller).await?;
+        let url = Some(self.nearest(&record, lookup.region.as_deref()).to_string());
+        self.after_lookup(&lookup, url).map_err(vetoed).ok()?
+    }
+}
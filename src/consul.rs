@@ -0,0 +1,213 @@
+//! Resolving siblings from Consul, behind the `consul` feature.
+//!
+//! [`ConsulSource`] is an [`EndpointSource`] answering each sibling from the healthy instances
+//! Consul's health API lists for the service of the same name
+//! (`GET /v1/health/service/{sibling}?passing=true`). The local datacenter gives the record's
+//! `default`; every region mapped with [`ConsulSource::with_datacenter`] is read from its own
+//! datacenter, so `try_sibling("k9", Some("US"))` resolves the same way as with Redis records.
+//! Of several healthy instances the first by address wins; pinned versions don't exist in
+//! Consul and find nothing.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use http_body_util::{BodyExt, Empty};
+use hyper::{body::Bytes, header, Request, Uri};
+use hyper_util::rt::TokioIo;
+use serde_derive::Deserialize;
+
+use crate::{EndpointSource, Env, FetchFuture, RegionEndpoint, Regions};
+
+/// Reads services from the Consul agent at `addr`
+#[derive(Debug, Clone)]
+pub struct ConsulSource {
+    addr: Uri,
+    token: Option<String>,
+    scheme: String,
+    /// region code -> datacenter
+    datacenters: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    #[serde(rename = "Node")]
+    node: Node,
+    #[serde(rename = "Service")]
+    service: Service,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    #[serde(rename = "Address")]
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Service {
+    #[serde(rename = "Address", default)]
+    address: String,
+    #[serde(rename = "Port")]
+    port: u16,
+}
+
+impl ConsulSource {
+    /// `addr` is the agent's HTTP API, e.g. `http://127.0.0.1:8500`
+    pub fn new(addr: &str) -> Result<Self> {
+        let addr = addr.parse::<Uri>()?;
+        if addr.scheme_str() != Some("http") || addr.host().is_none() {
+            bail!("consul needs an http:// address, got {addr}");
+        }
+
+        Ok(Self {
+            addr,
+            token: None,
+            scheme: "http".to_string(),
+            datacenters: BTreeMap::new(),
+        })
+    }
+
+    /// Sent as `X-Consul-Token` with every request
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Scheme of the urls built from instances, `http` unless set
+    pub fn with_scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.scheme = scheme.into();
+        self
+    }
+
+    /// Resolves lookups in `region` from the instances in `datacenter`
+    pub fn with_datacenter(mut self, region: Regions, datacenter: impl Into<String>) -> Self {
+        self.datacenters
+            .insert(region.code().to_owned(), datacenter.into());
+        self
+    }
+
+    async fn record(&self, sibling: &str) -> Result<Option<RegionEndpoint>> {
+        let Some(default) = self.instance(sibling, None).await? else {
+            return Ok(None);
+        };
+
+        let mut record = RegionEndpoint {
+            default: default.into(),
+            ..Default::default()
+        };
+        for (region, datacenter) in &self.datacenters {
+            if let Some(url) = self.instance(sibling, Some(datacenter)).await? {
+                record.regions.insert(region.clone(), url.into());
+            }
+        }
+
+        Ok(Some(record))
+    }
+
+    /// Url of the first healthy instance of `service` in `datacenter`, the agent's own if `None`
+    async fn instance(&self, service: &str, datacenter: Option<&str>) -> Result<Option<String>> {
+        let mut path = format!("/v1/health/service/{service}?passing=true");
+        if let Some(dc) = datacenter {
+            path.push_str(&format!("&dc={dc}"));
+        }
+        let mut entries = serde_json::from_slice::<Vec<Entry>>(&self.get(&path).await?)?;
+        if entries.is_empty() {
+            log_to!(
+                Resolve,
+                Debug,
+                "consul: sibling[{service}] has no healthy instance in {datacenter:?}"
+            );
+        }
+
+        entries.sort_by(|a, b| (address(a), a.service.port).cmp(&(address(b), b.service.port)));
+        Ok(entries
+            .first()
+            .map(|e| format!("{}://{}:{}", self.scheme, address(e), e.service.port)))
+    }
+
+    async fn get(&self, path: &str) -> Result<Vec<u8>> {
+        let host = self.addr.host().unwrap_or_default();
+        let port = self.addr.port_u16().unwrap_or(8500);
+        let stream = tokio::net::TcpStream::connect((host, port)).await?;
+        let (mut sender, conn) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(conn);
+
+        let mut req = Request::get(path).header(header::HOST, format!("{host}:{port}"));
+        if let Some(token) = &self.token {
+            req = req.header("X-Consul-Token", token);
+        }
+        let resp = sender
+            .send_request(req.body(Empty::<Bytes>::new())?)
+            .await?;
+        if !resp.status().is_success() {
+            bail!("consul answered {} for {path}", resp.status());
+        }
+
+        Ok(resp.into_body().collect().await?.to_bytes().to_vec())
+    }
+}
+
+/// The service's own address, else its node's
+fn address(entry: &Entry) -> &str {
+    match entry.service.address.as_str() {
+        "" => &entry.node.address,
+        address => address,
+    }
+}
+
+impl EndpointSource for ConsulSource {
+    fn fetch<'a>(&'a self, _: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        match pinned {
+            Some(_) => Box::pin(async { Ok(None) }),
+            None => Box::pin(self.record(sibling)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fake_http, Siblings};
+
+    /// A Consul agent answering every health query from `answers`, by datacenter
+    async fn fake_consul(answers: &'static [(&'static str, &'static str)]) -> Result<String> {
+        fake_http::serve(|request| {
+            let body = answers
+                .iter()
+                .find(|(query, _)| request.lines().next().is_some_and(|l| l.contains(query)))
+                .map_or("[]", |(_, body)| body);
+            fake_http::respond("200 OK", body)
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn resolves_from_healthy_instances() -> anyhow::Result<()> {
+        let addr = fake_consul(&[
+            (
+                "/v1/health/service/k9?passing=true&dc=us-east",
+                r#"[{"Node":{"Address":"10.1.0.9"},"Service":{"Address":"","Port":9000}}]"#,
+            ),
+            (
+                "/v1/health/service/k9?passing=true ",
+                r#"[{"Node":{"Address":"10.0.0.9"},"Service":{"Address":"10.0.0.7","Port":9000}},
+                   {"Node":{"Address":"10.0.0.2"},"Service":{"Address":"10.0.0.5","Port":9000}}]"#,
+            ),
+        ])
+        .await?;
+        let consul = ConsulSource::new(&addr)?.with_datacenter(Regions::US, "us-east");
+        let sib = Siblings::with_source(consul, None).with_env(Env::Dev);
+
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("http://10.0.0.5:9000")
+        );
+        assert_eq!(
+            sib.try_sibling("k9", Some("US")).await?.as_deref(),
+            Some("http://10.1.0.9:9000")
+        );
+        assert_eq!(sib.try_sibling("matrix", None).await?, None);
+
+        Ok(())
+    }
+}
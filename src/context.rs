@@ -0,0 +1,245 @@
+//! Per-request limits on resolution.
+//!
+//! A [`ResolveContext`] created for each user-facing request caps how many cold fetches (memory
+//! misses read from Redis) lookups made with it may trigger. Past the cap, lookups answer from the
+//! records memory held before the last [`Siblings::flush`], so a mass invalidation can't stack
+//! Redis round trips onto one request.
+//!
+//! A context can also carry a [`Priority`]. While Redis is degraded (a read failed in the last
+//! [`DEGRADED_FOR`]), best-effort lookups skip Redis and answer stale, and critical ones retry
+//! failed reads before falling back to stale.
+
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use crate::{RegionEndpoint, Siblings, SiblingsError};
+
+/// How long a failed Redis read marks Redis as degraded
+pub const DEGRADED_FOR: Duration = Duration::from_secs(5);
+/// Reads a critical lookup makes before giving up on Redis
+const CRITICAL_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Core flows: gets the retries while Redis is degraded
+    Critical,
+    /// Fails fast to stale data while Redis is degraded
+    BestEffort,
+}
+
+#[derive(Debug, Default)]
+pub struct ResolveContext {
+    max_cold_fetches: Option<u32>,
+    cold_fetches: AtomicU32,
+    /// `None` reads once, degraded or not
+    priority: Option<Priority>,
+}
+
+impl ResolveContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows at most `max` cold fetches; unlimited by default
+    pub fn with_max_cold_fetches(mut self, max: u32) -> Self {
+        self.max_cold_fetches = Some(max);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Cold fetches made so far
+    pub fn cold_fetches(&self) -> u32 {
+        self.cold_fetches.load(Ordering::Relaxed)
+    }
+
+    /// Takes one cold fetch from the budget; `false` once it's spent
+    fn take(&self) -> bool {
+        let Some(max) = self.max_cold_fetches else {
+            self.cold_fetches.fetch_add(1, Ordering::Relaxed);
+            return true;
+        };
+
+        self.cold_fetches
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .is_ok()
+    }
+}
+
+impl Siblings {
+    /// [`Self::endpoint`] within the cold fetch budget of `ctx`; once it's spent, the expired
+    /// record in memory or the one held before the last flush, if any
+    pub async fn endpoint_with(
+        &self,
+        ctx: &ResolveContext,
+        sibling: &str,
+    ) -> Result<Option<RegionEndpoint>, SiblingsError> {
+        {
+            let endpoints = self.endpoints.load();
+            if let Some(ep) = endpoints.held(sibling)
+                && !self.expired(&endpoints, sibling)
+            {
+                if !ep.is_enabled() {
+                    return Err(SiblingsError::Disabled(sibling.to_owned()));
+                }
+                self.usage.record(sibling);
+                return Ok(Some(ep.clone()));
+            }
+        }
+
+        if ctx.priority == Some(Priority::BestEffort) && self.degraded() {
+            return self.stale(sibling, "redis is degraded").await.transpose();
+        }
+        if !ctx.take() {
+            return self
+                .stale(sibling, "over the cold fetch budget")
+                .await
+                .transpose();
+        }
+
+        let attempts = match ctx.priority {
+            Some(Priority::Critical) => CRITICAL_ATTEMPTS,
+            _ => 1,
+        };
+        let mut attempt = 1;
+        loop {
+            let err = match self.endpoint(sibling).await {
+                Ok(ep) => return Ok(ep),
+                Err(
+                    e @ (SiblingsError::RedisUnreachable(_)
+                    | SiblingsError::Throttled(_)
+                    | SiblingsError::Timeout(_)
+                    | SiblingsError::CircuitOpen(_)),
+                ) => e,
+                Err(e) => return Err(e),
+            };
+            self.mark_degraded();
+
+            if attempt >= attempts {
+                log_to!(
+                    Resolve,
+                    Warn,
+                    "endpoint_with: sibling[{sibling}] failed {attempt} reads: {err}"
+                );
+                return self
+                    .stale(sibling, "redis read failed")
+                    .await
+                    .unwrap_or(Err(err))
+                    .map(Some);
+            }
+
+            let backoff = match err {
+                SiblingsError::Throttled(retry_after) => retry_after,
+                _ => RETRY_BACKOFF * attempt,
+            };
+            tokio::time::sleep(backoff.min(DEGRADED_FOR)).await;
+            attempt += 1;
+        }
+    }
+
+    /// The expired record memory holds for `sibling`, else the one held before the last flush;
+    /// [`SiblingsError::Disabled`] if that record is disabled
+    async fn stale(
+        &self,
+        sibling: &str,
+        why: &str,
+    ) -> Option<Result<RegionEndpoint, SiblingsError>> {
+        let expired = self.endpoints.load().get(sibling).cloned();
+        let stale = match expired {
+            Some(ep) => Some(ep),
+            None => self.stale.load().get(sibling).cloned(),
+        };
+        log_to!(
+            Resolve,
+            Warn,
+            "endpoint_with: sibling[{sibling}] {why}, stale record: {}",
+            stale.is_some()
+        );
+        stale.map(|ep| {
+            if ep.is_enabled() {
+                Ok(ep)
+            } else {
+                Err(SiblingsError::Disabled(sibling.to_owned()))
+            }
+        })
+    }
+
+    fn mark_degraded(&self) {
+        *self
+            .degraded_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(self.clock.now() + DEGRADED_FOR);
+    }
+
+    /// Whether a Redis read failed within the last [`DEGRADED_FOR`] or the circuit is open
+    pub fn degraded(&self) -> bool {
+        self.circuit_open()
+            || self
+                .degraded_until
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .is_some_and(|until| self.clock.now() < until)
+    }
+
+    /// [`Self::try_sibling`] within the cold fetch budget of `ctx`
+    pub async fn try_sibling_with(
+        &self,
+        ctx: &ResolveContext,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Result<Option<String>, SiblingsError> {
+        let lookup = self.before_lookup(sibling, region)?;
+        let url = self
+            .endpoint_with(ctx, &lookup.sibling)
+            .await?
+            .map(|ep| self.nearest(&ep, lookup.region.as_deref()).to_string());
+
+        self.after_lookup(&lookup, url)
+    }
+
+    /// [`Self::sibling`] within the cold fetch budget of `ctx`
+    pub async fn sibling_with(
+        &self,
+        ctx: &ResolveContext,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Option<String> {
+        match self.try_sibling_with(ctx, sibling, region).await {
+            Ok(url) => url,
+            Err(e) => {
+                log_to!(
+                    Resolve,
+                    Warn,
+                    "sibling_with: endpoint for sibling[{sibling}] was not fetched: {e}"
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cold_fetch_budget() {
+        let ctx = ResolveContext::new().with_max_cold_fetches(2);
+        assert!(ctx.take());
+        assert!(ctx.take());
+        assert!(!ctx.take());
+        assert_eq!(ctx.cold_fetches(), 2);
+
+        let unlimited = ResolveContext::new().with_priority(Priority::Critical);
+        assert!((0..10).all(|_| unlimited.take()));
+        assert_eq!(unlimited.cold_fetches(), 10);
+    }
+}
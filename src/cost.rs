@@ -0,0 +1,120 @@
+//! What a call to a sibling is expected to cost, by region.
+//!
+//! Records may note a latency class and a free-form cost note per region under `costs`, the
+//! `default` entry covering every region without one:
+//! `"costs": {"default": {"latency": "local"}, "us": {"latency": "cross_region", "cost": "egress billed per GB"}}`.
+//! [`Siblings::explain`] shows the note with the url a lookup resolves to, so a developer can
+//! weigh calling a sibling cross-region against queuing the work for the local region.
+
+use std::borrow::Cow;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{lookup_region, RegionEndpoint, Siblings, SiblingsError};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyClass {
+    /// Same region, a few milliseconds
+    Local,
+    /// Another zone or a nearby region
+    Regional,
+    /// Across continents, tens to hundreds of milliseconds
+    CrossRegion,
+}
+
+impl LatencyClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::Regional => "regional",
+            Self::CrossRegion => "cross_region",
+        }
+    }
+}
+
+/// Expected latency and cost of calling a sibling in one region
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CostNote {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<LatencyClass>,
+    /// Free-form, e.g. `"egress billed per GB"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<String>,
+}
+
+/// How a lookup resolves, from [`Siblings::explain`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// Sibling looked up, after interceptors
+    pub sibling: String,
+    /// Region code looked up, after the default region and interceptors
+    pub region: Option<String>,
+    /// What [`Siblings::try_sibling`] returns
+    pub url: Option<String>,
+    pub cost: Option<CostNote>,
+}
+
+impl RegionEndpoint {
+    /// The note for calls in `region`, the `default` one when the region has none
+    pub fn cost(&self, region: Option<&str>) -> Option<&CostNote> {
+        region
+            .and_then(|r| lookup_region(&self.costs, r))
+            .or_else(|| self.costs.get("default"))
+    }
+}
+
+impl Siblings {
+    /// The url a lookup of `sibling` in `region` resolves to, with the latency and cost noted for
+    /// that region
+    pub async fn explain(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Result<Explanation, SiblingsError> {
+        let lookup = self.before_lookup(sibling, region)?;
+        let ep = self.endpoint(&lookup.sibling).await?;
+        let region = lookup.region.as_deref();
+        let url = ep.as_ref().map(|ep| self.nearest(ep, region).to_string());
+        let url = self.after_lookup(&lookup, url)?;
+
+        Ok(Explanation {
+            cost: ep.as_ref().and_then(|ep| ep.cost(region)).cloned(),
+            url,
+            region: lookup.region.map(Cow::into_owned),
+            sibling: lookup.sibling.into_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn explain_shows_the_regions_cost() -> anyhow::Result<()> {
+        let ep = Siblings::deserialize(
+            br#"{"default":"https://k9","us":"https://k9.us","costs":{"default":{"latency":"local"},"us":{"latency":"cross_region","cost":"egress billed"}}}"#
+                .to_vec(),
+        )?;
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        crate::update(&sib.endpoints, |e| e.insert("k9", ep.clone()));
+
+        let us = sib.explain("k9", Some("USA")).await?;
+        assert_eq!(us.url.as_deref(), Some("https://k9.us"));
+        assert_eq!(
+            us.cost.as_ref().and_then(|c| c.latency),
+            Some(LatencyClass::CrossRegion)
+        );
+        assert_eq!(
+            us.cost.and_then(|c| c.cost).as_deref(),
+            Some("egress billed")
+        );
+
+        let home = sib.explain("k9", Some("in")).await?;
+        assert_eq!(home.url.as_deref(), Some("https://k9"));
+        assert_eq!(home.cost.and_then(|c| c.latency), Some(LatencyClass::Local));
+
+        Ok(())
+    }
+}
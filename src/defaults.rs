@@ -0,0 +1,195 @@
+//! `_defaults` entry and per-env expansion of siblings.json.
+//!
+//! One file can describe every env: a record's `_env` maps env names to keys overriding the
+//! record's own in that env, and `{env}` anywhere in a value is replaced with the env name, so
+//! `{"default": "https://k9.{env}.example.com", "_env": {"prod": {"default": "https://k9.example.com"}}}`
+//! yields a dev and a prod record. `_defaults` can carry its own `_env` the same way.
+//!
+//! Every record inherits the top level keys of `_defaults` it doesn't set itself. Two keys are
+//! directives rather than record fields: `scheme` and `domain` turn bare names into urls, so
+//! `{"default": "k9"}` under `{"scheme": "https", "domain": "svc.xambit.io"}` becomes
+//! `https://k9.svc.xambit.io`. Values that already carry a scheme are left alone.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+
+use crate::{Env, RegionEndpoint};
+
+const DEFAULTS: &str = "_defaults";
+const PER_ENV: &str = "_env";
+
+/// Parses a siblings file into the records of `env`, with `_defaults` and env overrides applied
+pub fn parse_siblings_file(data: &str, env: &Env) -> Result<HashMap<String, RegionEndpoint>> {
+    let mut file = serde_json::from_str::<Map<String, Value>>(data)?;
+    let defaults = match file.remove(DEFAULTS) {
+        Some(Value::Object(d)) => for_env(d, env)?,
+        Some(_) => return Err(anyhow!("{DEFAULTS} must be an object")),
+        None => Map::new(),
+    };
+
+    file.into_iter()
+        .map(|(sibling, record)| {
+            let Value::Object(record) = record else {
+                return Err(anyhow!("sibling[{sibling}] is not an object"));
+            };
+            let record = for_env(record, env).map_err(|e| anyhow!("sibling[{sibling}]: {e}"))?;
+            let mut record = Value::Object(inherit(record, &defaults));
+            substitute_env(&mut record, env);
+
+            let record =
+                serde_json::from_value(record).map_err(|e| anyhow!("sibling[{sibling}]: {e}"))?;
+
+            Ok((sibling, record))
+        })
+        .collect()
+}
+
+/// `obj` with the overrides of `env` from its `_env` applied
+fn for_env(mut obj: Map<String, Value>, env: &Env) -> Result<Map<String, Value>> {
+    let overrides = match obj.remove(PER_ENV) {
+        Some(Value::Object(mut per_env)) => per_env.remove(env.name()),
+        Some(_) => return Err(anyhow!("{PER_ENV} must be an object")),
+        None => None,
+    };
+
+    match overrides {
+        Some(Value::Object(overrides)) => obj.extend(overrides),
+        Some(_) => return Err(anyhow!("{PER_ENV}.{} must be an object", env.name())),
+        None => {}
+    }
+
+    Ok(obj)
+}
+
+fn substitute_env(value: &mut Value, env: &Env) {
+    match value {
+        Value::String(s) if s.contains("{env}") => *s = s.replace("{env}", env.name()),
+        Value::Array(values) => values.iter_mut().for_each(|v| substitute_env(v, env)),
+        Value::Object(values) => values.values_mut().for_each(|v| substitute_env(v, env)),
+        _ => {}
+    }
+}
+
+fn inherit(mut record: Map<String, Value>, defaults: &Map<String, Value>) -> Map<String, Value> {
+    for (k, v) in defaults {
+        if !record.contains_key(k) {
+            record.insert(k.clone(), v.clone());
+        }
+    }
+
+    let scheme = record.remove("scheme");
+    let domain = record.remove("domain");
+    let scheme = scheme.as_ref().and_then(Value::as_str);
+    let domain = domain.as_ref().and_then(Value::as_str);
+    if scheme.is_none() && domain.is_none() {
+        return record;
+    }
+
+    // `default` and the region codes are the only top level strings of a record
+    for v in record.values_mut() {
+        if let Value::String(url) = v {
+            *url = expand(url, scheme, domain);
+        }
+    }
+
+    record
+}
+
+fn expand(url: &str, scheme: Option<&str>, domain: Option<&str>) -> String {
+    if url.contains("://") {
+        return url.to_string();
+    }
+
+    let (host, path) = url.split_once('/').unwrap_or((url, ""));
+    let host = match domain {
+        Some(domain) if !host.contains(['.', ':']) => format!("{host}.{domain}"),
+        _ => host.to_string(),
+    };
+    let scheme = scheme.unwrap_or("https");
+
+    if path.is_empty() {
+        format!("{scheme}://{host}")
+    } else {
+        format!("{scheme}://{host}/{path}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_inherit_defaults() -> Result<()> {
+        let records = parse_siblings_file(
+            r#"{
+                "_defaults": {"scheme": "https", "domain": "svc.xambit.io", "in": "in.xambit.io"},
+                "k9": {"default": "k9"},
+                "matrix": {"default": "http://matrix:8080", "in": "matrix-in", "scheme": "http"}
+            }"#,
+            &Env::Prod,
+        )?;
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records["k9"].get_in(None).as_deref(),
+            Some("https://k9.svc.xambit.io")
+        );
+        assert_eq!(
+            records["k9"].get_in(Some("IN")).as_deref(),
+            Some("https://in.xambit.io")
+        );
+        assert_eq!(
+            records["matrix"].get_in(None).as_deref(),
+            Some("http://matrix:8080")
+        );
+        assert_eq!(
+            records["matrix"].get_in(Some("in")).as_deref(),
+            Some("http://matrix-in.svc.xambit.io")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_defaults_is_a_plain_file() -> Result<()> {
+        let records = parse_siblings_file(r#"{"k9": {"default": "k9"}}"#, &Env::Dev)?;
+        assert_eq!(records["k9"].get_in(None).as_deref(), Some("k9"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn one_file_for_every_env() -> Result<()> {
+        let file = r#"{
+            "_defaults": {"_env": {"dev": {"us": "https://us.dev.example.com"}}},
+            "k9": {
+                "default": "https://k9.{env}.example.com",
+                "_env": {"prod": {"default": "https://k9.example.com"}}
+            }
+        }"#;
+
+        let dev = parse_siblings_file(file, &Env::Dev)?;
+        assert_eq!(
+            dev["k9"].get_in(None).as_deref(),
+            Some("https://k9.dev.example.com")
+        );
+        assert_eq!(
+            dev["k9"].get_in(Some("us")).as_deref(),
+            Some("https://us.dev.example.com")
+        );
+
+        let prod = parse_siblings_file(file, &Env::Prod)?;
+        assert_eq!(
+            prod["k9"].get_in(None).as_deref(),
+            Some("https://k9.example.com")
+        );
+        assert_eq!(
+            prod["k9"].get_in(Some("us")).as_deref(),
+            Some("https://k9.example.com")
+        );
+
+        Ok(())
+    }
+}
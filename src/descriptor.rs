@@ -0,0 +1,160 @@
+//! How to talk to a sibling beyond its url: protocol, default port, health path and auth style.
+//!
+//! Descriptors of the first-class siblings ship with the crate in `descriptors.json`; one
+//! published with [`Siblings::register_descriptor`] as `svc-{sibling}` overrides the shipped one
+//! for that env. Siblings in neither get [`ServiceDescriptor::default`].
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{update, Siblings, SiblingsError};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Http,
+    #[default]
+    Https,
+    Grpc,
+}
+
+/// What a caller sends to authenticate
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(tag = "style", rename_all = "snake_case")]
+pub enum AuthStyle {
+    None,
+    /// `Authorization: Bearer {token}`
+    #[default]
+    Bearer,
+    /// The key in `header`
+    ApiKey {
+        header: String,
+    },
+    /// Client certificate
+    Mtls,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ServiceDescriptor {
+    pub protocol: Protocol,
+    /// Port when the url has none
+    pub port: u16,
+    pub health_path: String,
+    pub auth: AuthStyle,
+}
+
+impl Default for ServiceDescriptor {
+    fn default() -> Self {
+        Self {
+            protocol: Protocol::Https,
+            port: 443,
+            health_path: "/health".to_string(),
+            auth: AuthStyle::Bearer,
+        }
+    }
+}
+
+impl ServiceDescriptor {
+    /// The descriptor shipped with the crate for `sibling`
+    pub fn builtin(sibling: &str) -> Option<Self> {
+        static BUILTIN: OnceLock<HashMap<String, ServiceDescriptor>> = OnceLock::new();
+        BUILTIN
+            .get_or_init(|| {
+                serde_json::from_str(include_str!("descriptors.json"))
+                    .expect("descriptors.json is valid")
+            })
+            .get(sibling)
+            .cloned()
+    }
+
+    /// Health check url of a service reached at `base`
+    pub fn health_url(&self, base: &str) -> String {
+        format!(
+            "{}/{}",
+            base.trim_end_matches('/'),
+            self.health_path.trim_start_matches('/')
+        )
+    }
+}
+
+impl Siblings {
+    /// Publishes `descriptor` for `sibling` in the current env, overriding the shipped one
+    pub async fn register_descriptor(
+        &self,
+        sibling: &str,
+        descriptor: ServiceDescriptor,
+    ) -> Result<(), SiblingsError> {
+        let data = serde_json::to_vec(&descriptor)?;
+        self.set_cache(&self.keys.descriptor(sibling), &data)
+            .await?;
+
+        info!("register_descriptor: sibling[{sibling}] registered");
+        update(&self.endpoints, |e| {
+            e.descriptors.insert(sibling.to_owned(), descriptor.clone());
+        });
+
+        Ok(())
+    }
+
+    /// How to talk to `sibling`: the published descriptor, else the shipped one, else defaults
+    pub async fn descriptor(&self, sibling: &str) -> Result<ServiceDescriptor, SiblingsError> {
+        if let Some(descriptor) = self.endpoints.load().descriptors.get(sibling) {
+            return Ok(descriptor.clone());
+        }
+
+        let data = self.get_cache(&self.keys.descriptor(sibling)).await?;
+        let descriptor = if data.is_empty() {
+            ServiceDescriptor::builtin(sibling).unwrap_or_default()
+        } else {
+            serde_json::from_slice(&data)?
+        };
+        update(&self.endpoints, |e| {
+            e.descriptors.insert(sibling.to_owned(), descriptor.clone());
+        });
+
+        Ok(descriptor)
+    }
+
+    /// Health check url of `sibling` in `region`, from its endpoint and descriptor
+    pub async fn health_url(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Result<Option<String>, SiblingsError> {
+        let Some(base) = self.try_sibling(sibling, region).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(self.descriptor(sibling).await?.health_url(&base)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_descriptors() {
+        let sentry = ServiceDescriptor::builtin("sentry").unwrap();
+        assert_eq!(sentry.health_path, "/_health/");
+        assert_eq!(sentry.auth, AuthStyle::None);
+        assert_eq!(sentry.port, 443);
+
+        assert_eq!(
+            ServiceDescriptor::builtin("k9"),
+            Some(ServiceDescriptor::default())
+        );
+        assert_eq!(ServiceDescriptor::builtin("credit"), None);
+    }
+
+    #[test]
+    fn health_url() {
+        let descriptor = ServiceDescriptor::default();
+        assert_eq!(
+            descriptor.health_url("https://k9.xambit.io/"),
+            "https://k9.xambit.io/health"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Reading records from somewhere other than Redis.
+//!
+//! An [`EndpointSource`] answers the record reads of [`Siblings::with_source`] in place of Redis:
+//! Consul, etcd, a config service, or a map in a test. It is asked for the record of a sibling in
+//! an env, or for an archived version when the consumer is pinned, so env fallbacks, pins,
+//! regions and rollouts work unchanged on top of it. Everything else (publishing, listing,
+//! webhooks, descriptors, usage reports) stays Redis-only: writes fail with
+//! [`SiblingsError::Unsupported`] and descriptor and webhook reads find nothing.
+//!
+//! [`RedisSource`] is the source behind every Redis backend, reading records in the layout of its
+//! [`KeyScheme`]. A source slow or costly to ask can keep a Redis in front with [`CachedSource`]:
+//! records read from the source are written to Redis for a ttl and served from there, across
+//! every instance sharing that Redis. Redis failing only costs the caching, lookups go straight
+//! to the source.
+//!
+//! [`SiblingsError::Unsupported`]: crate::SiblingsError::Unsupported
+
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use redis::aio::MultiplexedConnection;
+#[cfg(feature = "cluster")]
+use redis::cluster_async::ClusterConnection;
+
+use crate::{cache, Backend, Env, KeyScheme, RegionEndpoint, Siblings, SiblingsError};
+
+pub type FetchFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<RegionEndpoint>>> + Send + 'a>>;
+
+pub trait EndpointSource: Send + Sync {
+    /// The live record of `sibling` in `env`, or its archived `pinned` version; `None` when there
+    /// is none
+    fn fetch<'a>(&'a self, env: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a>;
+}
+
+/// Records by sibling, the same in every env, as a test double; pinned versions find nothing
+impl EndpointSource for HashMap<String, RegionEndpoint> {
+    fn fetch<'a>(&'a self, _: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        let record = pinned
+            .is_none()
+            .then(|| self.get(sibling).cloned())
+            .flatten();
+        Box::pin(async move { Ok(record) })
+    }
+}
+
+/// The connection a [`RedisSource`] reads through
+#[derive(Clone)]
+enum RedisConn {
+    #[cfg(feature = "db")]
+    Pool(Arc<db::RedisPool>),
+    /// A Redis outside the `db` crate's config, e.g. one of several regional replicas
+    Direct(MultiplexedConnection),
+    /// A Redis Cluster, see [`Siblings::connect_cluster`]
+    #[cfg(feature = "cluster")]
+    Cluster(ClusterConnection),
+}
+
+/// The records of a Redis, as [`Siblings`] reads them and as a source other sources fall back to
+#[derive(Clone)]
+pub struct RedisSource {
+    conn: RedisConn,
+    pub(crate) keys: KeyScheme,
+}
+
+impl RedisSource {
+    /// Reads the Redis at `url`
+    pub async fn connect(url: &str) -> Result<Self, SiblingsError> {
+        let conn = redis::Client::open(url)
+            .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?
+            .get_multiplexed_tokio_connection()
+            .await
+            .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?;
+
+        Ok(Self::direct(conn))
+    }
+
+    /// Reads through a connection of the `db` crate's pool
+    #[cfg(feature = "db")]
+    pub(crate) fn pool(db: Arc<db::RedisPool>) -> Self {
+        Self::with_conn(RedisConn::Pool(db))
+    }
+
+    pub(crate) fn direct(conn: MultiplexedConnection) -> Self {
+        Self::with_conn(RedisConn::Direct(conn))
+    }
+
+    #[cfg(feature = "cluster")]
+    pub(crate) fn cluster(conn: ClusterConnection) -> Self {
+        Self::with_conn(RedisConn::Cluster(conn))
+    }
+
+    fn with_conn(conn: RedisConn) -> Self {
+        Self {
+            conn,
+            keys: KeyScheme::default(),
+        }
+    }
+
+    /// Key scheme the records are stored in, when not the default
+    pub fn with_key_scheme(mut self, keys: KeyScheme) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    pub(crate) fn conn(&self) -> cache::Conn<'_> {
+        match &self.conn {
+            #[cfg(feature = "db")]
+            RedisConn::Pool(db) => cache::Conn::Pool(db),
+            RedisConn::Direct(conn) => cache::Conn::Direct(conn),
+            #[cfg(feature = "cluster")]
+            RedisConn::Cluster(conn) => cache::Conn::Cluster(conn),
+        }
+    }
+}
+
+impl EndpointSource for RedisSource {
+    fn fetch<'a>(&'a self, env: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let data = self.read(env, sibling, pinned).await?;
+            match data.is_empty() {
+                true => Ok(None),
+                false => Ok(Some(Siblings::deserialize(data)?)),
+            }
+        })
+    }
+}
+
+/// `source` behind a Redis caching what it finds for `ttl`; what it doesn't find isn't cached.
+/// Records are cached under the keys of the default [`KeyScheme`].
+pub struct CachedSource<S> {
+    source: S,
+    conn: MultiplexedConnection,
+    ttl: Duration,
+}
+
+impl<S: EndpointSource> CachedSource<S> {
+    /// Caches the records of `source` in the Redis at `url`
+    pub async fn connect(source: S, url: &str, ttl: Duration) -> Result<Self, SiblingsError> {
+        let conn = redis::Client::open(url)
+            .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?
+            .get_multiplexed_tokio_connection()
+            .await
+            .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?;
+
+        Ok(Self { source, conn, ttl })
+    }
+
+    async fn cached(
+        &self,
+        env: &Env,
+        sibling: &str,
+        pinned: Option<u64>,
+    ) -> Result<Option<RegionEndpoint>> {
+        let keys = KeyScheme::default();
+        let key = keys.key(
+            env,
+            &match pinned {
+                Some(version) => keys.archive(sibling, version),
+                None => keys.endpoint(sibling),
+            },
+        );
+        let conn = cache::Conn::Direct(&self.conn);
+        match cache::get(conn, &key).await {
+            Ok(data) if !data.is_empty() => return Ok(Some(Siblings::deserialize(data)?)),
+            Ok(_) => {}
+            Err(e) => log_to!(Resolve, Warn, "source: {key} read without the cache: {e}"),
+        }
+
+        let ep = self.source.fetch(env, sibling, pinned).await?;
+        if let Some(ep) = &ep
+            && let Err(e) = cache::set_ex(conn, &key, &serde_json::to_vec(ep)?, self.ttl).await
+        {
+            log_to!(Resolve, Warn, "source: {key} not cached: {e}");
+        }
+
+        Ok(ep)
+    }
+}
+
+impl<S: EndpointSource> EndpointSource for CachedSource<S> {
+    fn fetch<'a>(&'a self, env: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        Box::pin(self.cached(env, sibling, pinned))
+    }
+}
+
+impl Siblings {
+    /// Reads records from `source` instead of Redis
+    pub fn with_source(source: impl EndpointSource + 'static, me: Option<&str>) -> Self {
+        Self::with_backend(Backend::Source(Arc::new(source)), me)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Env;
+
+    #[tokio::test]
+    async fn reads_through_a_source() -> anyhow::Result<()> {
+        let source = HashMap::from([
+            (
+                "k9".to_string(),
+                Siblings::deserialize(
+                    br#"{"default":"https://k9.dev","in":"https://k9.dev.in"}"#.to_vec(),
+                )?,
+            ),
+            (
+                "matrix".to_string(),
+                Siblings::deserialize(br#"{"default":"https://matrix"}"#.to_vec())?,
+            ),
+        ]);
+        let sib = Siblings::with_source(source, None).with_env(Env::Dev);
+
+        assert_eq!(
+            sib.try_sibling("k9", Some("IN")).await?.as_deref(),
+            Some("https://k9.dev.in")
+        );
+        assert_eq!(
+            sib.try_sibling("matrix", None).await?.as_deref(),
+            Some("https://matrix")
+        );
+        assert_eq!(sib.try_sibling("bureau", None).await?, None);
+        assert_eq!(
+            sib.health_url("k9", None).await?.as_deref(),
+            Some("https://k9.dev/health")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Comparing the records two envs publish, to catch "works in dev, missing in prod" before a
+//! deploy.
+//!
+//! [`Siblings::diff_envs`] compares two envs in the Redis of one instance;
+//! [`Siblings::diff_with`] compares this instance's env with another instance's, for envs in
+//! different Redis instances like dev and prod. `siblings-cli diff-envs dev prod` prints the latter.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_derive::Serialize;
+
+use crate::{Env, RegionEndpoint, Siblings, SiblingsError};
+
+/// How the records of env `a` compare to those of env `b`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EnvDiff {
+    pub a: String,
+    pub b: String,
+    /// Published in `a` only
+    pub only_in_a: Vec<String>,
+    /// Published in `b` only
+    pub only_in_b: Vec<String>,
+    /// Urls that differ between records published in both
+    pub urls: Vec<UrlDiff>,
+    /// Published in both with the same urls
+    pub same: Vec<String>,
+}
+
+/// One url of a sibling that differs between the envs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UrlDiff {
+    pub sibling: String,
+    /// `default` or a region code
+    pub region: String,
+    /// `None` when the record of that env has nothing for `region`
+    pub a: Option<String>,
+    pub b: Option<String>,
+}
+
+impl EnvDiff {
+    /// `true` when both envs publish the same siblings; their urls are expected to differ
+    pub fn same_siblings(&self) -> bool {
+        self.only_in_a.is_empty() && self.only_in_b.is_empty()
+    }
+
+    fn between(
+        a: &Env,
+        b: &Env,
+        in_a: &BTreeMap<String, RegionEndpoint>,
+        in_b: &BTreeMap<String, RegionEndpoint>,
+    ) -> Self {
+        let mut diff = Self {
+            a: a.name().to_string(),
+            b: b.name().to_string(),
+            ..Default::default()
+        };
+
+        for sibling in in_a.keys().chain(in_b.keys()).collect::<BTreeSet<_>>() {
+            let (ep_a, ep_b) = match (in_a.get(sibling), in_b.get(sibling)) {
+                (Some(ep_a), Some(ep_b)) => (ep_a, ep_b),
+                (Some(_), None) => {
+                    diff.only_in_a.push(sibling.clone());
+                    continue;
+                }
+                _ => {
+                    diff.only_in_b.push(sibling.clone());
+                    continue;
+                }
+            };
+
+            let urls = url_diffs(sibling, ep_a, ep_b);
+            if urls.is_empty() {
+                diff.same.push(sibling.clone());
+            }
+            diff.urls.extend(urls);
+        }
+
+        diff
+    }
+}
+
+fn url_diffs(sibling: &str, a: &RegionEndpoint, b: &RegionEndpoint) -> Vec<UrlDiff> {
+    let mut diffs = Vec::new();
+    if a.default != b.default {
+        diffs.push(UrlDiff {
+            sibling: sibling.to_owned(),
+            region: "default".to_string(),
+            a: Some(a.default.to_string()),
+            b: Some(b.default.to_string()),
+        });
+    }
+
+    for region in a
+        .regions
+        .keys()
+        .chain(b.regions.keys())
+        .collect::<BTreeSet<_>>()
+    {
+        let (url_a, url_b) = (a.regions.get(region), b.regions.get(region));
+        if url_a != url_b {
+            diffs.push(UrlDiff {
+                sibling: sibling.to_owned(),
+                region: region.clone(),
+                a: url_a.map(|url| url.to_string()),
+                b: url_b.map(|url| url.to_string()),
+            });
+        }
+    }
+
+    diffs
+}
+
+impl Siblings {
+    /// Compares the records envs `a` and `b` publish in this instance's Redis
+    pub async fn diff_envs(&self, a: Env, b: Env) -> Result<EnvDiff, SiblingsError> {
+        self.clone()
+            .with_env(a)
+            .diff_with(&self.clone().with_env(b))
+            .await
+    }
+
+    /// Compares the records of this instance's env with those of `other`'s
+    pub async fn diff_with(&self, other: &Siblings) -> Result<EnvDiff, SiblingsError> {
+        let (in_a, in_b) = tokio::try_join!(self.list_siblings(), other.list_siblings())?;
+        Ok(EnvDiff::between(&self.env, &other.env, &in_a, &in_b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(json: &str) -> RegionEndpoint {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn missing_and_moved() {
+        let dev = BTreeMap::from([
+            (
+                "k9".to_string(),
+                record(r#"{"default":"https://k9.dev","in":"https://k9.dev/in"}"#),
+            ),
+            (
+                "matrix".to_string(),
+                record(r#"{"default":"https://matrix"}"#),
+            ),
+            (
+                "retina".to_string(),
+                record(r#"{"default":"https://retina.dev"}"#),
+            ),
+        ]);
+        let prod = BTreeMap::from([
+            ("k9".to_string(), record(r#"{"default":"https://k9.dev"}"#)),
+            (
+                "matrix".to_string(),
+                record(r#"{"default":"https://matrix"}"#),
+            ),
+        ]);
+
+        let diff = EnvDiff::between(&Env::Dev, &Env::Prod, &dev, &prod);
+        assert_eq!(diff.only_in_a, vec!["retina"]);
+        assert!(diff.only_in_b.is_empty());
+        assert_eq!(diff.same, vec!["matrix"]);
+        assert_eq!(
+            diff.urls,
+            vec![UrlDiff {
+                sibling: "k9".to_string(),
+                region: "in".to_string(),
+                a: Some("https://k9.dev/in".to_string()),
+                b: None,
+            }]
+        );
+        assert!(!diff.same_siblings());
+    }
+}
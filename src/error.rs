@@ -0,0 +1,228 @@
+use std::{error::Error, fmt::Write, time::Duration};
+
+/// Why an endpoint could not be resolved
+#[derive(Debug, thiserror::Error)]
+pub enum SiblingsError {
+    /// No record is published for the sibling in this env. Also what an unknown sibling name
+    /// gets: there is no separate `UnknownSibling`, as any name may be published later
+    #[error("sibling {0} is not configured")]
+    NotConfigured(String),
+    /// The sibling was disabled with [`Siblings::disable`](crate::Siblings::disable)
+    #[error("sibling {0} is disabled")]
+    Disabled(String),
+    /// An [`Interceptor`](crate::Interceptor) stopped the lookup
+    #[error("lookup of sibling {sibling} vetoed: {reason}")]
+    Vetoed { sibling: String, reason: String },
+    /// Siblings required at startup with no record in this env
+    #[error("required siblings not configured: {}", .0.join(", "))]
+    Missing(Vec<String>),
+    /// Redis (or the sidecar agent in front of it) could not be read
+    #[error("redis unreachable: {0}")]
+    RedisUnreachable(#[source] Box<dyn Error + Send + Sync>),
+    /// A Redis read took longer than [`Siblings::with_read_timeout`](crate::Siblings::with_read_timeout)
+    #[error("redis read timed out after {0:?}")]
+    Timeout(Duration),
+    /// Redis failed too many reads in a row; reads fail fast until a probe gets through
+    #[error("redis circuit open for {0:?}, failing fast")]
+    CircuitOpen(Duration),
+    /// The process-wide Redis ops budget is exhausted
+    #[error("redis ops budget exceeded, retry after {0:?}")]
+    Throttled(Duration),
+    /// Region code not known to [`Regions`](crate::Regions)
+    #[error("region {0} not supported")]
+    UnknownRegion(String),
+    /// The configured backend can't do this
+    #[error("{what} not supported by the {backend} backend")]
+    Unsupported {
+        what: &'static str,
+        backend: &'static str,
+    },
+    /// The published record is not valid
+    #[error("failed to deserialize endpoint: {0}")]
+    Deserialize(#[from] serde_json::Error),
+    /// Someone else published the sibling between reading and writing it
+    #[error("sibling {sibling} is at version {found}, expected {expected}; re-read and retry")]
+    Conflict {
+        sibling: String,
+        expected: u64,
+        found: u64,
+    },
+    /// The record resolved to something that isn't a url
+    #[error("sibling {sibling} resolved to invalid url {url:?}")]
+    InvalidUrl { sibling: String, url: String },
+    /// A config knob was set to something it can't take
+    #[error("{knob}={value:?} is invalid, expected {expected}")]
+    InvalidKnob {
+        knob: String,
+        value: String,
+        expected: &'static str,
+    },
+}
+
+impl SiblingsError {
+    pub(crate) fn unreachable(e: anyhow::Error) -> Self {
+        Self::RedisUnreachable(e.into())
+    }
+
+    /// Short stable name of the variant, for metrics labels and logs
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::NotConfigured(_) => "not_configured",
+            Self::Missing(_) => "missing",
+            Self::Disabled(_) => "disabled",
+            Self::Vetoed { .. } => "vetoed",
+            Self::RedisUnreachable(_) => "redis_unreachable",
+            Self::Throttled(_) => "throttled",
+            Self::Timeout(_) => "timeout",
+            Self::CircuitOpen(_) => "circuit_open",
+            Self::UnknownRegion(_) => "unknown_region",
+            Self::Unsupported { .. } => "unsupported",
+            Self::Deserialize(_) => "deserialize",
+            Self::InvalidUrl { .. } => "invalid_url",
+            Self::Conflict { .. } => "conflict",
+            Self::InvalidKnob { .. } => "invalid_knob",
+        }
+    }
+
+    /// Stable error code, `siblings::` and the [`Self::label`], for runbooks and alerts
+    pub fn code(&self) -> String {
+        format!("siblings::{}", self.label())
+    }
+
+    /// What the engineer on call should do about it
+    pub fn help(&self) -> String {
+        match self {
+            Self::NotConfigured(sibling) => format!(
+                "publish a record for {sibling}: add it to the siblings file and run \
+                 `siblings-cli load` with X_ENV set to this service's env"
+            ),
+            Self::Missing(siblings) => format!(
+                "publish records for {}: add them to the siblings file and run \
+                 `siblings-cli load` with X_ENV set to this service's env",
+                siblings.join(", ")
+            ),
+            Self::Disabled(sibling) => {
+                format!("run `siblings-cli enable {sibling}` once it is safe to call again")
+            }
+            Self::Vetoed { .. } => {
+                "an interceptor added with `with_interceptor` refused the lookup, see its reason"
+                    .to_string()
+            }
+            Self::RedisUnreachable(_) => "check the discovery Redis (or the sidecar agent) is up \
+                 and reachable; records already in memory keep resolving"
+                .to_string(),
+            Self::Timeout(_) => "discovery Redis is slow to answer; check its latency before \
+                 raising `with_read_timeout`"
+                .to_string(),
+            Self::CircuitOpen(_) => "discovery Redis failed repeatedly; reads fail fast until a \
+                 probe gets through, check Redis health"
+                .to_string(),
+            Self::Throttled(_) => "this process used up its X_SIBLINGS_REDIS_OPS budget; look for \
+                 a lookup loop that never hits memory"
+                .to_string(),
+            Self::UnknownRegion(_) => {
+                "use IN, US, EU, SG or APAC, or a region code present in the record".to_string()
+            }
+            Self::Unsupported { backend, .. } => format!(
+                "build the instance on Redis (`Siblings::new`, `Siblings::connect_url`) \
+                 instead of the {backend} backend"
+            ),
+            Self::Deserialize(_) => "the published record is not valid; fix it in the siblings \
+                 file and run `siblings-cli load`"
+                .to_string(),
+            Self::Conflict { .. } => "re-read the record and publish again".to_string(),
+            Self::InvalidUrl { sibling, .. } => {
+                format!("fix the url of {sibling} in the siblings file and run `siblings-cli load`")
+            }
+            Self::InvalidKnob { knob, .. } => format!("fix or unset {knob}"),
+        }
+    }
+
+    /// One line for logs: the error with its code, the causes it doesn't print and what to do
+    pub fn report(&self) -> String {
+        let mut report = format!("{self} [{}]", self.code());
+        let mut source = self.source();
+        while let Some(cause) = source {
+            // most variants already print their source
+            let cause_text = cause.to_string();
+            if !report.contains(&cause_text) {
+                let _ = write!(report, ": {cause_text}");
+            }
+            source = cause.source();
+        }
+        let _ = write!(report, "; help: {}", self.help());
+        report
+    }
+
+    /// HTTP status a service fronting the lookup would answer with
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Self::NotConfigured(_) | Self::Missing(_) => 404,
+            Self::UnknownRegion(_) => 400,
+            Self::Unsupported { .. } => 501,
+            Self::Throttled(_) | Self::CircuitOpen(_) | Self::Disabled(_) => 503,
+            Self::Conflict { .. } => 409,
+            Self::Vetoed { .. } => 403,
+            Self::Timeout(_) => 504,
+            Self::InvalidKnob { .. } => 500,
+            Self::RedisUnreachable(_) | Self::Deserialize(_) | Self::InvalidUrl { .. } => 502,
+        }
+    }
+}
+
+#[cfg(feature = "diagnostics")]
+impl miette::Diagnostic for SiblingsError {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new(SiblingsError::code(self)))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new(SiblingsError::help(self)))
+    }
+}
+
+/// `url` if it has a scheme and a host, else [`SiblingsError::InvalidUrl`]
+pub(crate) fn check_url(sibling: &str, url: String) -> Result<String, SiblingsError> {
+    let valid = url
+        .split_once("://")
+        .is_some_and(|(scheme, rest)| !scheme.is_empty() && !rest.is_empty());
+    if !valid {
+        return Err(SiblingsError::InvalidUrl {
+            sibling: sibling.to_owned(),
+            url,
+        });
+    }
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_need_a_scheme_and_host() {
+        assert!(check_url("k9", "https://k9.xambit.io".to_string()).is_ok());
+
+        let err = check_url("k9", "k9.xambit.io".to_string()).unwrap_err();
+        assert_eq!(err.label(), "invalid_url");
+        assert_eq!(err.status_code(), 502);
+        assert!(check_url("k9", "https://".to_string()).is_err());
+    }
+
+    #[test]
+    fn reports_say_what_to_do() {
+        let err = SiblingsError::NotConfigured("k9".to_string());
+        assert_eq!(err.code(), "siblings::not_configured");
+        assert!(err
+            .report()
+            .starts_with("sibling k9 is not configured [siblings::not_configured]"));
+        assert!(err.report().contains("help: publish a record for k9"));
+
+        let err = SiblingsError::unreachable(anyhow::anyhow!("connection refused"));
+        assert_eq!(
+            err.report().split("; help").next(),
+            Some("redis unreachable: connection refused [siblings::redis_unreachable]")
+        );
+    }
+}
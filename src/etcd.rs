@@ -0,0 +1,309 @@
+//! Records stored in etcd, behind the `etcd` feature.
+//!
+//! [`EtcdSource`] is an [`EndpointSource`] reading the same JSON records Redis holds from etcd v3,
+//! through its JSON gateway (`POST /v3/kv/range`). Each record sits at the Redis key under a
+//! prefix, `siblings/dev-ep-k9` by default, so loaders write the same keys and values to either.
+//!
+//! [`Siblings::watch_etcd`] is the etcd take on [`Siblings::watch_keyspace`]: it watches the
+//! live endpoint keys of this env (`POST /v3/watch`) and refreshes a record in memory as soon as
+//! its key is put or deleted.
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::{Bytes, Incoming},
+    header, Request, Response, Uri,
+};
+use hyper_util::rt::TokioIo;
+use serde_derive::Deserialize;
+use serde_json::json;
+use tokio::task::JoinHandle;
+
+use crate::{EndpointSource, Env, FetchFuture, KeyScheme, RegionEndpoint, Siblings, SiblingsError};
+
+/// Prefix of record keys unless set with [`EtcdSource::with_prefix`]
+pub const DEFAULT_ETCD_PREFIX: &str = "siblings/";
+
+/// Wait before watching again after the watch stream ended
+const REWATCH_AFTER: Duration = Duration::from_secs(5);
+
+/// Reads records from the etcd at `addr`
+#[derive(Debug, Clone)]
+pub struct EtcdSource {
+    addr: Uri,
+    prefix: String,
+    keys: KeyScheme,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RangeResponse {
+    #[serde(default)]
+    kvs: Vec<KeyValue>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct KeyValue {
+    #[serde(default)]
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WatchMessage {
+    #[serde(default)]
+    result: WatchResult,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct WatchResult {
+    #[serde(default)]
+    events: Vec<WatchEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WatchEvent {
+    kv: KeyValue,
+}
+
+impl EtcdSource {
+    /// `addr` is the client url of an etcd member, e.g. `http://127.0.0.1:2379`
+    pub fn new(addr: &str) -> Result<Self> {
+        let addr = addr.parse::<Uri>()?;
+        if addr.scheme_str() != Some("http") || addr.host().is_none() {
+            bail!("etcd needs an http:// address, got {addr}");
+        }
+
+        Ok(Self {
+            addr,
+            prefix: DEFAULT_ETCD_PREFIX.to_string(),
+            keys: KeyScheme::default(),
+        })
+    }
+
+    /// Prefix the Redis keys of records are stored under in etcd
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Key scheme the records are stored under, when not the default
+    pub fn with_key_scheme(mut self, keys: KeyScheme) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    async fn record(
+        &self,
+        env: &Env,
+        sibling: &str,
+        pinned: Option<u64>,
+    ) -> Result<Option<RegionEndpoint>> {
+        let key = self.keys.key(
+            env,
+            &match pinned {
+                Some(version) => self.keys.archive(sibling, version),
+                None => self.keys.endpoint(sibling),
+            },
+        );
+        let body = json!({ "key": STANDARD.encode(format!("{}{key}", self.prefix)) });
+        let resp = self.post("/v3/kv/range", body).await?;
+        let range =
+            serde_json::from_slice::<RangeResponse>(&resp.into_body().collect().await?.to_bytes())?;
+
+        match range.kvs.first() {
+            Some(kv) => Ok(Some(Siblings::deserialize(STANDARD.decode(&kv.value)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn post(&self, path: &str, body: serde_json::Value) -> Result<Response<Incoming>> {
+        let host = self.addr.host().unwrap_or_default();
+        let port = self.addr.port_u16().unwrap_or(2379);
+        let stream = tokio::net::TcpStream::connect((host, port)).await?;
+        let (mut sender, conn) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(conn);
+
+        let req = Request::post(path)
+            .header(header::HOST, format!("{host}:{port}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(serde_json::to_vec(&body)?)))?;
+        let resp = sender.send_request(req).await?;
+        if !resp.status().is_success() {
+            bail!("etcd answered {} for {path}", resp.status());
+        }
+
+        Ok(resp)
+    }
+
+    /// Refreshes what `siblings` holds of each key put or deleted under `prefix`, until the
+    /// stream ends
+    async fn watch(&self, siblings: &Siblings, prefix: &str) -> Result<()> {
+        let key = format!("{}{prefix}", self.prefix);
+        let body = json!({ "create_request": {
+            "key": STANDARD.encode(&key),
+            "range_end": STANDARD.encode(prefix_end(key.as_bytes())),
+        }});
+        let mut body = self.post("/v3/watch", body).await?.into_body();
+
+        let mut buf = Vec::new();
+        while let Some(frame) = body.frame().await {
+            let Ok(data) = frame?.into_data() else {
+                continue;
+            };
+            buf.extend_from_slice(&data);
+            while let Some(end) = buf.iter().position(|b| *b == b'\n') {
+                let line = buf.drain(..=end).collect::<Vec<_>>();
+                for key in self.changed_keys(&line) {
+                    siblings.keyspace_event(&key).await;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Redis keys of the records a watch message reports changed
+    fn changed_keys(&self, line: &[u8]) -> Vec<String> {
+        let message = match serde_json::from_slice::<WatchMessage>(line) {
+            Ok(message) => message,
+            Err(e) => {
+                log_to!(Refresh, Warn, "etcd: unreadable watch message: {e}");
+                return Vec::new();
+            }
+        };
+
+        message
+            .result
+            .events
+            .iter()
+            .filter_map(|event| STANDARD.decode(&event.kv.key).ok())
+            .filter_map(|key| String::from_utf8(key).ok())
+            .filter_map(|key| key.strip_prefix(&self.prefix).map(str::to_owned))
+            .collect()
+    }
+}
+
+impl EndpointSource for EtcdSource {
+    fn fetch<'a>(&'a self, env: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        Box::pin(self.record(env, sibling, pinned))
+    }
+}
+
+impl Siblings {
+    /// Watches the live endpoint records of this env in `etcd` and refreshes each in memory as
+    /// its key changes. A watch that ends is started again every [`REWATCH_AFTER`] and every
+    /// record refreshed once it's back, to catch missed changes.
+    pub async fn watch_etcd(&self, etcd: &EtcdSource) -> Result<JoinHandle<()>, SiblingsError> {
+        let prefix = self.cache_key(&self.keys.endpoint(""));
+        // fail here rather than in the task when etcd can't be reached at all
+        etcd.post(
+            "/v3/kv/range",
+            json!({ "key": STANDARD.encode(&etcd.prefix) }),
+        )
+        .await
+        .map_err(SiblingsError::unreachable)?;
+        log_to!(Refresh, Info, "etcd: watching {}{prefix}", etcd.prefix);
+
+        let (slf, etcd) = (self.clone(), etcd.clone());
+        Ok(tokio::spawn(async move {
+            loop {
+                match etcd.watch(&slf, &prefix).await {
+                    Ok(()) => log_to!(Refresh, Warn, "etcd: watch ended, watching again"),
+                    Err(e) => log_to!(Refresh, Warn, "etcd: watch failed, watching again: {e}"),
+                }
+                tokio::time::sleep(REWATCH_AFTER).await;
+
+                let cached = slf.cached_siblings().await;
+                slf.refresh_each(&cached).await;
+            }
+        }))
+    }
+}
+
+/// The end of the range of keys starting with `prefix`
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return end;
+        }
+    }
+
+    // every byte 0xff: to the end of the keyspace
+    vec![0]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::fake_http;
+
+    /// An etcd answering ranges of single keys from `records`
+    async fn fake_etcd(records: HashMap<&'static str, &'static str>) -> Result<String> {
+        fake_http::serve(move |request| {
+            let key = request
+                .split_once("\r\n\r\n")
+                .and_then(|(_, body)| serde_json::from_str::<KeyValue>(body).ok())
+                .and_then(|kv| STANDARD.decode(kv.key).ok())
+                .map(|key| String::from_utf8_lossy(&key).to_string())
+                .unwrap_or_default();
+            let kvs = records.get(key.as_str()).map_or(
+                json!([]),
+                |value| json!([{ "key": STANDARD.encode(&key), "value": STANDARD.encode(value) }]),
+            );
+            fake_http::respond("200 OK", &json!({ "kvs": kvs }).to_string())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn reads_records_under_the_prefix() -> anyhow::Result<()> {
+        let addr = fake_etcd(HashMap::from([
+            (
+                "siblings/dev-ep-k9",
+                r#"{"default":"https://k9.dev","in":"https://k9.dev.in"}"#,
+            ),
+            ("ep-matrix", r#"{"default":"https://not-under-the-prefix"}"#),
+        ]))
+        .await?;
+        let sib = Siblings::with_source(EtcdSource::new(&addr)?, None).with_env(Env::Dev);
+
+        assert_eq!(
+            sib.try_sibling("k9", Some("IN")).await?.as_deref(),
+            Some("https://k9.dev.in")
+        );
+        assert_eq!(sib.try_sibling("matrix", None).await?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn watch_messages_to_keys() -> anyhow::Result<()> {
+        let etcd = EtcdSource::new("http://127.0.0.1:2379")?;
+        let line = json!({ "result": { "events": [
+            { "kv": { "key": STANDARD.encode("siblings/dev-ep-k9") } },
+            { "type": "DELETE", "kv": { "key": STANDARD.encode("siblings/dev-ep-matrix") } },
+        ]}})
+        .to_string();
+
+        assert_eq!(
+            etcd.changed_keys(line.as_bytes()),
+            ["dev-ep-k9", "dev-ep-matrix"]
+        );
+        assert_eq!(
+            etcd.changed_keys(br#"{"result":{"created":true}}"#),
+            Vec::<String>::new()
+        );
+        assert_eq!(prefix_end(b"siblings/dev-ep-"), b"siblings/dev-ep.");
+
+        Ok(())
+    }
+}
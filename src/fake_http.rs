@@ -0,0 +1,57 @@
+//! A plain HTTP/1.1 server for the tests of the HTTP backed sources.
+//!
+//! [`serve`] answers every request with what its handler returns for the whole request, head
+//! and body, so each test only writes how the service it fakes answers.
+
+use anyhow::Result;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Serves `answer` of each request on a local port, returning its `http://` base url
+pub(crate) async fn serve(answer: impl Fn(&str) -> String + Send + 'static) -> Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            while !complete(&request) {
+                let Ok(n @ 1..) = stream.read(&mut buf).await else {
+                    break;
+                };
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            let resp = answer(&String::from_utf8_lossy(&request));
+            let _ = stream.write_all(resp.as_bytes()).await;
+        }
+    });
+
+    Ok(addr)
+}
+
+/// A response with `status`, e.g. `200 OK`, and `body`
+pub(crate) fn respond(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Whether `request` holds its head and the `content-length` bytes of body after it
+fn complete(request: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(request);
+    let Some((head, body)) = text.split_once("\r\n\r\n") else {
+        return false;
+    };
+    let length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    body.len() >= length
+}
@@ -0,0 +1,312 @@
+//! Resolving siblings through GCP Service Directory, behind the `gcp` feature.
+//!
+//! [`ServiceDirectorySource`] is an [`EndpointSource`] answering each sibling from the endpoints
+//! of the service of the same name in a Service Directory namespace. Its home location gives the
+//! record's `default`; every location added with [`ServiceDirectorySource::with_location`] answers
+//! for the region it lies in (`asia-south1` for `in`, `us-east1` for `us`..), so
+//! `try_sibling("k9", Some("IN"))` resolves as it does with Redis records. Of several endpoints
+//! the first by address wins.
+//!
+//! Requests carry the access token of the VM's or pod's service account, read from the metadata
+//! server and renewed before it expires, or a fixed one from
+//! [`ServiceDirectorySource::with_token`]. Lookups hitting the API every ttl can keep a Redis in
+//! front with [`CachedSource`](crate::CachedSource).
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use http_body_util::{BodyExt, Empty};
+use hyper::{body::Bytes, header, Request, StatusCode};
+use serde_derive::Deserialize;
+
+use crate::{https, EndpointSource, Env, FetchFuture, RegionEndpoint, Regions};
+
+/// Service Directory API, unless set with [`ServiceDirectorySource::with_api`]
+pub const SERVICE_DIRECTORY_API: &str = "https://servicedirectory.googleapis.com";
+
+/// Access token of the default service account on GCE, GKE and Cloud Run
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// How long before it expires a token is renewed
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// GCP location prefixes and the region they lie in, the most specific first
+const GCP_LOCATIONS: [(&str, Regions); 7] = [
+    ("asia-south1", Regions::IN),
+    ("asia-south2", Regions::IN),
+    ("asia-southeast1", Regions::SG),
+    ("asia-", Regions::APAC),
+    ("australia-", Regions::APAC),
+    ("europe-", Regions::EU),
+    ("us-", Regions::US),
+];
+
+/// Services of a Service Directory namespace
+#[derive(Debug, Clone)]
+pub struct ServiceDirectorySource {
+    api: String,
+    project: String,
+    location: String,
+    namespace: String,
+    /// region code -> location
+    locations: BTreeMap<String, String>,
+    scheme: String,
+    token: Option<String>,
+    /// Metadata server token and when it expires
+    fetched: Arc<Mutex<Option<(String, Instant)>>>,
+    client: https::HttpsClient<Empty<Bytes>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct EndpointList {
+    #[serde(default)]
+    endpoints: Vec<Endpoint>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Endpoint {
+    #[serde(default)]
+    address: String,
+    #[serde(default)]
+    port: u16,
+}
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl Regions {
+    /// The region a GCP location lies in, `in` for `asia-south1`; `None` for those outside every
+    /// region
+    pub fn from_gcp_location(location: &str) -> Option<Self> {
+        GCP_LOCATIONS
+            .iter()
+            .find(|(prefix, _)| location.starts_with(prefix))
+            .map(|(_, region)| *region)
+    }
+}
+
+impl ServiceDirectorySource {
+    /// Services of `namespace` in `location` of `project`
+    pub fn new(
+        project: impl Into<String>,
+        location: impl Into<String>,
+        namespace: impl Into<String>,
+    ) -> Result<Self> {
+        Ok(Self {
+            api: SERVICE_DIRECTORY_API.to_string(),
+            project: project.into(),
+            location: location.into(),
+            namespace: namespace.into(),
+            locations: BTreeMap::new(),
+            scheme: "http".to_string(),
+            token: None,
+            fetched: Default::default(),
+            client: https::client()?,
+        })
+    }
+
+    /// Also resolves lookups in the region `location` lies in from its endpoints; a location in
+    /// none of [`Regions`] is logged and changes nothing
+    pub fn with_location(self, location: &str) -> Self {
+        match Regions::from_gcp_location(location) {
+            Some(region) => self.with_region(region, location),
+            None => {
+                log_to!(
+                    Refresh,
+                    Warn,
+                    "gcp: location {location} is in no region, ignored"
+                );
+                self
+            }
+        }
+    }
+
+    /// Resolves lookups in `region` from the endpoints in `location`
+    pub fn with_region(mut self, region: Regions, location: impl Into<String>) -> Self {
+        self.locations
+            .insert(region.code().to_owned(), location.into());
+        self
+    }
+
+    /// Sends `token` instead of the service account's from the metadata server
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Scheme of the urls built from endpoints, `http` unless set
+    pub fn with_scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.scheme = scheme.into();
+        self
+    }
+
+    /// Base url of the API, for a private endpoint or a test double
+    pub fn with_api(mut self, api: impl Into<String>) -> Self {
+        self.api = api.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    async fn record(&self, sibling: &str) -> Result<Option<RegionEndpoint>> {
+        let Some(default) = self.endpoint(sibling, &self.location).await? else {
+            return Ok(None);
+        };
+
+        let mut record = RegionEndpoint {
+            default: default.into(),
+            ..Default::default()
+        };
+        for (region, location) in &self.locations {
+            if let Some(url) = self.endpoint(sibling, location).await? {
+                record.regions.insert(region.clone(), url.into());
+            }
+        }
+
+        Ok(Some(record))
+    }
+
+    /// Url of the first endpoint of `service` in `location`, `None` when it has none or doesn't
+    /// exist there
+    async fn endpoint(&self, service: &str, location: &str) -> Result<Option<String>> {
+        let url = format!(
+            "{}/v1/projects/{}/locations/{location}/namespaces/{}/services/{service}/endpoints",
+            self.api, self.project, self.namespace
+        );
+        let token = self.access_token().await?;
+        let Some(body) = self
+            .get(
+                &url,
+                &[(header::AUTHORIZATION.as_str(), &format!("Bearer {token}"))],
+            )
+            .await?
+        else {
+            log_to!(Resolve, Debug, "gcp: sibling[{service}] not in {location}");
+            return Ok(None);
+        };
+
+        let mut endpoints = serde_json::from_slice::<EndpointList>(&body)?.endpoints;
+        endpoints.retain(|ep| !ep.address.is_empty());
+        endpoints.sort_by(|a, b| (&a.address, a.port).cmp(&(&b.address, b.port)));
+        Ok(endpoints.first().map(|ep| match ep.address.contains(':') {
+            true => format!("{}://[{}]:{}", self.scheme, ep.address, ep.port),
+            false => format!("{}://{}:{}", self.scheme, ep.address, ep.port),
+        }))
+    }
+
+    /// The fixed token, or the metadata server's while it's valid for [`TOKEN_MARGIN`]
+    async fn access_token(&self) -> Result<String> {
+        if let Some(token) = &self.token {
+            return Ok(token.clone());
+        }
+        let cached = self
+            .fetched
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if let Some((token, expires)) = cached
+            && Instant::now() + TOKEN_MARGIN < expires
+        {
+            return Ok(token);
+        }
+
+        let Some(body) = self
+            .get(METADATA_TOKEN_URL, &[("Metadata-Flavor", "Google")])
+            .await?
+        else {
+            bail!("no service account token on the metadata server");
+        };
+        let token = serde_json::from_slice::<Token>(&body)?;
+        let expires = Instant::now() + Duration::from_secs(token.expires_in);
+        *self.fetched.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((token.access_token.clone(), expires));
+
+        Ok(token.access_token)
+    }
+
+    /// Body of a GET of `url`, `None` when not found
+    async fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Option<Bytes>> {
+        let mut req = Request::get(url);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let resp = self.client.request(req.body(Empty::new())?).await?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(resp.into_body().collect().await?.to_bytes())),
+            status => bail!("{url} answered {status}"),
+        }
+    }
+}
+
+impl EndpointSource for ServiceDirectorySource {
+    fn fetch<'a>(&'a self, _: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        match pinned {
+            Some(_) => Box::pin(async { Ok(None) }),
+            None => Box::pin(self.record(sibling)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fake_http, Siblings};
+
+    /// A Service Directory answering the endpoint lists in `answers` by path, 404 for the rest
+    async fn fake_api(answers: &'static [(&'static str, &'static str)]) -> Result<String> {
+        fake_http::serve(|request| {
+            let authorized = request.contains("authorization: Bearer t0ken");
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            match answers.iter().find(|(p, _)| *p == path) {
+                Some((_, body)) if authorized => fake_http::respond("200 OK", body),
+                _ => fake_http::respond("404 Not Found", ""),
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn resolves_endpoints_by_location() -> anyhow::Result<()> {
+        let api = fake_api(&[
+            (
+                "/v1/projects/acme/locations/us-east1/namespaces/platform/services/k9/endpoints",
+                r#"{"endpoints":[{"name":"e2","address":"10.0.0.9","port":9000},
+                                 {"name":"e1","address":"10.0.0.5","port":9000}]}"#,
+            ),
+            (
+                "/v1/projects/acme/locations/asia-south1/namespaces/platform/services/k9/endpoints",
+                r#"{"endpoints":[{"name":"e1","address":"10.1.0.5","port":9000}]}"#,
+            ),
+        ])
+        .await?;
+        let source = ServiceDirectorySource::new("acme", "us-east1", "platform")?
+            .with_api(api)
+            .with_token("t0ken")
+            .with_location("asia-south1")
+            .with_location("mars-north1");
+        let sib = Siblings::with_source(source, None).with_env(Env::Dev);
+
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("http://10.0.0.5:9000")
+        );
+        assert_eq!(
+            sib.try_sibling("k9", Some("IND")).await?.as_deref(),
+            Some("http://10.1.0.5:9000")
+        );
+        assert_eq!(sib.try_sibling("matrix", None).await?, None);
+        assert!(matches!(
+            Regions::from_gcp_location("asia-southeast1"),
+            Some(Regions::SG)
+        ));
+
+        Ok(())
+    }
+}
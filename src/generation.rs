@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwap;
+
+use crate::{RegionEndpoint, Siblings, SiblingsError};
+
+/// Immutable set of endpoints loaded in one pass
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub(crate) generation: u64,
+    pub(crate) loaded_at: SystemTime,
+    pub(crate) endpoints: HashMap<String, RegionEndpoint>,
+}
+
+impl Generation {
+    /// [`SiblingsError::Disabled`] while the sibling is disabled
+    pub fn get(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Result<Option<String>, SiblingsError> {
+        match self.endpoints.get(sibling) {
+            Some(ep) if !ep.is_enabled() => Err(SiblingsError::Disabled(sibling.to_owned())),
+            ep => Ok(ep.and_then(|ep| ep.get_in(region))),
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn loaded_at(&self) -> SystemTime {
+        self.loaded_at
+    }
+
+    pub fn contains(&self, sibling: &str) -> bool {
+        self.endpoints.contains_key(sibling)
+    }
+}
+
+/// Whole-set endpoint cache: readers grab the current [`Generation`] without taking any lock,
+/// a background task reloads every sibling and swaps in a new generation when something changed.
+///
+/// The refresh task stops once the last clone of the cache is dropped.
+#[derive(Clone)]
+pub struct GenerationalCache {
+    pub(crate) current: Arc<ArcSwap<Generation>>,
+}
+
+impl GenerationalCache {
+    /// The generation in use right now. Hold on to it for the duration of a request to get a
+    /// consistent view across many lookups.
+    pub fn load(&self) -> Arc<Generation> {
+        self.current.load_full()
+    }
+
+    pub fn get(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Result<Option<String>, SiblingsError> {
+        self.current.load().get(sibling, region)
+    }
+}
+
+impl Siblings {
+    /// Loads `siblings` as a single [`Generation`] and keeps it fresh every `refresh_every`.
+    pub async fn generational(
+        &self,
+        siblings: &[&str],
+        refresh_every: Duration,
+    ) -> GenerationalCache {
+        let names = siblings.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let first = self.load_generation(&names, None).await;
+        let current = Arc::new(ArcSwap::from_pointee(first));
+        let cache = GenerationalCache {
+            current: current.clone(),
+        };
+
+        let slf = self.clone();
+        let weak = Arc::downgrade(&current);
+        drop(current);
+        tokio::spawn(async move { slf.refresh_generations(weak, names, refresh_every).await });
+
+        cache
+    }
+
+    async fn refresh_generations(
+        &self,
+        current: Weak<ArcSwap<Generation>>,
+        names: Vec<String>,
+        refresh_every: Duration,
+    ) {
+        let mut ticker = tokio::time::interval(refresh_every);
+        // the first tick completes immediately and the first generation is already loaded
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            let Some(current) = current.upgrade() else {
+                log_to!(
+                    Refresh,
+                    Info,
+                    "generational: cache dropped, stopping refresh"
+                );
+                return;
+            };
+
+            let prev = current.load_full();
+            let next = self.load_generation(&names, Some(&prev)).await;
+            if next.endpoints != prev.endpoints {
+                log_to!(
+                    Refresh,
+                    Info,
+                    "generational: swapping in generation[{}]",
+                    next.generation
+                );
+                current.store(Arc::new(next));
+            }
+        }
+    }
+
+    /// Fetches every name in one round trip; a failed fetch keeps whatever `prev` had for that
+    /// sibling
+    pub(crate) async fn load_generation(
+        &self,
+        names: &[String],
+        prev: Option<&Generation>,
+    ) -> Generation {
+        let mut endpoints = HashMap::with_capacity(names.len());
+        let mut fetched = match self.fetch_each(names).await {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                log_to!(Refresh, Warn, "generational: fetching siblings failed: {e}");
+                HashMap::new()
+            }
+        };
+
+        for name in names {
+            match fetched.remove(name) {
+                Some(Ok(Some(ep))) => {
+                    endpoints.insert(name.clone(), ep);
+                }
+                Some(Ok(None)) => log_to!(
+                    Refresh,
+                    Warn,
+                    "generational: endpoint for sibling[{name}] not found"
+                ),
+                failed => {
+                    if let Some(Err(e)) = failed {
+                        log_to!(
+                            Refresh,
+                            Warn,
+                            "generational: fetching sibling[{name}] failed: {e}"
+                        );
+                    }
+                    if let Some(ep) = prev.and_then(|p| p.endpoints.get(name)) {
+                        endpoints.insert(name.clone(), ep.clone());
+                    }
+                }
+            }
+        }
+
+        Generation {
+            generation: prev.map_or(1, |p| p.generation + 1),
+            loaded_at: SystemTime::now(),
+            endpoints,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_siblings_fail() -> anyhow::Result<()> {
+        let generation = Generation {
+            generation: 1,
+            loaded_at: SystemTime::UNIX_EPOCH,
+            endpoints: HashMap::from([
+                (
+                    "k9".to_string(),
+                    Siblings::deserialize(br#"{"default":"https://k9"}"#.to_vec())?,
+                ),
+                (
+                    "kyc".to_string(),
+                    Siblings::deserialize(
+                        br#"{"default":"https://kyc","enabled":false}"#.to_vec(),
+                    )?,
+                ),
+            ]),
+        };
+
+        assert_eq!(generation.get("k9", None)?.as_deref(), Some("https://k9"));
+        assert!(matches!(
+            generation.get("kyc", None),
+            Err(SiblingsError::Disabled(_))
+        ));
+        assert_eq!(generation.get("pdf", None)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+//! Platform-wide sibling health, probed once and shared through Redis.
+//!
+//! [`Siblings::check_health`] probes the reachability of each sibling: a TCP connect to the host
+//! of its health url within [`PROBE_TIMEOUT`]. No request is sent, so [`HealthStatus::Up`] means
+//! the host accepts connections, not that its health endpoint answers 2xx. Whoever runs the probes (a cron, the resolution server, `siblings-cli
+//! health --publish`) writes the snapshot to `siblings:health:{env}` with
+//! [`Siblings::publish_health`]; dashboards and lightweight consumers read it with
+//! [`Siblings::published_health`] instead of probing every sibling themselves. The key expires
+//! after [`HEALTH_TTL`], so a prober that stopped leaves no health behind rather than stale health.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use futures_util::future::join_all;
+use serde_derive::{Deserialize, Serialize};
+use tokio::{net::TcpStream, time::timeout};
+
+use crate::{cache, Siblings, SiblingsError};
+
+/// Longest a single probe may take
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a published snapshot stays readable
+pub const HEALTH_TTL: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// The host accepted a connection
+    Up,
+    /// The host refused or didn't answer in time
+    Down,
+    /// Not probed: no record, or it couldn't be resolved
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SiblingHealth {
+    pub status: HealthStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub detail: String,
+    pub took_ms: u64,
+}
+
+/// Outcome of [`Siblings::check_health`], as published to Redis
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HealthSnapshot {
+    pub env: String,
+    /// Unix seconds the probes started at
+    pub taken_at: u64,
+    pub siblings: BTreeMap<String, SiblingHealth>,
+}
+
+impl HealthSnapshot {
+    /// Siblings whose probe failed
+    pub fn down(&self) -> Vec<&str> {
+        self.siblings
+            .iter()
+            .filter(|(_, h)| h.status == HealthStatus::Down)
+            .map(|(sibling, _)| sibling.as_str())
+            .collect()
+    }
+}
+
+impl Siblings {
+    /// Probes the reachability of every one of `siblings` at once
+    pub async fn check_health(&self, siblings: &[&str]) -> HealthSnapshot {
+        let taken_at = self.unix_now();
+        let probes = siblings.iter().map(|sibling| async move {
+            (sibling.to_string(), self.probe_reachability(sibling).await)
+        });
+
+        HealthSnapshot {
+            env: self.env.name().to_owned(),
+            taken_at,
+            siblings: join_all(probes).await.into_iter().collect(),
+        }
+    }
+
+    /// Whether the host of the health url of `sibling` accepts a connection
+    async fn probe_reachability(&self, sibling: &str) -> SiblingHealth {
+        let started = Instant::now();
+        let unknown = |detail: String| SiblingHealth {
+            status: HealthStatus::Unknown,
+            url: None,
+            detail,
+            took_ms: 0,
+        };
+        let url = match self.health_url(sibling, None).await {
+            Ok(Some(url)) => url,
+            Ok(None) => return unknown("no record".to_string()),
+            Err(e) => return unknown(e.to_string()),
+        };
+
+        let (status, detail) = match address(&url) {
+            None => (HealthStatus::Down, "no host to connect to".to_string()),
+            Some((host, port)) => {
+                match timeout(PROBE_TIMEOUT, TcpStream::connect((host.as_str(), port))).await {
+                    Ok(Ok(_)) => (
+                        HealthStatus::Up,
+                        format!("{host}:{port} accepts connections"),
+                    ),
+                    Ok(Err(e)) => (HealthStatus::Down, format!("{host}:{port}: {e}")),
+                    Err(_) => (
+                        HealthStatus::Down,
+                        format!("{host}:{port}: no answer in {PROBE_TIMEOUT:?}"),
+                    ),
+                }
+            }
+        };
+        if status == HealthStatus::Down {
+            log_to!(Health, Warn, "health: sibling[{sibling}] down, {detail}");
+        }
+
+        SiblingHealth {
+            status,
+            url: Some(url),
+            detail,
+            took_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+
+    /// Writes `snapshot` to `siblings:health:{env}` for [`HEALTH_TTL`]
+    pub async fn publish_health(&self, snapshot: &HealthSnapshot) -> Result<(), SiblingsError> {
+        let key = self.keys.health(&self.env);
+        let conn = self.backend.redis("writes")?;
+        let data = serde_json::to_vec(snapshot)?;
+        cache::set_ex(conn, &key, &data, HEALTH_TTL)
+            .await
+            .map_err(SiblingsError::unreachable)?;
+        log_to!(
+            Health,
+            Info,
+            "health: published {} siblings to {key}, {} down",
+            snapshot.siblings.len(),
+            snapshot.down().len()
+        );
+
+        Ok(())
+    }
+
+    /// The snapshot last published for this env, `None` when none was within [`HEALTH_TTL`]
+    pub async fn published_health(&self) -> Result<Option<HealthSnapshot>, SiblingsError> {
+        let data = self.get_cache_key(&self.keys.health(&self.env)).await?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+}
+
+/// Host and port of `url`, the scheme's default port when none is given
+fn address(url: &str) -> Option<(String, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority.rsplit('@').next()?;
+    let default = match scheme.to_ascii_lowercase().as_str() {
+        "https" | "wss" => 443,
+        "http" | "ws" => 80,
+        _ => return None,
+    };
+
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+            (host, port.parse().ok()?)
+        }
+        _ => (authority, default),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    (!host.is_empty()).then(|| (host.to_owned(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn addresses_of_urls() {
+        assert_eq!(
+            address("https://k9.xambit.io/health"),
+            Some(("k9.xambit.io".into(), 443))
+        );
+        assert_eq!(
+            address("http://user@10.0.0.5:9000?x=1"),
+            Some(("10.0.0.5".into(), 9000))
+        );
+        assert_eq!(address("http://[::1]:8080/"), Some(("::1".into(), 8080)));
+        assert_eq!(address("grpc://k9"), None);
+        assert_eq!(address("k9"), None);
+    }
+
+    #[tokio::test]
+    async fn probes_what_accepts_connections() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let up = listener.local_addr()?.port();
+        let closed = TcpListener::bind("127.0.0.1:0").await?;
+        let down = closed.local_addr()?.port();
+        drop(closed);
+
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        for (sibling, port) in [("k9", up), ("matrix", down)] {
+            let ep = Siblings::deserialize(
+                format!(r#"{{"default":"http://127.0.0.1:{port}"}}"#).into_bytes(),
+            )?;
+            sib.set_endpoint(sibling, ep);
+            crate::update(&sib.endpoints, |e| {
+                e.descriptors.insert(sibling.to_owned(), Default::default());
+            });
+        }
+
+        let snapshot = sib.check_health(&["k9", "matrix"]).await;
+        assert_eq!(snapshot.siblings["k9"].status, HealthStatus::Up);
+        assert_eq!(snapshot.siblings["matrix"].status, HealthStatus::Down);
+        assert_eq!(snapshot.down(), ["matrix"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! Records served by an HTTP config service, behind the `http-source` feature.
+//!
+//! [`HttpSource`] is an [`EndpointSource`] reading a siblings file (the `siblings.json` format,
+//! `_defaults` and `_env` included) from a url, for environments reaching HTTPS but not Redis.
+//! Each env is answered with its records from the same file. The file is fetched once and
+//! revalidated at most every [`HttpSource::with_revalidate`] with `If-None-Match`, so an unchanged
+//! file costs a `304` and no parsing. A failed revalidation keeps serving the last file until the
+//! next period; lookups never wait on another's request.
+//! [`Siblings::spawn_refresher`](crate::Siblings::spawn_refresher) or a ttl keeps lookups
+//! revalidating in the background.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use http_body_util::{BodyExt, Empty};
+use hyper::{body::Bytes, header, Request, StatusCode};
+
+use crate::{https, parse_siblings_file, EndpointSource, Env, FetchFuture, RegionEndpoint};
+
+/// Least time between two fetches of the file, unless set with [`HttpSource::with_revalidate`]
+pub const DEFAULT_REVALIDATE: Duration = Duration::from_secs(30);
+
+/// The siblings file at a url, as last fetched
+pub struct HttpSource {
+    url: String,
+    headers: Vec<(String, String)>,
+    revalidate: Duration,
+    file: Mutex<Fetched>,
+    client: https::HttpsClient<Empty<Bytes>>,
+}
+
+#[derive(Default)]
+struct Fetched {
+    etag: Option<String>,
+    /// When the file was fetched or last revalidated, successfully or not; `None` until fetched
+    at: Option<Instant>,
+    body: String,
+    /// env -> records of `body`, parsed on the first lookup in the env
+    records: HashMap<String, HashMap<String, RegionEndpoint>>,
+}
+
+impl HttpSource {
+    /// The siblings file at `url`
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            url: url.into(),
+            headers: Vec::new(),
+            revalidate: DEFAULT_REVALIDATE,
+            file: Default::default(),
+            client: https::client()?,
+        })
+    }
+
+    /// Sends `name: value` with every request, e.g. an `Authorization` header
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Least time between two fetches of the file
+    pub fn with_revalidate(mut self, every: Duration) -> Self {
+        self.revalidate = every;
+        self
+    }
+
+    fn file(&self) -> MutexGuard<'_, Fetched> {
+        self.file.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn record(&self, env: &Env, sibling: &str) -> Result<Option<RegionEndpoint>> {
+        let (due, etag) = {
+            let mut file = self.file();
+            let due = file.at.is_none_or(|at| at.elapsed() >= self.revalidate);
+            // stamped before asking, so lookups meanwhile, and after a failure, keep the file
+            // until the next period instead of asking again
+            if due && file.at.is_some() {
+                file.at = Some(Instant::now());
+            }
+            (due, file.etag.clone())
+        };
+
+        if due {
+            match self.revalidated(env, etag).await {
+                Ok(fetched) => {
+                    let mut file = self.file();
+                    if let Some(fetched) = fetched {
+                        *file = fetched;
+                    }
+                    file.at = Some(Instant::now());
+                }
+                // nothing to serve yet, the lookup fails like a Redis read would
+                Err(e) if self.file().at.is_none() => return Err(e),
+                Err(e) => log_to!(
+                    Refresh,
+                    Warn,
+                    "http: {} kept, revalidating failed: {e:#}",
+                    self.url
+                ),
+            }
+        }
+
+        let mut file = self.file();
+        if !file.records.contains_key(env.name()) {
+            let records = parse_siblings_file(&file.body, env)?;
+            file.records.insert(env.name().to_owned(), records);
+        }
+
+        Ok(file.records[env.name()].get(sibling).cloned())
+    }
+
+    /// The file, unless the service answers it's unchanged since `etag`. It must parse for `env`
+    /// to replace the last one.
+    async fn revalidated(&self, env: &Env, etag: Option<String>) -> Result<Option<Fetched>> {
+        let mut req = Request::get(&self.url);
+        for (name, value) in &self.headers {
+            req = req.header(name, value);
+        }
+        if let Some(etag) = &etag {
+            req = req.header(header::IF_NONE_MATCH, etag);
+        }
+
+        let resp = self.client.request(req.body(Empty::new())?).await?;
+        match resp.status() {
+            StatusCode::NOT_MODIFIED => return Ok(None),
+            status if status.is_success() => {}
+            status => bail!("{} answered {status}", self.url),
+        }
+        let etag = resp
+            .headers()
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+        let body = String::from_utf8(resp.into_body().collect().await?.to_bytes().to_vec())?;
+
+        let records = parse_siblings_file(&body, env)?;
+        log_to!(
+            Refresh,
+            Info,
+            "http: {} siblings from {}, etag {etag:?}",
+            records.len(),
+            self.url
+        );
+
+        Ok(Some(Fetched {
+            etag,
+            at: None,
+            body,
+            records: HashMap::from([(env.name().to_owned(), records)]),
+        }))
+    }
+}
+
+impl EndpointSource for HttpSource {
+    fn fetch<'a>(&'a self, env: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        match pinned {
+            // the file only holds live records
+            Some(_) => Box::pin(async { Ok(None) }),
+            None => Box::pin(self.record(env, sibling)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::{fake_http, Siblings};
+
+    #[tokio::test]
+    async fn revalidates_with_the_etag() -> anyhow::Result<()> {
+        let (full, conditional) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let counts = (full.clone(), conditional.clone());
+        let base = fake_http::serve(move |request| {
+            match request.to_lowercase().contains("if-none-match: \"v1\"") {
+                true => {
+                    counts.1.fetch_add(1, Ordering::SeqCst);
+                    "HTTP/1.1 304 Not Modified\r\netag: \"v1\"\r\n\r\n".to_string()
+                }
+                false => {
+                    counts.0.fetch_add(1, Ordering::SeqCst);
+                    let body = r#"{"k9":{"default":"https://k9.prod","_env":{"dev":{"default":"https://k9.dev"}}}}"#;
+                    format!(
+                        "HTTP/1.1 200 OK\r\netag: \"v1\"\r\ncontent-length: {}\r\n\r\n{body}",
+                        body.len()
+                    )
+                }
+            }
+        })
+        .await?;
+
+        let source =
+            HttpSource::new(format!("{base}/siblings.json"))?.with_revalidate(Duration::ZERO);
+        let sib = Siblings::with_source(source, None)
+            .with_env(Env::Dev)
+            .with_prod_fallback(true);
+
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("https://k9.dev")
+        );
+        sib.flush().await;
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("https://k9.dev")
+        );
+        assert_eq!(sib.try_sibling("matrix", None).await?, None);
+        assert_eq!(full.load(Ordering::SeqCst), 1);
+        assert!(conditional.load(Ordering::SeqCst) >= 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_revalidations_wait_a_period() -> anyhow::Result<()> {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let count = requests.clone();
+        let base = fake_http::serve(move |_| match count.fetch_add(1, Ordering::SeqCst) {
+            0 => fake_http::respond("200 OK", r#"{"k9":{"default":"https://k9"}}"#),
+            _ => fake_http::respond("503 Service Unavailable", ""),
+        })
+        .await?;
+
+        let source = HttpSource::new(base)?.with_revalidate(Duration::from_millis(50));
+        assert!(source.record(&Env::Dev, "k9").await?.is_some());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        for _ in 0..3 {
+            assert!(source.record(&Env::Dev, "k9").await?.is_some());
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+}
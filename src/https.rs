@@ -0,0 +1,27 @@
+//! The HTTPS client of the cloud backends, `aws` and `gcp`.
+
+use anyhow::Result;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+
+/// Client reaching both `https://` and `http://` urls
+pub(crate) type HttpsClient<B> = Client<HttpsConnector<HttpConnector>, B>;
+
+/// A client trusting the platform's roots. The crypto provider is picked here rather than left
+/// to rustls, which can't pick one when the `db` crate pulls in another.
+pub(crate) fn client<B>() -> Result<HttpsClient<B>>
+where
+    B: hyper::body::Body + Send,
+    B::Data: Send,
+{
+    let connector = HttpsConnectorBuilder::new()
+        .with_provider_and_native_roots(rustls::crypto::ring::default_provider())?
+        .https_or_http()
+        .enable_http1()
+        .build();
+
+    Ok(Client::builder(TokioExecutor::new()).build(connector))
+}
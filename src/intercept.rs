@@ -0,0 +1,146 @@
+//! Interceptors run on every url lookup.
+//!
+//! An [`Interceptor`] added with [`Siblings::with_interceptor`] sees each lookup twice: before the
+//! record is read, where it may point the lookup at another sibling or region, and after, where it
+//! may replace the url. Either step can veto the lookup with an error, e.g.
+//! [`SiblingsError::Vetoed`]. `before` runs in the order interceptors were added and `after` in
+//! reverse, so the first one added wraps all the others.
+//!
+//! They run on [`Siblings::try_sibling`] and everything built on it, on
+//! [`Siblings::try_sibling_with`], [`Siblings::resolve`] and [`Siblings::peek`]. Records read
+//! whole with [`Siblings::endpoint`] are not intercepted.
+
+use std::{borrow::Cow, sync::Arc};
+
+use crate::{Siblings, SiblingsError};
+
+/// A lookup as interceptors see it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution<'a> {
+    pub sibling: Cow<'a, str>,
+    /// Region code, after the default region applied
+    pub region: Option<Cow<'a, str>>,
+}
+
+pub trait Interceptor: Send + Sync {
+    /// Runs before the record is read; may rewrite `resolution` or veto the lookup
+    fn before(&self, _resolution: &mut Resolution<'_>) -> Result<(), SiblingsError> {
+        Ok(())
+    }
+
+    /// Runs on the url found, `None` when nothing is published; may replace it or veto the lookup
+    fn after(
+        &self,
+        _resolution: &Resolution<'_>,
+        _url: &mut Option<String>,
+    ) -> Result<(), SiblingsError> {
+        Ok(())
+    }
+}
+
+impl Siblings {
+    /// Runs `interceptor` on every url lookup, after the interceptors added before it
+    pub fn with_interceptor(mut self, interceptor: impl Interceptor + 'static) -> Self {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// `sibling` and `region` as the interceptors rewrote them
+    pub(crate) fn before_lookup<'a>(
+        &'a self,
+        sibling: &'a str,
+        region: Option<&'a str>,
+    ) -> Result<Resolution<'a>, SiblingsError> {
+        let mut resolution = Resolution {
+            sibling: Cow::Borrowed(sibling),
+            region: self.region(region).map(Cow::Borrowed),
+        };
+        for interceptor in &self.interceptors {
+            interceptor.before(&mut resolution)?;
+        }
+
+        Ok(resolution)
+    }
+
+    /// `url` as the interceptors rewrote it
+    pub(crate) fn after_lookup(
+        &self,
+        resolution: &Resolution<'_>,
+        mut url: Option<String>,
+    ) -> Result<Option<String>, SiblingsError> {
+        for interceptor in self.interceptors.iter().rev() {
+            interceptor.after(resolution, &mut url)?;
+        }
+
+        Ok(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RegionEndpoint;
+
+    struct AllowList(&'static [&'static str]);
+
+    impl Interceptor for AllowList {
+        fn before(&self, resolution: &mut Resolution<'_>) -> Result<(), SiblingsError> {
+            if self.0.contains(&&*resolution.sibling) {
+                return Ok(());
+            }
+            Err(SiblingsError::Vetoed {
+                sibling: resolution.sibling.to_string(),
+                reason: "not on the allow-list".to_string(),
+            })
+        }
+    }
+
+    /// Sends `k9` lookups to the `k9-acme` tenant and tags urls with the region looked up
+    struct Tenant;
+
+    impl Interceptor for Tenant {
+        fn before(&self, resolution: &mut Resolution<'_>) -> Result<(), SiblingsError> {
+            if resolution.sibling == "k9" {
+                resolution.sibling = Cow::Owned("k9-acme".to_string());
+            }
+            Ok(())
+        }
+
+        fn after(
+            &self,
+            resolution: &Resolution<'_>,
+            url: &mut Option<String>,
+        ) -> Result<(), SiblingsError> {
+            if let (Some(url), Some(region)) = (url, &resolution.region) {
+                url.push_str(&format!("?region={region}"));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn interceptors_rewrite_and_veto() -> anyhow::Result<()> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None)
+            .with_interceptor(AllowList(&["k9"]))
+            .with_interceptor(Tenant);
+        crate::update(&sib.endpoints, |e| {
+            let ep = RegionEndpoint {
+                default: "https://acme.k9".into(),
+                ..Default::default()
+            };
+            e.insert("k9-acme", ep);
+        });
+
+        assert_eq!(
+            sib.try_sibling("k9", Some("in")).await?.as_deref(),
+            Some("https://acme.k9?region=in")
+        );
+        assert_eq!(sib.peek("k9", None).as_deref(), Some("https://acme.k9"));
+        assert!(matches!(
+            sib.try_sibling("matrix", None).await,
+            Err(SiblingsError::Vetoed { .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+//! How cache keys are laid out, so several teams can share one Redis.
+//!
+//! A key is `[namespace-][env prefix-]{kind}-{name}` where kind is `ep` for endpoint records, `wh`
+//! for webhook urls, `svc` for service descriptors, `usage` for usage reports and `seen` for the
+//! record versions consumers hold. The defaults give the historical layout:
+//! `ep-k9` in prod, `dev-ep-k9` in dev, no namespace.
+//!
+//! With [`Layout::Hash`] the live endpoint records of an env are instead fields of one hash,
+//! `[namespace-]siblings:{env}`; archived versions and every other kind stay plain keys.
+
+use std::{collections::HashMap, env};
+
+use crate::{Env, SiblingsError};
+
+/// Where live endpoint records are stored; reads look in both, the configured one first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// A key per sibling, `ep-{sibling}`
+    #[default]
+    Keys,
+    /// One hash per env, `siblings:{env}`, with a field per sibling
+    Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyScheme {
+    layout: Layout,
+    namespace: Option<String>,
+    separator: String,
+    /// env name -> prefix, overriding the default (none in prod, the env name elsewhere)
+    env_prefixes: HashMap<String, String>,
+    endpoint: String,
+    webhook: String,
+    descriptor: String,
+    usage: String,
+    seen: String,
+}
+
+impl Default for KeyScheme {
+    fn default() -> Self {
+        Self {
+            layout: Layout::Keys,
+            namespace: None,
+            separator: "-".to_string(),
+            env_prefixes: HashMap::new(),
+            endpoint: "ep".to_string(),
+            webhook: "wh".to_string(),
+            descriptor: "svc".to_string(),
+            usage: "usage".to_string(),
+            seen: "seen".to_string(),
+        }
+    }
+}
+
+impl KeyScheme {
+    /// The default scheme changed by `X_SIBLINGS_NAMESPACE`, `X_SIBLINGS_SEPARATOR`,
+    /// `X_SIBLINGS_LAYOUT` (`keys` or `hash`) and `X_SIBLINGS_ENV_PREFIXES` (`staging=stg,dev=`),
+    /// for binaries sharing a Redis laid out by a library user's scheme
+    pub fn from_env() -> Result<Self, SiblingsError> {
+        Self::from_vars(|var| env::var(var).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, SiblingsError> {
+        let mut keys = Self::default();
+        if let Some(namespace) = var("X_SIBLINGS_NAMESPACE") {
+            keys = keys.with_namespace(namespace);
+        }
+        if let Some(separator) = var("X_SIBLINGS_SEPARATOR") {
+            keys = keys.with_separator(separator);
+        }
+        if let Some(layout) = var("X_SIBLINGS_LAYOUT") {
+            keys = keys.with_layout(match layout.trim().to_lowercase().as_str() {
+                "keys" => Layout::Keys,
+                "hash" => Layout::Hash,
+                _ => {
+                    return Err(SiblingsError::InvalidKnob {
+                        knob: "X_SIBLINGS_LAYOUT".to_string(),
+                        value: layout,
+                        expected: "keys or hash",
+                    })
+                }
+            });
+        }
+        for pair in var("X_SIBLINGS_ENV_PREFIXES")
+            .iter()
+            .flat_map(|p| p.split(','))
+        {
+            let Some((env, prefix)) = pair.split_once('=') else {
+                return Err(SiblingsError::InvalidKnob {
+                    knob: "X_SIBLINGS_ENV_PREFIXES".to_string(),
+                    value: pair.to_string(),
+                    expected: "env=prefix pairs",
+                });
+            };
+            keys = keys.with_env_prefix(&Env::from_name(env), prefix.trim());
+        }
+
+        Ok(keys)
+    }
+
+    /// Prefixes every key with `namespace`, e.g. the owning team
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    pub fn with_separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Uses `prefix` for the keys of `env`; an empty prefix leaves them unprefixed like prod's
+    pub fn with_env_prefix(mut self, env: &Env, prefix: impl Into<String>) -> Self {
+        self.env_prefixes
+            .insert(env.name().to_string(), prefix.into());
+        self
+    }
+
+    /// Stores live endpoint records per `layout`
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Layouts in the order reads look in them
+    pub(crate) fn read_order(&self) -> [Layout; 2] {
+        match self.layout {
+            Layout::Keys => [Layout::Keys, Layout::Hash],
+            Layout::Hash => [Layout::Hash, Layout::Keys],
+        }
+    }
+
+    /// The hash holding every live endpoint record of `env` in the hash layout
+    pub fn hash(&self, env: &Env) -> String {
+        self.namespaced(&format!("siblings:{}", env.name()))
+    }
+
+    /// The key of the health snapshot of `env`
+    pub fn health(&self, env: &Env) -> String {
+        self.namespaced(&format!("siblings:health:{}", env.name()))
+    }
+
+    /// Channel every publish and prune is announced on, for all envs
+    pub fn channel(&self) -> String {
+        self.namespaced("siblings:updated")
+    }
+
+    fn namespaced(&self, name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{namespace}{}{name}", self.separator),
+            None => name.to_string(),
+        }
+    }
+
+    /// Kind segment of endpoint records, `ep` by default
+    pub fn with_endpoint_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.endpoint = prefix.into();
+        self
+    }
+
+    fn env_prefix<'a>(&'a self, env: &'a Env) -> &'a str {
+        match self.env_prefixes.get(env.name()) {
+            Some(prefix) => prefix,
+            None if env.is_prod() => "",
+            None => env.name(),
+        }
+    }
+
+    /// Namespace and env part of every key of `env`, separator included
+    pub fn prefix(&self, env: &Env) -> String {
+        [
+            self.namespace.as_deref().unwrap_or(""),
+            self.env_prefix(env),
+        ]
+        .into_iter()
+        .filter(|p| !p.is_empty())
+        .map(|p| format!("{p}{}", self.separator))
+        .collect()
+    }
+
+    /// `key` as stored for `env`
+    pub fn key(&self, env: &Env, key: &str) -> String {
+        format!("{}{key}", self.prefix(env))
+    }
+
+    /// Live endpoint record of `sibling`, without the env part
+    pub fn endpoint(&self, sibling: &str) -> String {
+        format!("{}{}{sibling}", self.endpoint, self.separator)
+    }
+
+    /// Archived `version` of the endpoint record of `sibling`
+    pub fn archive(&self, sibling: &str, version: u64) -> String {
+        format!("{}@{version}", self.endpoint(sibling))
+    }
+
+    pub fn webhook(&self, sibling: &str, hook: &str) -> String {
+        let sep = &self.separator;
+        format!("{}{sep}{sibling}{sep}{hook}", self.webhook)
+    }
+
+    pub fn descriptor(&self, sibling: &str) -> String {
+        format!("{}{}{sibling}", self.descriptor, self.separator)
+    }
+
+    pub fn usage(&self, sibling: &str) -> String {
+        format!("{}{}{sibling}", self.usage, self.separator)
+    }
+
+    pub fn seen(&self, sibling: &str) -> String {
+        format!("{}{}{sibling}", self.seen, self.separator)
+    }
+
+    /// Whether `key` (with any namespace and env prefix) is an endpoint, webhook or descriptor
+    /// record, the only keys the sidecar agent proxies
+    pub fn is_record(&self, key: &str) -> bool {
+        key.split(self.separator.as_str())
+            .any(|part| part == self.endpoint || part == self.webhook || part == self.descriptor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_scheme_keeps_the_historical_keys() {
+        let keys = KeyScheme::default();
+
+        assert_eq!(keys.key(&Env::Prod, &keys.endpoint("k9")), "ep-k9");
+        assert_eq!(keys.key(&Env::Dev, &keys.endpoint("k9")), "dev-ep-k9");
+        assert_eq!(
+            keys.key(&Env::Staging, &keys.archive("k9", 3)),
+            "staging-ep-k9@3"
+        );
+        assert_eq!(
+            keys.key(&Env::Prod, &keys.webhook("xchange", "cb")),
+            "wh-xchange-cb"
+        );
+        assert!(keys.is_record("dev-wh-xchange-cb"));
+        assert!(!keys.is_record("dev-usage-k9"));
+    }
+
+    #[test]
+    fn namespaced_scheme() {
+        let keys = KeyScheme::default()
+            .with_namespace("risk")
+            .with_separator(":")
+            .with_env_prefix(&Env::Dev, "d");
+
+        assert_eq!(keys.key(&Env::Prod, &keys.endpoint("k9")), "risk:ep:k9");
+        assert_eq!(keys.key(&Env::Dev, &keys.endpoint("k9")), "risk:d:ep:k9");
+        assert_eq!(keys.prefix(&Env::Dev), "risk:d:");
+        assert_eq!(keys.hash(&Env::Prod), "risk:siblings:prod");
+        assert_eq!(keys.channel(), "risk:siblings:updated");
+        assert_eq!(KeyScheme::default().hash(&Env::Dev), "siblings:dev");
+    }
+
+    #[test]
+    fn scheme_from_vars() -> Result<(), SiblingsError> {
+        let vars = HashMap::from([
+            ("X_SIBLINGS_NAMESPACE", "risk"),
+            ("X_SIBLINGS_SEPARATOR", ":"),
+            ("X_SIBLINGS_LAYOUT", "hash"),
+            ("X_SIBLINGS_ENV_PREFIXES", "dev=d, staging="),
+        ]);
+        let keys = KeyScheme::from_vars(|var| vars.get(var).map(|v| v.to_string()))?;
+
+        assert_eq!(keys.layout(), Layout::Hash);
+        assert_eq!(keys.key(&Env::Dev, &keys.endpoint("k9")), "risk:d:ep:k9");
+        assert_eq!(keys.prefix(&Env::Staging), "risk:");
+        assert!(KeyScheme::from_vars(|_| Some("tree".to_string())).is_err());
+        Ok(())
+    }
+}
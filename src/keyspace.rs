@@ -0,0 +1,233 @@
+//! Invalidation driven by Redis notifications.
+//!
+//! [`Siblings::watch_keyspace`] subscribes to the notifications of the live endpoint records of
+//! this env and refreshes the record in memory as soon as the loader writes or deletes its key, so
+//! changes reach consumers in seconds without a flush. Siblings not in memory are left alone.
+//! In the hash layout any change to the hash refreshes every record in memory.
+//!
+//! Redis only sends these with keyspace events on, e.g. `CONFIG SET notify-keyspace-events Kgh$x`;
+//! without them the subscription is silent and records stay until flushed, refreshed or expired.
+//! Where they can't be turned on, [`Siblings::subscribe_updates`] follows the `siblings:updated`
+//! channel every publish and prune announces on instead.
+
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use tokio::task::JoinHandle;
+
+use crate::{ChangeEvent, Siblings, SiblingsError};
+
+/// Wait before subscribing again after the subscription connection dropped
+pub(crate) const RESUBSCRIBE_AFTER: Duration = Duration::from_secs(5);
+
+/// What a subscription listens to
+#[derive(Debug, Clone, Copy)]
+enum Feed {
+    /// Keyspace notifications of the record keys, the key in the channel name
+    Keyspace,
+    /// `siblings:updated`, a [`ChangeEvent`] as JSON in the payload
+    Announcements,
+}
+
+impl Siblings {
+    /// Subscribes through the Redis at `url`, the one the loader writes to, and refreshes records
+    /// in memory as their keys change. A dropped subscription is retried every
+    /// [`RESUBSCRIBE_AFTER`] and every record refreshed once it's back, to catch missed changes.
+    pub async fn watch_keyspace(&self, url: &str) -> Result<JoinHandle<()>, SiblingsError> {
+        let client =
+            redis::Client::open(url).map_err(|e| SiblingsError::RedisUnreachable(e.into()))?;
+        let patterns = self.keyspace_patterns(client.get_connection_info().redis.db);
+        self.follow(client, patterns, Feed::Keyspace).await
+    }
+
+    /// [`Self::watch_keyspace`] for Redis setups with keyspace notifications off: follows the
+    /// changes [`Self::publish`] and [`loader::prune`](crate::loader::prune) announce on
+    /// `siblings:updated` and refreshes those of this env in memory
+    pub async fn subscribe_updates(&self, url: &str) -> Result<JoinHandle<()>, SiblingsError> {
+        let client =
+            redis::Client::open(url).map_err(|e| SiblingsError::RedisUnreachable(e.into()))?;
+        self.follow(client, vec![self.keys.channel()], Feed::Announcements)
+            .await
+    }
+
+    async fn follow(
+        &self,
+        client: redis::Client,
+        patterns: Vec<String>,
+        feed: Feed,
+    ) -> Result<JoinHandle<()>, SiblingsError> {
+        let mut pubsub = subscribe(&client, &patterns).await?;
+        log_to!(Refresh, Info, "keyspace: watching {patterns:?}");
+
+        let slf = self.clone();
+        Ok(tokio::spawn(async move {
+            loop {
+                let mut messages = pubsub.into_on_message();
+                while let Some(msg) = messages.next().await {
+                    match feed {
+                        Feed::Keyspace => {
+                            let key = msg
+                                .get_channel_name()
+                                .split_once("__:")
+                                .map_or("", |(_, key)| key);
+                            slf.keyspace_event(key).await;
+                        }
+                        Feed::Announcements => {
+                            match serde_json::from_slice(msg.get_payload_bytes()) {
+                                Ok(event) => slf.announced(event).await,
+                                Err(e) => {
+                                    log_to!(Refresh, Warn, "keyspace: unreadable announcement: {e}")
+                                }
+                            }
+                        }
+                    }
+                }
+
+                log_to!(
+                    Refresh,
+                    Warn,
+                    "keyspace: subscription dropped, resubscribing"
+                );
+                pubsub = loop {
+                    tokio::time::sleep(RESUBSCRIBE_AFTER).await;
+                    match subscribe(&client, &patterns).await {
+                        Ok(pubsub) => break pubsub,
+                        Err(e) => log_to!(Refresh, Warn, "keyspace: resubscribing failed: {e}"),
+                    }
+                };
+                let cached = slf.cached_siblings().await;
+                slf.refresh_each(&cached).await;
+            }
+        }))
+    }
+
+    /// Channels of the live endpoint records of this env in database `db`, in both layouts
+    fn keyspace_patterns(&self, db: i64) -> Vec<String> {
+        vec![
+            format!(
+                "__keyspace@{db}__:{}*",
+                self.cache_key(&self.keys.endpoint(""))
+            ),
+            format!("__keyspace@{db}__:{}", self.keys.hash(&self.env)),
+        ]
+    }
+
+    /// Refreshes the siblings in memory a change to `key` concerns
+    pub(crate) async fn keyspace_event(&self, key: &str) {
+        let siblings = if key == self.keys.hash(&self.env) {
+            self.cached_siblings().await
+        } else {
+            match self.sibling_of_key(key) {
+                Some(sibling) if self.endpoints.load().get(sibling).is_some() => {
+                    vec![sibling.to_owned()]
+                }
+                _ => return,
+            }
+        };
+
+        log_to!(Refresh, Debug, "keyspace: {key} changed");
+        self.refresh_each(&siblings).await;
+    }
+
+    /// Refreshes the sibling `event` is about if it's in memory and of this env
+    async fn announced(&self, event: ChangeEvent) {
+        let held = self.endpoints.load().get(&event.sibling).is_some();
+        if event.env != self.env.name() || !held {
+            return;
+        }
+
+        log_to!(
+            Refresh,
+            Debug,
+            "keyspace: sibling[{}] {:?} announced",
+            event.sibling,
+            event.kind
+        );
+        self.refresh_each(&[event.sibling]).await;
+    }
+
+    /// The sibling whose live endpoint record is stored at `key`; `None` for archived versions
+    fn sibling_of_key<'a>(&self, key: &'a str) -> Option<&'a str> {
+        let sibling = key.strip_prefix(&self.cache_key(&self.keys.endpoint("")))?;
+        (!sibling.is_empty() && !sibling.contains('@')).then_some(sibling)
+    }
+
+    pub(crate) async fn cached_siblings(&self) -> Vec<String> {
+        self.endpoints
+            .load()
+            .iter()
+            .map(|(sibling, _)| sibling.to_owned())
+            .collect()
+    }
+
+    /// [`Self::refresh_each`] of those of `siblings` held in memory
+    #[cfg(feature = "kube-watch")]
+    pub(crate) async fn refresh_held(&self, siblings: &[String]) {
+        let held = {
+            let endpoints = self.endpoints.load();
+            siblings
+                .iter()
+                .filter(|sibling| endpoints.get(sibling).is_some())
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if !held.is_empty() {
+            self.refresh_each(&held).await;
+        }
+    }
+
+    pub(crate) async fn refresh_each(&self, siblings: &[String]) {
+        let fetched = match self.fetch_each(siblings).await {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                log_to!(Refresh, Warn, "keyspace: every record kept: {e}");
+                return;
+            }
+        };
+
+        for (sibling, ep) in fetched {
+            match ep {
+                Ok(ep) => self.replace(&sibling, ep.as_ref()),
+                Err(e) => log_to!(
+                    Refresh,
+                    Warn,
+                    "keyspace: sibling[{sibling}] keeps its record: {e}"
+                ),
+            }
+        }
+    }
+}
+
+pub(crate) async fn subscribe(
+    client: &redis::Client,
+    patterns: &[String],
+) -> Result<redis::aio::PubSub, SiblingsError> {
+    let mut pubsub = client
+        .get_async_pubsub()
+        .await
+        .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?;
+    for pattern in patterns {
+        pubsub
+            .psubscribe(pattern)
+            .await
+            .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?;
+    }
+
+    Ok(pubsub)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Siblings;
+
+    #[test]
+    fn keys_to_siblings() {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let live = sib.cache_key(&sib.keys.endpoint("k9"));
+
+        assert_eq!(sib.sibling_of_key(&live), Some("k9"));
+        assert_eq!(sib.sibling_of_key(&format!("{live}@3")), None);
+        assert_eq!(sib.sibling_of_key("usage-k9"), None);
+        assert!(sib.keyspace_patterns(0)[0].starts_with("__keyspace@0__:"));
+    }
+}
@@ -0,0 +1,121 @@
+//! One way to write the durations and sizes config knobs take, from env vars, flags or builders.
+//!
+//! Durations are a whole number and a unit: `250ms`, `30s`, `5m`, `1h`, `2d`; a bare number is
+//! seconds, which keeps the older `*_SECS` knobs working. Sizes take `b`, `kb`, `mb` or `gb`
+//! (powers of 1024), a bare number being bytes. Units are case insensitive. A bad value names the
+//! knob and what it expected in [`SiblingsError::InvalidKnob`].
+
+use std::{env, time::Duration};
+
+use crate::{Siblings, SiblingsError};
+
+/// Parses `value` of `knob` as a duration, e.g. `30s` or `5m`
+pub fn parse_duration(knob: &str, value: &str) -> Result<Duration, SiblingsError> {
+    let (n, unit) = split(knob, value, "a number with ms, s, m, h or d, e.g. 30s")?;
+    let per_unit = match unit.as_str() {
+        "ms" => Duration::from_millis(1),
+        "" | "s" => Duration::from_secs(1),
+        "m" => Duration::from_secs(60),
+        "h" => Duration::from_secs(3600),
+        "d" => Duration::from_secs(24 * 3600),
+        _ => return Err(invalid(knob, value, "a unit of ms, s, m, h or d")),
+    };
+
+    per_unit
+        .checked_mul(u32::try_from(n).map_err(|_| invalid(knob, value, "a shorter duration"))?)
+        .ok_or_else(|| invalid(knob, value, "a shorter duration"))
+}
+
+/// Parses `value` of `knob` as a size in bytes, e.g. `512kb` or `4mb`
+pub fn parse_size(knob: &str, value: &str) -> Result<u64, SiblingsError> {
+    let (n, unit) = split(knob, value, "a number with b, kb, mb or gb, e.g. 512kb")?;
+    let per_unit: u64 = match unit.as_str() {
+        "" | "b" => 1,
+        "kb" => 1 << 10,
+        "mb" => 1 << 20,
+        "gb" => 1 << 30,
+        _ => return Err(invalid(knob, value, "a unit of b, kb, mb or gb")),
+    };
+
+    n.checked_mul(per_unit)
+        .ok_or_else(|| invalid(knob, value, "a smaller size"))
+}
+
+/// [`parse_duration`] of env var `var`, `None` when it's unset
+pub fn duration_from_env(var: &str) -> Result<Option<Duration>, SiblingsError> {
+    env::var(var)
+        .ok()
+        .map(|value| parse_duration(var, &value))
+        .transpose()
+}
+
+/// The number and lowercased unit of `value`
+fn split(knob: &str, value: &str, expected: &'static str) -> Result<(u64, String), SiblingsError> {
+    let value = value.trim();
+    let (n, unit) = value.split_at(value.trim_end_matches(char::is_alphabetic).len());
+    let n = n
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| invalid(knob, value, expected))?;
+
+    Ok((n, unit.to_ascii_lowercase()))
+}
+
+fn invalid(knob: &str, value: &str, expected: &'static str) -> SiblingsError {
+    SiblingsError::InvalidKnob {
+        knob: knob.to_owned(),
+        value: value.to_owned(),
+        expected,
+    }
+}
+
+impl Siblings {
+    /// [`Self::with_ttl`] from `X_SIBLINGS_TTL` (`5m`, `30s`..), unchanged when it's unset
+    pub fn with_ttl_from_env(self) -> Result<Self, SiblingsError> {
+        Ok(match duration_from_env("X_SIBLINGS_TTL")? {
+            Some(ttl) => self.with_ttl(ttl),
+            None => self,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(
+            parse_duration("t", "250ms").ok(),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            parse_duration("t", "30").ok(),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_duration("t", "5M").ok(),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(
+            parse_duration("t", " 1h ").ok(),
+            Some(Duration::from_secs(3600))
+        );
+
+        let err = parse_duration("X_SIBLINGS_TTL", "5 minutes").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"X_SIBLINGS_TTL="5 minutes" is invalid, expected a unit of ms, s, m, h or d"#
+        );
+        assert!(parse_duration("t", "m").is_err());
+        assert!(parse_duration("t", "-5s").is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("s", "512").ok(), Some(512));
+        assert_eq!(parse_size("s", "64KB").ok(), Some(64 * 1024));
+        assert_eq!(parse_size("s", "4mb").ok(), Some(4 << 20));
+        assert!(parse_size("s", "4tb").is_err());
+    }
+}
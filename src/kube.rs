@@ -0,0 +1,140 @@
+//! Resolving siblings to Kubernetes services, behind the `kube` feature.
+//!
+//! [`KubeSource`] is an [`EndpointSource`] that builds records instead of reading them: every
+//! sibling resolves to its service in the namespace, `http://k9.platform.svc.cluster.local:80`,
+//! so in-cluster deployments need nothing published. Ports come from [`KubeSource::with_port`],
+//! 80 otherwise. A region mapped with [`KubeSource::with_region`] resolves to the service in that
+//! namespace, or in another cluster's domain with [`KubeSource::with_region_in`]. Pinned versions
+//! don't exist here and find nothing.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{EndpointSource, Env, FetchFuture, RegionEndpoint, Regions};
+
+/// Port of siblings without one set
+pub const DEFAULT_KUBE_PORT: u16 = 80;
+
+/// Services of a namespace, and the namespaces and clusters regions map to
+#[derive(Debug, Clone)]
+pub struct KubeSource {
+    namespace: String,
+    domain: String,
+    scheme: String,
+    ports: HashMap<String, u16>,
+    /// region code -> (namespace, cluster domain)
+    regions: BTreeMap<String, (String, String)>,
+}
+
+impl KubeSource {
+    /// Services in `namespace` of this cluster, `cluster.local`
+    pub fn new(namespace: impl Into<String>) -> Self {
+        Self {
+            namespace: namespace.into(),
+            domain: "cluster.local".to_string(),
+            scheme: "http".to_string(),
+            ports: HashMap::new(),
+            regions: BTreeMap::new(),
+        }
+    }
+
+    /// Cluster domain, when not `cluster.local`
+    pub fn with_domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = domain.into();
+        self
+    }
+
+    /// Scheme of the urls, `http` unless set
+    pub fn with_scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.scheme = scheme.into();
+        self
+    }
+
+    /// Port the service of `sibling` listens on
+    pub fn with_port(mut self, sibling: &str, port: u16) -> Self {
+        self.ports.insert(sibling.to_owned(), port);
+        self
+    }
+
+    /// Resolves lookups in `region` to the services in `namespace` of this cluster
+    pub fn with_region(self, region: Regions, namespace: impl Into<String>) -> Self {
+        let domain = self.domain.clone();
+        self.with_region_in(region, namespace, domain)
+    }
+
+    /// Resolves lookups in `region` to the services in `namespace` of the cluster at `domain`
+    pub fn with_region_in(
+        mut self,
+        region: Regions,
+        namespace: impl Into<String>,
+        domain: impl Into<String>,
+    ) -> Self {
+        self.regions
+            .insert(region.code().to_owned(), (namespace.into(), domain.into()));
+        self
+    }
+
+    fn record(&self, sibling: &str) -> RegionEndpoint {
+        let url = |namespace: &str, domain: &str| {
+            let port = self
+                .ports
+                .get(sibling)
+                .copied()
+                .unwrap_or(DEFAULT_KUBE_PORT);
+            format!(
+                "{}://{sibling}.{namespace}.svc.{domain}:{port}",
+                self.scheme
+            )
+        };
+
+        RegionEndpoint {
+            default: url(&self.namespace, &self.domain).into(),
+            regions: self
+                .regions
+                .iter()
+                .map(|(region, (namespace, domain))| {
+                    (region.clone(), url(namespace, domain).into())
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl EndpointSource for KubeSource {
+    fn fetch<'a>(&'a self, _: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        let record = pinned.is_none().then(|| self.record(sibling));
+        Box::pin(async move { Ok(record) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Siblings;
+
+    #[tokio::test]
+    async fn resolves_to_services() -> anyhow::Result<()> {
+        let kube = KubeSource::new("platform")
+            .with_port("k9", 9000)
+            .with_region(Regions::IN, "platform-in")
+            .with_region_in(Regions::US, "platform", "us.example.internal");
+        let sib = Siblings::with_source(kube, None).with_env(Env::Dev);
+
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("http://k9.platform.svc.cluster.local:9000")
+        );
+        assert_eq!(
+            sib.try_sibling("k9", Some("IN")).await?.as_deref(),
+            Some("http://k9.platform-in.svc.cluster.local:9000")
+        );
+        assert_eq!(
+            sib.try_sibling("bank-statement", Some("US"))
+                .await?
+                .as_deref(),
+            Some("http://bank-statement.platform.svc.us.example.internal:80")
+        );
+
+        Ok(())
+    }
+}
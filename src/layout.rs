@@ -0,0 +1,210 @@
+//! Reading and writing live endpoint records in either [`Layout`].
+//!
+//! Reads look in the configured layout first and then the other, so consumers keep resolving
+//! while an env moves from one to the other. Archived versions are plain keys in both.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::LazyLock,
+};
+
+use crate::{
+    agent, budget, cache, Backend, Env, Layout, RedisSource, RegionEndpoint, Siblings,
+    SiblingsError,
+};
+
+/// KEYS: live key, env hash, archive key. ARGV: sibling, configured layout, expected version,
+/// record. Reads the live version the way [`RedisSource::read`] does, configured layout
+/// first; writes only when it is the expected one and returns -1, else returns it.
+static CAS_PUBLISH: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        local function version(data)
+            if not data then return nil end
+            local ok, record = pcall(cjson.decode, data)
+            if ok and type(record) == 'table' then return tonumber(record.version) or 0 end
+            return 0
+        end
+
+        local from_key = version(redis.call('GET', KEYS[1]))
+        local from_hash = version(redis.call('HGET', KEYS[2], ARGV[1]))
+        local current
+        if ARGV[2] == 'hash' then current = from_hash or from_key else current = from_key or from_hash end
+        current = current or 0
+
+        if current ~= tonumber(ARGV[3]) then return current end
+
+        redis.call('SET', KEYS[3], ARGV[4])
+        if ARGV[2] == 'hash' then
+            redis.call('HSET', KEYS[2], ARGV[1], ARGV[4])
+        else
+            redis.call('SET', KEYS[1], ARGV[4])
+        end
+        return -1
+        ",
+    )
+});
+
+impl RedisSource {
+    /// The live record of `sibling` in `env`, or its archived `pinned` version; empty when
+    /// neither layout has it
+    pub(crate) async fn read(
+        &self,
+        env: &Env,
+        sibling: &str,
+        pinned: Option<u64>,
+    ) -> Result<Vec<u8>, SiblingsError> {
+        if let Some(version) = pinned {
+            return self
+                .get(&self.keys.key(env, &self.keys.archive(sibling, version)))
+                .await;
+        }
+
+        for layout in self.keys.read_order() {
+            let data = match layout {
+                Layout::Keys => {
+                    self.get(&self.keys.key(env, &self.keys.endpoint(sibling)))
+                        .await?
+                }
+                Layout::Hash => {
+                    let hash = self.keys.hash(env);
+                    log_to!(Resolve, Info, "get_cache.hash: {hash}[{sibling}]");
+                    budget::acquire(&hash)?;
+                    cache::hget(self.conn(), &hash, sibling)
+                        .await
+                        .map_err(SiblingsError::unreachable)?
+                        .unwrap_or_default()
+                }
+            };
+
+            if !data.is_empty() {
+                return Ok(data);
+            }
+        }
+
+        Ok(Vec::new())
+    }
+
+    /// The value at `key`, empty when it isn't set
+    pub(crate) async fn get(&self, key: &str) -> Result<Vec<u8>, SiblingsError> {
+        log_to!(Resolve, Info, "get_cache.key:  {key}");
+        budget::acquire(key)?;
+        cache::get(self.conn(), key)
+            .await
+            .map_err(SiblingsError::unreachable)
+    }
+}
+
+impl Siblings {
+    /// The live record of `sibling` in `env`, or its archived `pinned` version, from whichever
+    /// backend this instance reads; empty when there is none
+    pub(crate) async fn read_record(
+        &self,
+        env: &Env,
+        sibling: &str,
+        pinned: Option<u64>,
+    ) -> Result<Vec<u8>, SiblingsError> {
+        match &self.backend {
+            Backend::Redis(redis) => redis.read(env, sibling, pinned).await,
+            Backend::Agent(socket) => agent::read_record(socket, env, sibling, pinned)
+                .await
+                .map_err(SiblingsError::unreachable),
+            Backend::Static => Err(self.backend.unsupported("reading Redis")),
+            Backend::Source(source) => match source
+                .fetch(env, sibling, pinned)
+                .await
+                .map_err(SiblingsError::unreachable)?
+            {
+                Some(ep) => Ok(serde_json::to_vec(&ep)?),
+                None => Ok(Vec::new()),
+            },
+        }
+    }
+
+    /// Writes `data` as the live record of `sibling` in the current env, in the configured
+    /// layout, and archives it as `version`, but only while the live version is still
+    /// `expected`. Returns the version found instead when it isn't; both writes happen in one
+    /// script so concurrent publishers can't overwrite each other.
+    pub(crate) async fn write_record_if(
+        &self,
+        sibling: &str,
+        expected: u64,
+        version: u64,
+        data: &[u8],
+    ) -> Result<Option<u64>, SiblingsError> {
+        let live = self.cache_key(&self.keys.endpoint(sibling));
+        let hash = self.keys.hash(&self.env);
+        let archive = self.cache_key(&self.keys.archive(sibling, version));
+        let layout = match self.keys.layout() {
+            Layout::Keys => "keys",
+            Layout::Hash => "hash",
+        };
+        info!("set_cache.cas: {live} {hash}[{sibling}] expecting version[{expected}]");
+
+        let mut script = CAS_PUBLISH.prepare_invoke();
+        script
+            .key(&live)
+            .key(&hash)
+            .key(&archive)
+            .arg(sibling)
+            .arg(layout)
+            .arg(expected)
+            .arg(data);
+        let found: i64 = cache::invoke(self.write_conn()?, &script)
+            .await
+            .map_err(SiblingsError::unreachable)?;
+
+        Ok((found >= 0).then_some(found as u64))
+    }
+
+    /// Removes the live record of `sibling` in the current env from both layouts
+    pub(crate) async fn delete_record(&self, sibling: &str) -> Result<(), SiblingsError> {
+        self.del_cache(&self.keys.endpoint(sibling)).await?;
+
+        let hash = self.keys.hash(&self.env);
+        info!("del_cache.hash: {hash}[{sibling}]");
+        cache::hdel(self.write_conn()?, &hash, sibling)
+            .await
+            .map_err(SiblingsError::unreachable)
+    }
+
+    /// Siblings with a live record in the current env, in either layout, walked with SCAN and
+    /// HSCAN
+    pub(crate) async fn published(&self) -> Result<Vec<String>, SiblingsError> {
+        let prefix = self.keys.endpoint("");
+        let mut published = self
+            .scan_cache(&self.keys.endpoint("*"))
+            .await?
+            .into_iter()
+            .filter_map(|k| k.strip_prefix(&prefix).map(str::to_string))
+            // archived versions are `ep-{sibling}@{version}`
+            .filter(|s| !s.contains('@'))
+            .collect::<BTreeSet<_>>();
+
+        published.extend(self.scan_hash(&self.keys.hash(&self.env)).await?);
+
+        Ok(published.into_iter().collect())
+    }
+
+    /// Every sibling published in the current env with its live record as published, without
+    /// pins or rollout applied. Records that don't parse are logged and left out.
+    pub async fn list_siblings(&self) -> Result<BTreeMap<String, RegionEndpoint>, SiblingsError> {
+        let published = self.published().await?;
+        let records = self.read_many(&published, &HashMap::new()).await?;
+
+        Ok(records
+            .into_iter()
+            .filter_map(|(sibling, data)| match Self::deserialize(data) {
+                Ok(ep) => Some((sibling, ep)),
+                Err(e) => {
+                    warn!("list_siblings: sibling[{sibling}] skipped: {e}");
+                    None
+                }
+            })
+            .collect())
+    }
+
+    fn write_conn(&self) -> Result<cache::Conn<'_>, SiblingsError> {
+        self.backend.redis("writes")
+    }
+}
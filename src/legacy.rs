@@ -0,0 +1,89 @@
+//! The flat shape siblings.json had before records carried more than urls:
+//! `{"k9": {"default": "https://k9", "in": "https://k9.in"}}`.
+//!
+//! Tools still built on `HashMap<String, HashMap<String, String>>` convert with
+//! [`Endpoints::to_legacy_map`] (or [`EndpointsSnapshot::to_legacy_map`]) and
+//! [`Endpoints::from_legacy_map`]. Only urls survive the trip:
+//! stream and public urls, queues, databases, rollouts and stamps are dropped.
+
+use std::collections::HashMap;
+
+use crate::{Endpoints, EndpointsSnapshot, RegionEndpoint, SiblingsError};
+
+pub type LegacyMap = HashMap<String, HashMap<String, String>>;
+
+impl Endpoints {
+    /// Every record as sibling -> `default` and region code -> url
+    pub fn to_legacy_map(&self) -> LegacyMap {
+        self.iter()
+            .map(|(sibling, ep)| (sibling.to_owned(), ep.legacy_urls()))
+            .collect()
+    }
+
+    /// Records from the flat shape; one without a `default` url fails the whole conversion
+    pub fn from_legacy_map(map: LegacyMap) -> Result<Self, SiblingsError> {
+        let mut endpoints = Self::default();
+        for (sibling, urls) in map {
+            let ep: RegionEndpoint = serde_json::from_value(serde_json::to_value(urls)?)?;
+            endpoints.insert(&sibling, ep);
+        }
+
+        Ok(endpoints)
+    }
+}
+
+impl EndpointsSnapshot {
+    /// Every record held as sibling -> `default` and region code -> url
+    pub fn to_legacy_map(&self) -> LegacyMap {
+        self.records
+            .iter()
+            .map(|(sibling, held)| (sibling.clone(), held.record.legacy_urls()))
+            .collect()
+    }
+}
+
+impl RegionEndpoint {
+    fn legacy_urls(&self) -> HashMap<String, String> {
+        let mut urls = self
+            .regions
+            .iter()
+            .map(|(region, url)| (region.clone(), url.to_string()))
+            .collect::<HashMap<_, _>>();
+        urls.insert("default".to_string(), self.default.to_string());
+        urls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_round_trip() -> anyhow::Result<()> {
+        let map = LegacyMap::from([
+            (
+                "k9".to_string(),
+                HashMap::from([
+                    ("default".to_string(), "https://k9".to_string()),
+                    ("in".to_string(), "https://k9.in".to_string()),
+                ]),
+            ),
+            (
+                "credit".to_string(),
+                HashMap::from([("default".to_string(), "https://credit".to_string())]),
+            ),
+        ]);
+
+        let endpoints = Endpoints::from_legacy_map(map.clone())?;
+        assert_eq!(
+            endpoints.get("k9").and_then(|ep| ep.get_in(Some("IN"))),
+            Some("https://k9.in".to_string())
+        );
+        assert_eq!(endpoints.to_legacy_map(), map);
+
+        let broken = LegacyMap::from([("k9".to_string(), HashMap::new())]);
+        assert!(Endpoints::from_legacy_map(broken).is_err());
+
+        Ok(())
+    }
+}
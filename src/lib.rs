@@ -1,477 +1,1282 @@
 #![feature(let_chains)]
 
-use std::{collections::HashMap, env, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    env,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
-use db::Db;
-use serde_derive::Deserialize;
+use arc_swap::ArcSwap;
+use serde_derive::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
 #[macro_use]
 extern crate log;
 
+/// `log!` to the target of a [`LogTarget`], unless [`Siblings::with_log_level`] turned it down
+macro_rules! log_to {
+    ($target:ident, $level:ident, $($arg:tt)+) => {
+        if $crate::LogTarget::$target.enabled(log::Level::$level) {
+            log::log!(target: $crate::LogTarget::$target.target(), log::Level::$level, $($arg)+);
+        }
+    };
+}
+
+pub mod agent;
+mod breaker;
+mod budget;
+mod bulk;
+mod cache;
+mod clock;
+#[cfg(feature = "aws")]
+mod cloudmap;
+#[cfg(feature = "compat")]
+mod compat;
+#[cfg(feature = "consul")]
+mod consul;
+mod context;
+mod cost;
+mod defaults;
+mod descriptor;
+mod dsn;
+mod endpoint_source;
+mod envdiff;
+mod error;
+#[cfg(feature = "etcd")]
+mod etcd;
+#[cfg(all(
+    test,
+    any(
+        feature = "aws",
+        feature = "consul",
+        feature = "etcd",
+        feature = "gcp",
+        feature = "http-source"
+    )
+))]
+mod fake_http;
+#[cfg(feature = "gcp")]
+mod gcp;
+mod generation;
+mod health;
+#[cfg(feature = "http-source")]
+mod http_source;
+#[cfg(any(feature = "aws", feature = "gcp", feature = "http-source"))]
+mod https;
+mod intercept;
+mod keys;
+mod keyspace;
+mod knobs;
+#[cfg(feature = "kube")]
+mod kube;
+mod layout;
+mod legacy;
+pub mod lint;
+pub mod loader;
+mod logging;
+#[cfg(feature = "test-util")]
+mod memory;
+mod notify;
+mod offline;
+mod overrides;
+mod pin;
+mod propagation;
+mod publish;
+mod queue;
+mod refresher;
+mod resolved;
+mod rollout;
+mod sandbox;
+mod scan;
+mod scheme;
+mod selftest;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "tower")]
+mod service;
+#[cfg(feature = "shared-file")]
+mod shared_file;
+#[cfg(feature = "kube-watch")]
+mod slices;
+mod snapshot;
+mod sources;
+#[cfg(feature = "srv")]
+mod srv;
+mod status;
+mod stream;
+mod topology;
+mod typed;
+mod usage;
+mod warmup;
+mod watch;
+mod webhook;
+
+pub use breaker::{DEFAULT_BREAKER_THRESHOLD, MAX_PROBE_BACKOFF, MIN_PROBE_BACKOFF};
+pub use budget::{set_redis_budget, RedisBudget};
+pub use clock::{Clock, MockClock, SystemClock};
+#[cfg(feature = "aws")]
+pub use cloudmap::{AwsCredentials, CloudMapSource};
+#[cfg(feature = "consul")]
+pub use consul::ConsulSource;
+pub use context::{Priority, ResolveContext, DEGRADED_FOR};
+pub use cost::{CostNote, Explanation, LatencyClass};
+pub use defaults::parse_siblings_file;
+pub use descriptor::{AuthStyle, Protocol, ServiceDescriptor};
+pub use dsn::{DsnEndpoint, EnvSecrets, SecretResolver, StaticSecrets};
+pub use endpoint_source::{CachedSource, EndpointSource, FetchFuture, RedisSource};
+pub use envdiff::{EnvDiff, UrlDiff};
+pub use error::SiblingsError;
+#[cfg(feature = "etcd")]
+pub use etcd::{EtcdSource, DEFAULT_ETCD_PREFIX};
+#[cfg(feature = "gcp")]
+pub use gcp::{ServiceDirectorySource, SERVICE_DIRECTORY_API};
+pub use generation::{Generation, GenerationalCache};
+pub use health::{HealthSnapshot, HealthStatus, SiblingHealth, HEALTH_TTL, PROBE_TIMEOUT};
+#[cfg(feature = "http-source")]
+pub use http_source::{HttpSource, DEFAULT_REVALIDATE};
+pub use intercept::{Interceptor, Resolution};
+pub use keys::{KeyScheme, Layout};
+pub use knobs::{duration_from_env, parse_duration, parse_size};
+#[cfg(feature = "kube")]
+pub use kube::{KubeSource, DEFAULT_KUBE_PORT};
+pub use legacy::LegacyMap;
+pub use logging::LogTarget;
+#[cfg(feature = "test-util")]
+pub use memory::MemorySource;
+pub use notify::{ChangeEvent, ChangeKind, ChangeSink, NotifyFuture};
+#[cfg(feature = "notify")]
+pub use notify::{WebhookSink, DEFAULT_WEBHOOK_TIMEOUT};
+pub use propagation::Propagation;
+pub use queue::{KafkaTarget, QueueEndpoint};
+pub use resolved::{CacheMeta, ResolvedEndpoint};
+pub use rollout::Rollout;
+pub use scan::{SCAN_BATCH, SCAN_PAUSE};
+pub use selftest::{Check, CheckStatus, SelfTestReport, CHECK_TIMEOUT, MAX_CLOCK_SKEW};
+#[cfg(feature = "tower")]
+pub use service::{ResolveFuture, ResolveRequest, SiblingsResolver};
+#[cfg(feature = "kube-watch")]
+pub use slices::EndpointSliceSource;
+pub use snapshot::{EndpointsSnapshot, HeldRecord};
+pub use sources::{Source, DEFAULT_PRECEDENCE};
+#[cfg(feature = "srv")]
+pub use srv::SrvSource;
+pub use status::{PlatformStatus, SiblingStatus, STATUS_FRESH};
+pub use warmup::{WarmUpReport, MAX_WARM_RETRY, MIN_WARM_RETRY};
+pub use watch::ChangeCallback;
+
+/// How long a sibling found unpublished is answered with `None`, see [`Siblings::with_negative_ttl`]
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
+/// How long a Redis read may take, see [`Siblings::with_read_timeout`]
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_millis(200);
+
 #[derive(Clone)]
 pub struct Siblings {
-    db: Arc<db::RedisPool>,
+    backend: Backend,
     me: Option<String>, // define who is me - this has to be the template code
     env: Env,
-    endpoints: Arc<RwLock<Endpoints>>,
+    keys: KeyScheme,
+    /// Records in memory, read without locking and replaced whole on every change, see [`update`]
+    endpoints: Arc<ArcSwap<Endpoints>>,
+    /// What `endpoints` held before the last flush, for lookups out of cold fetch budget
+    stale: Arc<ArcSwap<Endpoints>>,
+    /// Until when Redis counts as degraded after a failed read, see [`ResolveContext`]
+    degraded_until: Arc<std::sync::Mutex<Option<Instant>>>,
+    /// Fails reads fast while Redis is down
+    breaker: Arc<breaker::Breaker>,
+    /// sibling -> record version this consumer is pinned to
+    pins: Arc<RwLock<HashMap<String, u64>>>,
+    /// Region used when a lookup passes none
+    default_region: Option<Regions>,
+    /// Zone this deployment runs in, see [`Siblings::with_topology`]
+    zone: Option<String>,
+    /// Outside prod, read the prod key of a sibling whose env key is not set
+    prod_fallback: bool,
+    /// Env whose records show through where `env` has none, dev for a sandbox
+    base_env: Option<Env>,
+    /// Whether `http://` urls are upgraded to `https://`, in prod only when unset
+    https_upgrade: Option<bool>,
+    /// Siblings whose urls are never upgraded to https
+    plain_http: HashSet<String>,
+    /// How long a record stays in memory before the next lookup re-fetches it, forever if unset
+    ttl: Option<Duration>,
+    /// Per-sibling overrides of `ttl`
+    sibling_ttls: HashMap<String, Duration>,
+    /// How long a sibling found unpublished is answered with `None` without reading Redis
+    negative_ttl: Duration,
+    /// How long a Redis read may take before it fails with [`SiblingsError::Timeout`]
+    read_timeout: Duration,
+    /// Siblings resolved since the last usage report
+    usage: Arc<usage::Usage>,
+    /// Set with lazy hydration, initialised once every published record is loaded
+    hydration: Option<Arc<tokio::sync::OnceCell<()>>>,
+    /// Told about every record published or pruned through this instance
+    sinks: Vec<Arc<dyn ChangeSink>>,
+    /// Run on every url lookup, in order
+    interceptors: Vec<Arc<dyn Interceptor>>,
+    /// Receivers handed out by [`Self::watch`] and callbacks added with [`Self::on_change`]
+    observers: Arc<std::sync::Mutex<watch::Observers>>,
+    /// Where ttls, windows and report timestamps read time from
+    clock: clock::SharedClock,
+}
+
+/// Where cache keys are read from
+#[derive(Clone)]
+enum Backend {
+    /// A Redis: the `db` crate's pool, one reached by url or a Redis Cluster
+    Redis(Box<RedisSource>),
+    /// Unix socket of the node-local `siblings-agent`
+    Agent(PathBuf),
+    /// No Redis at all, see [`Siblings::from_records`]
+    Static,
+    /// Records read from elsewhere, see [`Siblings::with_source`]
+    Source(Arc<dyn EndpointSource>),
+}
+
+impl Backend {
+    /// The Redis behind this backend, `None` for any other
+    fn conn(&self) -> Option<cache::Conn<'_>> {
+        match self {
+            Self::Redis(redis) => Some(redis.conn()),
+            Self::Agent(_) | Self::Static | Self::Source(_) => None,
+        }
+    }
+
+    /// [`Self::conn`], or [`SiblingsError::Unsupported`] naming this backend as unable to do `what`
+    fn redis(&self, what: &'static str) -> Result<cache::Conn<'_>, SiblingsError> {
+        self.conn().ok_or_else(|| self.unsupported(what))
+    }
+
+    fn unsupported(&self, what: &'static str) -> SiblingsError {
+        SiblingsError::Unsupported {
+            what,
+            backend: self.name(),
+        }
+    }
+
+    /// What errors and skipped checks call this backend
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Redis(_) => "redis",
+            Self::Agent(_) => "sidecar agent",
+            Self::Static => "static",
+            Self::Source(_) => "endpoint source",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum Regions {
     IN,
     US,
+    EU,
+    SG,
+    APAC,
 }
 
-impl From<&str> for Regions {
-    fn from(value: &str) -> Self {
-        match value {
-            "IN" | "IND" => Self::IN,
-            "US" | "USA" => Self::US,
-            _ => panic!("Region {value} not supported"),
+impl TryFrom<&str> for Regions {
+    type Error = SiblingsError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::parse(value).ok_or_else(|| SiblingsError::UnknownRegion(value.to_string()))
+    }
+}
+
+impl FromStr for Regions {
+    type Err = SiblingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s)
+    }
+}
+
+const REGION_ALIASES: [(&str, Regions); 9] = [
+    ("IN", Regions::IN),
+    ("IND", Regions::IN),
+    ("US", Regions::US),
+    ("USA", Regions::US),
+    ("EU", Regions::EU),
+    ("EUR", Regions::EU),
+    ("SG", Regions::SG),
+    ("SGP", Regions::SG),
+    ("APAC", Regions::APAC),
+];
+
+impl Regions {
+    /// Case-insensitive match of a code or alias, without allocating on the lookup path
+    fn parse(value: &str) -> Option<Self> {
+        REGION_ALIASES
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(value))
+            .map(|(_, r)| *r)
+    }
+
+    /// Key of the region in records
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::IN => "in",
+            Self::US => "us",
+            Self::EU => "eu",
+            Self::SG => "sg",
+            Self::APAC => "apac",
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl AsRef<str> for Regions {
+    fn as_ref(&self) -> &str {
+        self.code()
+    }
+}
+
+/// Finds `region` among a record's per-region values: case-insensitive, with aliases like
+/// `IND`/`USA` mapped to their region code. Any code present in the record works, known to
+/// [`Regions`] or not.
+fn lookup_region<'a, V>(regions: &'a HashMap<String, V>, region: &str) -> Option<&'a V> {
+    let code = Regions::parse(region).map_or(region, |r| r.code());
+    regions
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(code))
+        .map(|(_, v)| v)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Env {
     Prod,
     Dev,
+    Staging,
+    /// Any other cluster, keys are prefixed with its name like dev's
+    Custom(String),
 }
 
 impl Env {
     pub fn new_from_env() -> Self {
-        env::var("X_ENV").map_or(Self::Dev, |env| {
-            let env = env.to_lowercase();
-            if &env == "prod" {
-                Self::Prod
-            } else if &env == "dev" {
-                Self::Dev
-            } else {
-                panic!("Siblings: invalid env: {env}");
-            }
-        })
+        env::var("X_ENV").map_or(Self::Dev, |env| Self::from_name(&env))
     }
-}
 
-#[derive(Debug, Clone, Default)]
-pub struct Endpoints {
-    august: Option<RegionEndpoint>,
-    bankstatement: Option<RegionEndpoint>,
-    bureau: Option<RegionEndpoint>,
-    gst: Option<RegionEndpoint>,
-    k9: Option<RegionEndpoint>,
-    matrix: Option<RegionEndpoint>,
-    pandora: Option<RegionEndpoint>,
-    retina: Option<RegionEndpoint>,
-    schematron: Option<RegionEndpoint>,
-    sentry: Option<RegionEndpoint>,
-    siblings: HashMap<String, RegionEndpoint>,
-    thumbnailer: Option<RegionEndpoint>,
-    xchange: Option<RegionEndpoint>,
-}
+    pub fn from_name(name: &str) -> Self {
+        let name = name.trim().to_lowercase();
+        match name.as_str() {
+            "prod" => Self::Prod,
+            "dev" => Self::Dev,
+            "staging" => Self::Staging,
+            _ => Self::Custom(name),
+        }
+    }
 
-#[derive(Debug, Clone, Deserialize, Default)]
-pub struct RegionEndpoint {
-    default: String,
-    ind: Option<String>,
-    usa: Option<String>,
+    /// As written in `X_ENV` and in `{env}` placeholders of a siblings file
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Prod => "prod",
+            Self::Dev => "dev",
+            Self::Staging => "staging",
+            Self::Custom(name) => name,
+        }
+    }
+
+    /// Only prod keys are unprefixed and live on the prod Redis
+    pub fn is_prod(&self) -> bool {
+        *self == Self::Prod
+    }
 }
 
-impl RegionEndpoint {
-    pub fn get(&self, region: Option<Regions>) -> Option<String> {
-        if let Some(region) = region {
-            match region {
-                Regions::US => {
-                    if self.usa.is_some() {
-                        return self.usa.clone();
+/// Generates [`Endpoints`] and the named accessors of the first-class siblings from one table of
+/// `(accessor, try_ accessor, _in accessor) => sibling name in cache keys`, so a field can't be
+/// read or written under the wrong service. Adding a service is one line in the table below.
+macro_rules! services {
+    ($(($field:ident, $try_field:ident, $field_in:ident) => $sibling:literal,)*) => {
+        #[derive(Debug, Clone, Default)]
+        pub struct Endpoints {
+            $($field: Option<RegionEndpoint>,)*
+            siblings: HashMap<String, RegionEndpoint>,
+            /// Registered webhook urls by cache key
+            webhooks: HashMap<String, RegionValue>,
+            /// Service descriptors by sibling
+            descriptors: HashMap<String, ServiceDescriptor>,
+            /// When each record was put in memory, for [`Siblings::with_ttl`]
+            loaded_at: HashMap<String, Instant>,
+            /// When each sibling was last found unpublished, for [`Siblings::with_negative_ttl`]
+            missing: HashMap<String, Instant>,
+            /// Records set with [`Siblings::set_endpoint`], answering ahead of whatever is loaded
+            overrides: HashMap<String, RegionEndpoint>,
+            /// Records from [`Siblings::with_local_file`]
+            local: HashMap<String, RegionEndpoint>,
+            /// Which of overrides, the local file and loaded records answer first
+            precedence: sources::Precedence,
+            /// Stamps `loaded_at` and `missing`
+            clock: clock::SharedClock,
+        }
+
+        impl Endpoints {
+            fn with_clock(clock: clock::SharedClock) -> Self {
+                Self {
+                    clock,
+                    ..Default::default()
+                }
+            }
+
+            /// An empty set on `clock`, keeping overrides, the local file and the precedence
+            fn layers_only(&self, clock: clock::SharedClock) -> Self {
+                Self {
+                    overrides: self.overrides.clone(),
+                    local: self.local.clone(),
+                    precedence: self.precedence.clone(),
+                    ..Self::with_clock(clock)
+                }
+            }
+
+            /// The record of `sibling` from the first source holding one
+            pub fn get(&self, sibling: &str) -> Option<&RegionEndpoint> {
+                self.answer(sibling, self.precedence.sources()).map(|(_, ep)| ep)
+            }
+
+            /// Which source answers for `sibling`
+            pub fn source_of(&self, sibling: &str) -> Option<Source> {
+                self.answer(sibling, self.precedence.sources()).map(|(source, _)| source)
+            }
+
+            /// The record answering ahead of a Redis read, from Redis' own when loaded
+            fn held(&self, sibling: &str) -> Option<&RegionEndpoint> {
+                self.answer(sibling, self.precedence.held()).map(|(_, ep)| ep)
+            }
+
+            /// The record answering when Redis has none for `sibling`
+            fn fallback(&self, sibling: &str) -> Option<&RegionEndpoint> {
+                self.answer(sibling, self.precedence.fallback()).map(|(_, ep)| ep)
+            }
+
+            fn answer(
+                &self,
+                sibling: &str,
+                sources: &[Source],
+            ) -> Option<(Source, &RegionEndpoint)> {
+                sources.iter().find_map(|source| {
+                    let ep = match source {
+                        Source::Overrides => self.overrides.get(sibling),
+                        Source::LocalFile => self.local.get(sibling),
+                        Source::Redis => self.loaded(sibling),
+                    };
+                    ep.map(|ep| (*source, ep))
+                })
+            }
+
+            /// The record of `sibling` read from Redis
+            fn loaded(&self, sibling: &str) -> Option<&RegionEndpoint> {
+                match sibling {
+                    $($sibling => self.$field.as_ref(),)*
+                    _ => self.siblings.get(sibling),
+                }
+            }
+
+            fn remove(&mut self, sibling: &str) {
+                self.loaded_at.remove(sibling);
+                match sibling {
+                    $($sibling => self.$field = None,)*
+                    _ => {
+                        self.siblings.remove(sibling);
                     }
                 }
-                Regions::IN => {
-                    if self.ind.is_some() {
-                        return self.ind.clone();
+            }
+
+            /// Stores `ep` against the sibling name used for its cache key (`bank-statement`, `k9`, ...)
+            fn insert(&mut self, sibling: &str, ep: RegionEndpoint) {
+                self.missing.remove(sibling);
+                self.loaded_at.insert(sibling.to_owned(), self.clock.now());
+                match sibling {
+                    $($sibling => self.$field = Some(ep),)*
+                    _ => {
+                        self.siblings.insert(sibling.to_owned(), ep);
                     }
                 }
             }
+
+            /// How long `sibling` has been in memory; overrides and the local file never age
+            fn age(&self, sibling: &str) -> Option<Duration> {
+                if self.source_of(sibling).is_some_and(|s| s != Source::Redis) {
+                    return Some(Duration::ZERO);
+                }
+                self.loaded_at
+                    .get(sibling)
+                    .map(|at| self.clock.now().saturating_duration_since(*at))
+            }
+
+            /// Drops the record of `sibling`, noting that nothing is published for it
+            fn missed(&mut self, sibling: &str) {
+                self.remove(sibling);
+                self.missing.insert(sibling.to_owned(), self.clock.now());
+            }
+
+            /// Whether `sibling` was found unpublished within the last `ttl`
+            fn missed_within(&self, sibling: &str, ttl: Duration) -> bool {
+                self.missing
+                    .get(sibling)
+                    .is_some_and(|at| self.clock.now().saturating_duration_since(*at) < ttl)
+            }
+
+            /// Every record answering, with the sibling name used for its cache key, by name
+            pub fn iter(&self) -> impl Iterator<Item = (&str, &RegionEndpoint)> {
+                let names = [$(($sibling, &self.$field),)*]
+                    .into_iter()
+                    .filter_map(|(sibling, ep)| ep.as_ref().map(|_| sibling))
+                    .chain(self.siblings.keys().map(String::as_str))
+                    .chain(self.overrides.keys().map(String::as_str))
+                    .chain(self.local.keys().map(String::as_str))
+                    .collect::<BTreeSet<_>>();
+                names
+                    .into_iter()
+                    .filter_map(|sibling| self.get(sibling).map(|ep| (sibling, ep)))
+            }
         }
 
-        Some(self.default.clone())
+        impl Siblings {
+            $(
+                #[cfg(feature = "compat")]
+                #[deprecated(note = "use the `try_` accessor, it tells a missing record from a failed read")]
+                pub async fn $field(&self, region: Option<&str>) -> Option<String> {
+                    self.lookup($sibling, region, stringify!($field)).await
+                }
+
+                pub async fn $try_field(
+                    &self,
+                    region: Option<&str>,
+                ) -> Result<Option<String>, SiblingsError> {
+                    self.try_sibling($sibling, region).await
+                }
+
+                #[cfg(feature = "compat")]
+                #[deprecated(note = "use `try_sibling_in`, it tells a missing record from a failed read")]
+                pub async fn $field_in(&self, region: Regions) -> Option<String> {
+                    self.lookup($sibling, Some(region.code()), stringify!($field_in)).await
+                }
+            )*
+        }
+    };
+}
+
+services! {
+    (august, try_august, august_in) => "august",
+    (bankstatement, try_bankstatement, bankstatement_in) => "bank-statement",
+    (bureau, try_bureau, bureau_in) => "bureau",
+    (gst, try_gst, gst_in) => "gst",
+    (k9, try_k9, k9_in) => "k9",
+    (matrix, try_matrix, matrix_in) => "matrix",
+    (pandora, try_pandora, pandora_in) => "pandora",
+    (retina, try_retina, retina_in) => "retina",
+    (schematron, try_schematron, schematron_in) => "schematron",
+    (sentry, try_sentry, sentry_in) => "sentry",
+    (thumbnailer, try_thumbnailer, thumbnailer_in) => "thumbnailer",
+    (xchange, try_xchange, xchange_in) => "xchange",
+}
+
+/// The records as siblings.json lays them out, sibling -> record, sorted by sibling. Only the
+/// records go in: webhooks, descriptors and load times stay behind.
+impl serde::Serialize for Endpoints {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter().collect::<BTreeMap<_, _>>())
     }
 }
 
-impl Siblings {
-    pub async fn new(db: Arc<db::RedisPool>, me: Option<&str>) -> Self {
-        if env::var("X_LOCAL").map_or(false, |x| x == "TRUE") {
-            return Self::for_local(db, me).await;
-        }
-        Self {
-            me: me.map(|s| s.to_string()),
-            db,
-            env: Env::new_from_env(),
-            endpoints: Arc::new(RwLock::new(Endpoints::default())),
+impl<'de> serde::Deserialize<'de> for Endpoints {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let records =
+            <HashMap<String, RegionEndpoint> as serde::Deserialize>::deserialize(deserializer)?;
+        let mut endpoints = Self::default();
+        for (sibling, ep) in records {
+            endpoints.insert(&sibling, ep);
         }
+
+        Ok(endpoints)
     }
+}
 
-    async fn for_local(db: Arc<db::RedisPool>, me: Option<&str>) -> Self {
-        let slf = Self {
-            me: me.map(|s| s.to_string()),
-            db,
-            env: Env::new_from_env(),
-            endpoints: Arc::new(RwLock::new(Endpoints::default())),
-        };
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct RegionEndpoint {
+    /// Urls are shared, so [`Siblings::endpoint_arc`] hands them out without copying
+    default: Arc<str>,
+    /// Every other top level string of the record: region code -> url (`"in"`, `"us"`, `"jp"`..)
+    #[serde(flatten)]
+    regions: HashMap<String, Arc<str>>,
+    /// Streaming (websocket) endpoint, when it isn't just the REST url with a `ws` scheme
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_url: Option<RegionValue>,
+    /// External base url for links handed to end users (emails, webhooks)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    public_url: Option<RegionValue>,
+    /// Pub/Sub and Kafka topics by queue name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    queues: HashMap<String, QueueEndpoint>,
+    /// DSN templates by database name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    databases: HashMap<String, DsnEndpoint>,
+    /// Expected latency and cost of calls by region code, `default` for the rest
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    costs: HashMap<String, CostNote>,
+    /// Next endpoint being rolled out to a share of consumers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rollout: Option<Rollout>,
+    /// Bumped by [`Siblings::publish`] on every change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u64>,
+    /// Hash of the record content, stamped by [`Siblings::publish`] with the version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+    /// Unix seconds of the publish that wrote this version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    published_at: Option<u64>,
+    /// `Some(false)` while the sibling is disabled, see [`Siblings::disable`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+}
 
-        let f_iter = if let Ok(i) = dotenvy::from_filename_iter("svc.env") {
-            i
-        } else {
-            return slf;
-        };
+/// A per-region value inside a record: either a plain string used everywhere or
+/// `{"default": .., "in": .., "us": .., ..}`
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(from = "RegionValueRepr")]
+pub struct RegionValue {
+    default: String,
+    #[serde(flatten)]
+    regions: HashMap<String, String>,
+}
 
-        for item in f_iter {
-            let (key, val) = item.unwrap();
-            let endpoint = RegionEndpoint {
-                default: format!("http://localhost:{val}"),
-                ..Default::default()
-            };
-            if &key == "bank_statement" {
-                let mut w = slf.endpoints.write().await;
-                w.bankstatement = Some(endpoint);
-            } else if &key == "bureau" {
-                let mut w = slf.endpoints.write().await;
-                w.bureau = Some(endpoint);
-            } else if &key == "k9" {
-                let mut w = slf.endpoints.write().await;
-                w.k9 = Some(endpoint);
-            } else if &key == "gst" {
-                let mut w = slf.endpoints.write().await;
-                w.gst = Some(endpoint);
-            } else if &key == "matrix" {
-                let mut w = slf.endpoints.write().await;
-                w.matrix = Some(endpoint);
-            } else if &key == "pandora" {
-                let mut w = slf.endpoints.write().await;
-                w.pandora = Some(endpoint);
-            } else if key == "retina" {
-                let mut w = slf.endpoints.write().await;
-                w.retina = Some(endpoint);
-            } else if &key == "schematron" {
-                let mut w = slf.endpoints.write().await;
-                w.schematron = Some(endpoint);
-            } else if &key == "sentry" {
-                let mut w = slf.endpoints.write().await;
-                w.sentry = Some(endpoint);
-            } else if &key == "thumbnailer" {
-                let mut w = slf.endpoints.write().await;
-                w.thumbnailer = Some(endpoint);
-            } else if &key == "xchange" {
-                let mut w = slf.endpoints.write().await;
-                w.xchange = Some(endpoint);
-            } else {
-                let mut w = slf.endpoints.write().await;
-                let key = key.split('_').collect::<Vec<_>>().join("-");
-
-                w.siblings.insert(key, endpoint);
-            }
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RegionValueRepr {
+    Plain(String),
+    Regional {
+        default: String,
+        #[serde(flatten)]
+        regions: HashMap<String, String>,
+    },
+}
+
+impl From<RegionValueRepr> for RegionValue {
+    fn from(value: RegionValueRepr) -> Self {
+        match value {
+            RegionValueRepr::Plain(default) => Self::new(default),
+            RegionValueRepr::Regional { default, regions } => Self { default, regions },
         }
+    }
+}
 
-        slf
+impl RegionValue {
+    pub fn new(default: impl Into<String>) -> Self {
+        Self {
+            default: default.into(),
+            ..Default::default()
+        }
     }
 
-    async fn get_cache(&self, key: &str) -> Result<Vec<u8>> {
-        // Appends `dev` if target environment is dev
-        let key = if self.env == Env::Dev {
-            format!("dev-{key}")
-        } else {
-            key.to_string()
-        };
-        info!("get_cache.key:  {key}");
-        Db::get_cache_for_pool(self.db.clone(), &key).await
+    /// Sets the value used for `region` (a [`Regions`] or any region code) instead of the default
+    pub fn with_region(mut self, region: impl AsRef<str>, value: impl Into<String>) -> Self {
+        let region = region.as_ref();
+        let code = Regions::parse(region).map_or(region, |r| r.code());
+        self.regions.insert(code.to_ascii_lowercase(), value.into());
+        self
     }
 
-    pub async fn august(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(august) = &self.endpoints.read().await.august {
-            return august.get(region);
-        }
+    pub fn get(&self, region: Option<&str>) -> &str {
+        region
+            .and_then(|r| lookup_region(&self.regions, r))
+            .unwrap_or(&self.default)
+    }
+}
 
-        if let Ok(c) = self.get_cache("ep-august").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.august = Some(ep.clone());
+impl RegionEndpoint {
+    pub fn get(&self, region: Option<Regions>) -> Option<String> {
+        self.get_in(region.as_ref().map(|r| r.code()))
+    }
 
-            return ep.get(region);
-        }
+    /// Url for a region code as callers pass it (`"IN"`, `"usa"`, `"jp"`), falling back to the
+    /// default when the record has nothing specific for it
+    pub fn get_in(&self, region: Option<&str>) -> Option<String> {
+        Some(self.url(region).to_string())
+    }
 
-        warn!("august: endpoint not found and was not fetched!");
-        None
+    /// [`Self::get_in`] without copying the url
+    pub fn get_arc(&self, region: Option<&str>) -> Arc<str> {
+        self.url(region).clone()
     }
 
-    pub async fn bankstatement(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(bs) = &self.endpoints.read().await.bankstatement {
-            return bs.get(region);
-        }
+    fn url(&self, region: Option<&str>) -> &Arc<str> {
+        region
+            .and_then(|r| lookup_region(&self.regions, r))
+            .unwrap_or(&self.default)
+    }
 
-        if let Ok(c) = self.get_cache("ep-bank-statement").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.bankstatement = Some(ep.clone());
+    /// Record version, `None` for records published before versioning
+    pub fn version(&self) -> Option<u64> {
+        self.version
+    }
 
-            return ep.get(region);
-        }
+    /// Content hash stamped at publish, `None` for records published before checksums
+    pub fn checksum(&self) -> Option<&str> {
+        self.checksum.as_deref()
+    }
 
-        warn!("bankstatement: endpoint not found and was not fetched!");
-        None
+    /// Unix seconds this version was published at, `None` for records published before stamps
+    pub fn published_at(&self) -> Option<u64> {
+        self.published_at
     }
 
-    pub async fn bureau(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(bs) = &self.endpoints.read().await.bureau {
-            return bs.get(region);
-        }
+    /// `false` while the sibling is disabled
+    pub fn is_enabled(&self) -> bool {
+        self.enabled != Some(false)
+    }
 
-        if let Ok(c) = self.get_cache("ep-bureau").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.bureau = Some(ep.clone());
+    /// The user-facing base url; never falls back to the internal endpoint
+    pub fn public_url(&self, region: Option<&str>) -> Option<String> {
+        self.public_url.as_ref().map(|p| p.get(region).to_string())
+    }
+}
 
-            return ep.get(region);
+impl Siblings {
+    #[cfg(feature = "db")]
+    pub async fn new(db: Arc<db::RedisPool>, me: Option<&str>) -> Self {
+        let slf = Self::with_backend(Backend::Redis(Box::new(RedisSource::pool(db))), me);
+        if env::var("X_LOCAL").map_or(false, |x| x == "TRUE") {
+            return slf.with_local_file("svc.env");
         }
+        slf
+    }
 
-        warn!("bureau: endpoint not found and was not fetched!");
-        None
+    /// Talks to the Redis at `url` directly instead of the one configured for the `db` crate
+    pub async fn connect_url(url: &str, me: Option<&str>) -> Result<Self, SiblingsError> {
+        let conn = redis::Client::open(url)
+            .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?
+            .get_multiplexed_tokio_connection()
+            .await
+            .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?;
+
+        Ok(Self::with_backend(
+            Backend::Redis(Box::new(RedisSource::direct(conn))),
+            me,
+        ))
     }
 
-    pub async fn gst(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(gst) = &self.endpoints.read().await.gst {
-            return gst.get(region);
-        }
+    /// Talks to the Redis Cluster reachable through any of the seed `nodes`, following
+    /// `MOVED`/`ASK` redirects. Publishing needs an env's keys in one slot, so give the
+    /// [`KeyScheme`] a hash-tagged namespace like `{siblings}`.
+    #[cfg(feature = "cluster")]
+    pub async fn connect_cluster(nodes: &[&str], me: Option<&str>) -> Result<Self, SiblingsError> {
+        let conn = redis::cluster::ClusterClient::new(nodes.to_vec())
+            .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?
+            .get_async_connection()
+            .await
+            .map_err(|e| SiblingsError::RedisUnreachable(e.into()))?;
+
+        Ok(Self::with_backend(
+            Backend::Redis(Box::new(RedisSource::cluster(conn))),
+            me,
+        ))
+    }
 
-        if let Ok(c) = self.get_cache("ep-gst").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.gst = Some(ep.clone());
+    /// Resolves through the `siblings-agent` listening on `socket` instead of talking to Redis.
+    /// The agent shares its connection and cache with every process on the node.
+    pub fn sidecar(socket: impl Into<PathBuf>, me: Option<&str>) -> Self {
+        Self::with_backend(Backend::Agent(socket.into()), me)
+    }
 
-            return ep.get(region);
+    fn with_backend(backend: Backend, me: Option<&str>) -> Self {
+        Self {
+            pins: Arc::new(RwLock::new(pin::pins_from_env())),
+            ..Self::unlayered(backend, me, Env::new_from_env())
         }
-
-        warn!("gst: endpoint not found and was not fetched!");
-        None
+        .with_sandbox_from_env()
+        .with_url_overrides_from_env()
     }
 
-    pub async fn k9(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(k9) = &self.endpoints.read().await.k9 {
-            return k9.get(region);
+    /// An instance of `env` reading `backend` alone: no url overrides, pins or sandbox from the
+    /// process env
+    fn unlayered(backend: Backend, me: Option<&str>, env: Env) -> Self {
+        Self {
+            me: me.map(|s| s.to_string()),
+            backend,
+            env,
+            keys: KeyScheme::default(),
+            endpoints: Arc::new(ArcSwap::from_pointee(Endpoints::default())),
+            stale: Arc::new(ArcSwap::from_pointee(Endpoints::default())),
+            degraded_until: Arc::new(std::sync::Mutex::new(None)),
+            breaker: Arc::new(breaker::Breaker::new(DEFAULT_BREAKER_THRESHOLD)),
+            pins: Arc::default(),
+            default_region: None,
+            zone: None,
+            prod_fallback: false,
+            base_env: None,
+            https_upgrade: None,
+            plain_http: HashSet::new(),
+            ttl: None,
+            sibling_ttls: HashMap::new(),
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            usage: Arc::new(usage::Usage::default()),
+            hydration: None,
+            sinks: Vec::new(),
+            interceptors: Vec::new(),
+            observers: Arc::default(),
+            clock: clock::SharedClock::default(),
         }
+    }
 
-        if let Ok(c) = self.get_cache("ep-k9").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.k9 = Some(ep.clone());
+    /// Reads and writes the keys of `env` instead of the one in `X_ENV`, leaving any sandbox
+    pub fn with_env(mut self, env: Env) -> Self {
+        self.env = env;
+        self.base_env = None;
+        self
+    }
 
-            return ep.get(region);
+    /// Lays keys out per `keys` instead of the default `[dev-]ep-{sibling}`
+    pub fn with_key_scheme(mut self, keys: KeyScheme) -> Self {
+        if let Backend::Redis(redis) = &mut self.backend {
+            redis.keys = keys.clone();
         }
+        self.keys = keys;
+        self
+    }
 
-        warn!("k9: endpoint not found and was not fetched!");
-        None
+    /// Outside prod, resolves a sibling with no `dev-ep-{sibling}` from the prod `ep-{sibling}` on
+    /// the same Redis, so an env only has to publish the records that differ from prod
+    pub fn with_prod_fallback(mut self, fallback: bool) -> Self {
+        self.prod_fallback = fallback;
+        self
     }
 
-    pub async fn matrix(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(matrix) = &self.endpoints.read().await.matrix {
-            return matrix.get(region);
-        }
+    /// Re-fetches a record on the first lookup after it spent `ttl` in memory, so endpoint
+    /// changes reach every pod without a coordinated [`Self::flush`]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
 
-        if let Ok(c) = self.get_cache("ep-matrix").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.matrix = Some(ep.clone());
+    /// [`Self::with_ttl`] for `sibling` only, overriding the instance wide one
+    pub fn with_sibling_ttl(mut self, sibling: &str, ttl: Duration) -> Self {
+        self.sibling_ttls.insert(sibling.to_owned(), ttl);
+        self
+    }
 
-            return ep.get(region);
-        }
+    /// Whether the record of `sibling` in `endpoints` outlived its ttl
+    fn expired(&self, endpoints: &Endpoints, sibling: &str) -> bool {
+        self.ttl_of(sibling)
+            .zip(endpoints.age(sibling))
+            .is_some_and(|(ttl, age)| age >= ttl)
+    }
 
-        warn!("matrix: endpoint not found and was not fetched!");
-        None
+    /// Answers lookups of a sibling found unpublished with `None` for `ttl` instead of reading
+    /// Redis again on every call; [`DEFAULT_NEGATIVE_TTL`] unless set, `Duration::ZERO` turns it off.
+    /// A record published meanwhile shows up once `ttl` passes or after a [`Self::flush`].
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+        self
     }
 
-    pub async fn pandora(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(pandora) = &self.endpoints.read().await.pandora {
-            return pandora.get(region);
-        }
+    /// Gives up on a Redis read after `timeout` instead of [`DEFAULT_READ_TIMEOUT`], failing it with
+    /// [`SiblingsError::Timeout`]; lookups holding an expired or stale record serve that instead
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
 
-        if let Ok(c) = self.get_cache("ep-pandora").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.pandora = Some(ep.clone());
+    /// Whether `sibling` is answered from the negative cache right now
+    async fn known_missing(&self, sibling: &str) -> bool {
+        self.endpoints
+            .load()
+            .missed_within(sibling, self.negative_ttl)
+    }
 
-            return ep.get(region);
-        }
+    /// The ttl records of `sibling` live by, if any
+    fn ttl_of(&self, sibling: &str) -> Option<Duration> {
+        self.sibling_ttls.get(sibling).copied().or(self.ttl)
+    }
 
-        warn!("pandora: endpoint not found and was not fetched!");
-        None
+    /// Resolves lookups that pass no region against `region` instead of the record's `default`
+    pub fn with_default_region(mut self, region: Regions) -> Self {
+        self.default_region = Some(region);
+        self
     }
 
-    pub async fn retina(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(k9) = &self.endpoints.read().await.k9 {
-            return k9.get(region);
+    /// [`Self::with_default_region`] with the region the deployment sets in `X_REGION`, and its
+    /// zone from `X_ZONE` (see [`Self::with_topology`]). Unset or unknown values leave lookups
+    /// without a region on the record's `default`.
+    pub fn with_region_from_env(self) -> Self {
+        let slf = self.with_zone_from_env();
+        match env::var("X_REGION") {
+            Ok(region) => match Regions::try_from(region.as_str()) {
+                Ok(region) => slf.with_default_region(region),
+                Err(e) => {
+                    warn!("X_REGION: {e}, not setting a default region");
+                    slf
+                }
+            },
+            Err(_) => slf,
         }
+    }
 
-        if let Ok(c) = self.get_cache("ep-retina").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.retina = Some(ep.clone());
-
-            return ep.get(region);
-        }
+    pub fn default_region(&self) -> Option<Regions> {
+        self.default_region
+    }
 
-        warn!("retina: endpoint not found and was not fetched!");
-        None
+    /// The region a lookup resolves against: the caller's, else the instance default
+    fn region<'a>(&self, region: Option<&'a str>) -> Option<&'a str> {
+        region.or(self.default_region.map(|r| r.code()))
     }
 
-    pub async fn schematron(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(schematron) = &self.endpoints.read().await.schematron {
-            return schematron.get(region);
-        }
+    /// Prefixes `key` with the namespace and env of the key scheme (`dev-ep-k9`, `staging-ep-k9`)
+    fn cache_key(&self, key: &str) -> String {
+        self.keys.key(&self.env, key)
+    }
 
-        if let Ok(c) = self.get_cache("ep-schematron").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.pandora = Some(ep.clone());
+    async fn get_cache(&self, key: &str) -> Result<Vec<u8>, SiblingsError> {
+        self.get_cache_key(&self.cache_key(key)).await
+    }
 
-            return ep.get(region);
+    /// [`Self::get_cache`] of a key already prefixed
+    async fn get_cache_key(&self, key: &str) -> Result<Vec<u8>, SiblingsError> {
+        match &self.backend {
+            Backend::Redis(redis) => redis.get(key).await,
+            Backend::Agent(socket) => {
+                log_to!(Resolve, Info, "get_cache.key:  {key}");
+                agent::get_cache(socket, key)
+                    .await
+                    .map_err(SiblingsError::unreachable)
+            }
+            Backend::Static => Err(self.backend.unsupported("reading Redis")),
+            // sources only hold endpoint records
+            Backend::Source(_) => Ok(Vec::new()),
         }
+    }
 
-        warn!("schematron: endpoint not found and was not fetched!");
-        None
+    /// The url of `sibling` from memory only, without awaiting, for synchronous code (`Drop`,
+    /// metrics callbacks). `None` when it isn't in memory.
+    pub fn peek(&self, sibling: &str, region: Option<&str>) -> Option<String> {
+        let lookup = self.before_lookup(sibling, region).ok()?;
+        let endpoints = self.endpoints.load();
+        let ep = endpoints.get(&lookup.sibling)?;
+        let url = ep
+            .is_enabled()
+            .then(|| self.nearest(ep, lookup.region.as_deref()).to_string());
+
+        self.after_lookup(&lookup, url).ok()?
     }
 
-    pub async fn sentry(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(sentry) = &self.endpoints.read().await.sentry {
-            return sentry.get(region);
+    /// The whole record for `sibling`, from memory or fetched and kept in memory. Fails with
+    /// [`SiblingsError::Disabled`] while the sibling is disabled.
+    pub async fn endpoint(&self, sibling: &str) -> Result<Option<RegionEndpoint>, SiblingsError> {
+        match self.held_or_fetched(sibling).await? {
+            Some(ep) if !ep.is_enabled() => Err(SiblingsError::Disabled(sibling.to_owned())),
+            ep => Ok(ep),
         }
+    }
 
-        if let Ok(c) = self.get_cache("ep-sentry").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.sentry = Some(ep.clone());
+    async fn held_or_fetched(
+        &self,
+        sibling: &str,
+    ) -> Result<Option<RegionEndpoint>, SiblingsError> {
+        self.usage.record(sibling);
+        let expired = {
+            let endpoints = self.endpoints.load();
+            match endpoints.held(sibling) {
+                Some(ep) if !self.expired(&endpoints, sibling) => return Ok(Some(ep.clone())),
+                None if !endpoints.precedence.reads_redis()
+                    || endpoints.missed_within(sibling, self.negative_ttl) =>
+                {
+                    return Ok(endpoints.fallback(sibling).cloned());
+                }
+                ep => ep.cloned(),
+            }
+        };
 
-            return ep.get(region);
+        if expired.is_none()
+            && self.hydrate_lazily().await
+            && let Some(ep) = self.endpoints.load().get(sibling)
+        {
+            return Ok(Some(ep.clone()));
         }
 
-        warn!("sentry: endpoint not found and was not fetched!");
-        None
+        let ep = match (self.fetch(sibling).await, expired) {
+            (Ok(ep), _) => ep,
+            (Err(e), Some(expired)) => {
+                log_to!(
+                    Refresh,
+                    Warn,
+                    "endpoint: sibling[{sibling}] re-fetch failed, serving expired record: {e}"
+                );
+                return Ok(Some(expired));
+            }
+            (Err(e), None) => return Err(e),
+        };
+
+        update(&self.endpoints, |endpoints| match &ep {
+            Some(ep) => endpoints.insert(sibling, ep.clone()),
+            None => endpoints.missed(sibling),
+        });
+        let ep = ep.or_else(|| self.endpoints.load().fallback(sibling).cloned());
+        self.record_read(sibling, ep.as_ref());
+
+        Ok(ep)
+    }
+
+    async fn set_cache(&self, key: &str, data: &[u8]) -> Result<(), SiblingsError> {
+        let key = self.cache_key(key);
+        info!("set_cache.key:  {key}");
+        let conn = self.backend.redis("writes")?;
+        cache::set(conn, &key, data)
+            .await
+            .map_err(SiblingsError::unreachable)
+    }
+
+    async fn del_cache(&self, key: &str) -> Result<(), SiblingsError> {
+        let key = self.cache_key(key);
+        info!("del_cache.key:  {key}");
+        let conn = self.backend.redis("writes")?;
+        cache::del(conn, &key)
+            .await
+            .map_err(SiblingsError::unreachable)
+    }
+
+    /// Like [`Self::sibling`] but surfaces why the lookup failed.
+    /// `Ok(None)` means no endpoint is published for `sibling` in this env.
+    pub async fn try_sibling(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Result<Option<String>, SiblingsError> {
+        let lookup = self.before_lookup(sibling, region)?;
+        let url = self
+            .endpoint(&lookup.sibling)
+            .await?
+            .map(|ep| self.nearest(&ep, lookup.region.as_deref()).to_string());
+
+        self.after_lookup(&lookup, url)
     }
 
-    pub async fn thumbnailer(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(thumb) = &self.endpoints.read().await.thumbnailer {
-            return thumb.get(region);
+    /// [`Self::try_sibling`] without copying the url: a record fresh in memory answers with the url
+    /// it shares, no allocation. Lookups that miss memory or run interceptors cost what
+    /// [`Self::try_sibling`] does.
+    pub async fn endpoint_arc(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Result<Option<Arc<str>>, SiblingsError> {
+        if !self.interceptors.is_empty() {
+            return Ok(self.try_sibling(sibling, region).await?.map(Arc::from));
         }
 
-        if let Ok(c) = self.get_cache("ep-thumbnailer").await
-            && let Ok(ep) = Self::deserialize(c)
+        let region = self.region(region);
         {
-            let mut w = self.endpoints.write().await;
-            w.thumbnailer = Some(ep.clone());
-
-            return ep.get(region);
+            let endpoints = self.endpoints.load();
+            if let Some(ep) = endpoints.held(sibling)
+                && ep.is_enabled()
+                && !self.expired(&endpoints, sibling)
+            {
+                self.usage.record(sibling);
+                return Ok(Some(self.nearest(ep, region)));
+            }
         }
 
-        warn!("thumbnailer: endpoint not found and was not fetched!");
-        None
+        Ok(self
+            .endpoint(sibling)
+            .await?
+            .map(|ep| self.nearest(&ep, region)))
     }
 
-    pub async fn xchange(&self, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(x) = &self.endpoints.read().await.xchange {
-            return x.get(region);
+    /// Like [`Self::try_sibling`] for callers that need the url: a sibling with no record is
+    /// [`SiblingsError::NotConfigured`] and a value that isn't a url is
+    /// [`SiblingsError::InvalidUrl`]
+    pub async fn require(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Result<String, SiblingsError> {
+        let url = self
+            .try_sibling(sibling, region)
+            .await?
+            .ok_or_else(|| SiblingsError::NotConfigured(sibling.to_owned()))?;
+
+        error::check_url(sibling, url)
+    }
+
+    pub async fn try_me(&self, region: Option<&str>) -> Result<Option<String>, SiblingsError> {
+        match &self.me {
+            Some(me) => self.try_sibling(me, region).await,
+            None => Ok(None),
         }
+    }
 
-        if let Ok(c) = self.get_cache("ep-xchange").await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.xchange = Some(ep.clone());
+    /// [`Self::endpoint`] for the `Option` returning accessors, logging why nothing came back
+    async fn record(&self, sibling: &str, caller: &str) -> Option<RegionEndpoint> {
+        let known_missing = self.known_missing(sibling).await;
+        match self.endpoint(sibling).await {
+            Ok(Some(ep)) => Some(ep),
+            Ok(None) if known_missing => {
+                log_to!(
+                    Resolve,
+                    Debug,
+                    "{caller}: endpoint for sibling[{sibling}] not found, cached"
+                );
+                None
+            }
+            Ok(None) => {
+                log_to!(
+                    Resolve,
+                    Warn,
+                    "{caller}: endpoint for sibling[{sibling}] not found! {}",
+                    SiblingsError::NotConfigured(sibling.to_owned()).help()
+                );
+                None
+            }
+            Err(e) => {
+                log_to!(
+                    Resolve,
+                    Warn,
+                    "{caller}: endpoint for sibling[{sibling}] was not fetched: {}",
+                    e.report()
+                );
+                None
+            }
+        }
+    }
 
-            return ep.get(region);
+    /// Base url to use in links for end users, as opposed to the internal endpoint
+    pub async fn public_url(&self, sibling: &str, region: Option<&str>) -> Option<String> {
+        let region = self.region(region);
+        let url = self.record(sibling, "public_url").await?.public_url(region);
+        if url.is_none() {
+            log_to!(
+                Resolve,
+                Warn,
+                "public_url: sibling[{sibling}] has no public_url"
+            );
         }
 
-        warn!("xchange: endpoint not found and was not fetched!");
-        None
+        url
     }
 
-    pub async fn sibling(&self, sibling: &str, region: Option<&str>) -> Option<String> {
-        let region = region.map(|r| r.into());
-        if let Some(siblingmap) = self.endpoints.read().await.siblings.get(sibling) {
-            return siblingmap.get(region);
+    /// Reads the endpoint for `sibling` straight from the cache.
+    /// `Ok(None)` means the key is not set for the current env.
+    async fn fetch(&self, sibling: &str) -> Result<Option<RegionEndpoint>, SiblingsError> {
+        let pinned = self.pins.read().await.get(sibling).copied();
+        let mut c = self
+            .guarded(self.read_record(&self.env, sibling, pinned))
+            .await?;
+        if c.is_empty()
+            && let Some(base) = &self.base_env
+        {
+            log_to!(
+                Resolve,
+                Debug,
+                "fetch: sibling[{sibling}] not set in {}, reading {}",
+                self.env.name(),
+                base.name()
+            );
+            c = self
+                .guarded(self.read_record(base, sibling, pinned))
+                .await?;
+        }
+        if c.is_empty() && self.prod_fallback && !self.env.is_prod() {
+            log_to!(
+                Resolve,
+                Info,
+                "fetch: sibling[{sibling}] not set in {}, falling back to prod",
+                self.env.name()
+            );
+            c = self
+                .guarded(self.read_record(&Env::Prod, sibling, pinned))
+                .await?;
+        }
+        if c.is_empty() {
+            return Ok(None);
         }
 
-        if let Ok(c) = self.get_cache(format!("ep-{sibling}").as_str()).await
-            && let Ok(ep) = Self::deserialize(c)
-        {
-            let mut w = self.endpoints.write().await;
-            w.siblings.insert(sibling.to_owned(), ep.clone());
+        self.parse(sibling, c).map(Some)
+    }
 
-            return ep.get(region);
-        }
+    /// The record of `sibling` as read from Redis, as this consumer sees it
+    fn parse(&self, sibling: &str, data: Vec<u8>) -> Result<RegionEndpoint, SiblingsError> {
+        Ok(self.upgraded(
+            sibling,
+            Self::deserialize(data)?.for_consumer(sibling, self.me.as_deref()),
+        ))
+    }
+
+    /// Re-reads `sibling` from Redis and swaps it into memory, leaving every other record alone.
+    /// Lookups keep the old record until the new one is in; a failed read keeps it for good and
+    /// one no longer published is dropped.
+    pub async fn refresh(&self, sibling: &str) -> Result<Option<RegionEndpoint>, SiblingsError> {
+        let ep = self.fetch(sibling).await?;
+        self.replace(sibling, ep.as_ref());
+        log_to!(
+            Refresh,
+            Info,
+            "refresh: sibling[{sibling}] now at version[{:?}]",
+            ep.as_ref().and_then(RegionEndpoint::version)
+        );
 
-        warn!("siblings: endpoint for sibling[{sibling}] not found and was not fetched!");
-        None
+        Ok(ep)
     }
 
-    pub async fn me(&self, region: Option<&str>) -> Option<String> {
-        if let Some(me) = &self.me {
-            self.sibling(me, region).await
-        } else {
-            None
-        }
+    /// Swaps `ep` in as the record of `sibling`, dropping it when `None`, and tells watchers
+    pub(crate) fn replace(&self, sibling: &str, ep: Option<&RegionEndpoint>) {
+        update(&self.endpoints, |endpoints| match ep {
+            Some(ep) => endpoints.insert(sibling, ep.clone()),
+            None => endpoints.remove(sibling),
+        });
+        self.record_read(sibling, ep);
     }
 
+    /// Drops every record from memory; they're kept aside as stale for [`ResolveContext`] lookups.
+    /// Overrides set with [`Self::set_endpoint`] and the local file stay.
     pub async fn flush(&self) {
-        let mut ep = self.endpoints.write().await;
-        *ep = Endpoints::default();
-    }
+        let fresh = self.endpoints.load().layers_only(self.clock.clone());
+        let flushed = self.endpoints.swap(Arc::new(fresh));
 
-    fn deserialize(data: Vec<u8>) -> Result<RegionEndpoint> {
-        let ep: HashMap<String, String> = serde_json::from_slice(&data[..])?;
+        update(&self.stale, |stale| {
+            for (sibling, ep) in flushed.iter() {
+                stale.insert(sibling, ep.clone());
+            }
+        });
+    }
 
-        Ok(RegionEndpoint {
-            default: ep.get("default").unwrap().to_string(),
-            ind: ep.get("in").map(|i| i.to_string()),
-            usa: ep.get("us").map(|u| u.to_string()),
-        })
+    fn deserialize(data: Vec<u8>) -> Result<RegionEndpoint, SiblingsError> {
+        Ok(serde_json::from_slice(&data[..])?)
     }
 }
 
+/// Copy-on-write change to the records in `cell`: `f` edits a copy that then replaces them, and
+/// runs again on the newer records when another change got in first. Readers never wait.
+pub(crate) fn update(cell: &ArcSwap<Endpoints>, mut f: impl FnMut(&mut Endpoints)) {
+    cell.rcu(|current| {
+        let mut next = Endpoints::clone(current);
+        f(&mut next);
+        next
+    });
+}
+
+/// 64-bit FNV-1a, stable across processes and releases
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325_u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 #[cfg(test)]
+#[allow(deprecated)]
 mod tests {
     // use crate::Siblings;
 
+    #[cfg(all(feature = "compat", feature = "db"))]
     use std::{collections::HashMap, env, fs::read_to_string};
 
     use anyhow::Result;
 
-    use crate::Siblings;
+    use crate::{Regions, Siblings};
 
+    #[cfg(all(feature = "compat", feature = "db"))]
     #[tokio::test]
     async fn check_prod() -> Result<()> {
-        let db = std::sync::Arc::new(crate::Db::connect_redis(false).await?);
+        let db = std::sync::Arc::new(db::Db::connect_redis(false).await?);
         let sib = Siblings::new(db, None).await;
 
         let data = serde_json::from_str::<HashMap<String, HashMap<String, String>>>(
@@ -544,11 +1349,12 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(all(feature = "compat", feature = "db"))]
     #[tokio::test]
     async fn check_dev() -> Result<()> {
         pretty_env_logger::init();
 
-        let db = std::sync::Arc::new(crate::Db::connect_redis(true).await?);
+        let db = std::sync::Arc::new(db::Db::connect_redis(true).await?);
         env::set_var("X_ENV", "dev");
 
         let sib = Siblings::new(db, None).await;
@@ -623,9 +1429,80 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(all(feature = "compat", feature = "db"))]
+    #[tokio::test]
+    async fn check_warm_up() -> Result<()> {
+        let db = std::sync::Arc::new(db::Db::connect_redis(false).await?);
+        let sib = Siblings::new(db, None).await;
+
+        let report = sib.warm_up(&["august", "k9", "not-a-sibling"]).await;
+
+        assert_eq!(
+            report.resolved,
+            vec!["august".to_string(), "k9".to_string()]
+        );
+        assert_eq!(report.missing, vec!["not-a-sibling".to_string()]);
+        assert!(report.errored.is_empty());
+        assert_eq!(report.latency.len(), 3);
+        assert!(!report.is_ready());
+
+        Ok(())
+    }
+
+    #[test]
+    fn region_parsing() {
+        assert!(matches!("IND".parse::<Regions>(), Ok(Regions::IN)));
+        assert!(matches!(Regions::try_from("US"), Ok(Regions::US)));
+        assert!(matches!(Regions::try_from("SGP"), Ok(Regions::SG)));
+        assert!(matches!(Regions::try_from("usa"), Ok(Regions::US)));
+        assert!(Regions::try_from("XX").is_err());
+
+        let ep = Siblings::deserialize(br#"{"default":"d","in":"i","eu":"e","JP":"j"}"#.to_vec())
+            .unwrap();
+        assert_eq!(ep.get_in(Some("??")).as_deref(), Some("d"));
+        assert_eq!(ep.get_in(Some("IND")).as_deref(), Some("i"));
+        assert_eq!(ep.get_in(Some("jp")).as_deref(), Some("j"));
+        assert_eq!(ep.get(Some(Regions::EU)).as_deref(), Some("e"));
+        assert_eq!(ep.get(Some(Regions::SG)).as_deref(), Some("d"));
+    }
+
+    #[test]
+    fn default_region_applies_to_lookups_without_one() {
+        let sib = Siblings::sidecar("/dev/null", None);
+        assert_eq!(sib.region(None), None);
+
+        let sib = sib.with_default_region(Regions::US);
+        assert_eq!(sib.region(None), Some("us"));
+        assert_eq!(sib.region(Some("IN")), Some("IN"));
+    }
+
+    #[test]
+    fn ws_url_from_record() -> Result<()> {
+        let ep = Siblings::deserialize(
+            br#"{"default":"https://x.example.com","in":"http://x.in.example.com"}"#.to_vec(),
+        )?;
+        assert_eq!(ep.ws_url(None).as_deref(), Some("wss://x.example.com"));
+        assert_eq!(
+            ep.ws_url(Some("in")).as_deref(),
+            Some("ws://x.in.example.com")
+        );
+
+        let ep = Siblings::deserialize(
+            br#"{"default":"https://x.example.com","stream_url":{"default":"wss://stream.example.com","us":"wss://stream.us.example.com"}}"#.to_vec(),
+        )?;
+        assert_eq!(ep.ws_url(None).as_deref(), Some("wss://stream.example.com"));
+        assert_eq!(
+            ep.ws_url(Some("US")).as_deref(),
+            Some("wss://stream.us.example.com")
+        );
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "compat", feature = "db"))]
     #[tokio::test]
     async fn check_local() -> Result<()> {
-        let db = std::sync::Arc::new(crate::Db::connect_redis(false).await?);
+        let db = std::sync::Arc::new(db::Db::connect_redis(false).await?);
         let sib = Siblings::new(db.clone(), None).await;
 
         let data = serde_json::from_str::<HashMap<String, HashMap<String, String>>>(
@@ -662,4 +1539,150 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn each_service_keeps_its_own_record() {
+        let mut endpoints = crate::Endpoints::default();
+        let retina = crate::RegionEndpoint {
+            default: "https://retina".into(),
+            ..Default::default()
+        };
+        endpoints.insert("retina", retina.clone());
+
+        assert_eq!(endpoints.get("retina"), Some(&retina));
+        assert_eq!(endpoints.retina, Some(retina));
+        assert!(endpoints.k9.is_none());
+        assert_eq!(endpoints.iter().count(), 1);
+
+        endpoints.remove("retina");
+        assert!(endpoints.get("retina").is_none());
+    }
+
+    #[tokio::test]
+    async fn peek_reads_memory_only() {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        assert_eq!(sib.peek("k9", None), None);
+
+        let ep = crate::RegionEndpoint {
+            default: "https://k9".into(),
+            ..Default::default()
+        };
+        crate::update(&sib.endpoints, |e| e.insert("k9", ep.clone()));
+        assert_eq!(sib.peek("k9", Some("IN")).as_deref(), Some("https://k9"));
+
+        // readers keep the records they loaded while a change swaps in new ones
+        let held = sib.endpoints.load();
+        crate::update(&sib.endpoints, |e| e.remove("k9"));
+        assert!(held.get("k9").is_some());
+        assert_eq!(sib.peek("k9", None), None);
+    }
+
+    #[tokio::test]
+    async fn expired_record_served_when_refetch_fails() -> Result<()> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None)
+            .with_ttl(std::time::Duration::from_secs(300))
+            .with_sibling_ttl("k9", std::time::Duration::ZERO);
+        let ep = crate::RegionEndpoint {
+            default: "https://k9".into(),
+            ..Default::default()
+        };
+        crate::update(&sib.endpoints, |endpoints| {
+            endpoints.insert("k9", ep.clone());
+            endpoints.insert("matrix", ep.clone());
+        });
+        let endpoints = sib.endpoints.load();
+        assert!(sib.expired(&endpoints, "k9"));
+        assert!(!sib.expired(&endpoints, "matrix"));
+
+        assert_eq!(sib.endpoint("k9").await?, Some(ep));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unpublished_siblings_are_cached() -> Result<()> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        crate::update(&sib.endpoints, |e| e.missed("credit"));
+
+        // answered without reaching the (missing) agent
+        assert_eq!(sib.endpoint("credit").await?, None);
+        assert!(sib.endpoint("k9").await.is_err());
+
+        let sib = sib.with_negative_ttl(std::time::Duration::ZERO);
+        assert!(sib.endpoint("credit").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn disabled_siblings_fail_lookups() -> Result<()> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let ep = Siblings::deserialize(br#"{"default":"https://k9","enabled":false}"#.to_vec())?;
+        assert!(!ep.is_enabled());
+        assert!(ep.same_as(&Siblings::deserialize(
+            br#"{"default":"https://k9"}"#.to_vec()
+        )?));
+        crate::update(&sib.endpoints, |e| e.insert("k9", ep.clone()));
+
+        assert!(matches!(
+            sib.endpoint("k9").await,
+            Err(crate::SiblingsError::Disabled(_))
+        ));
+        assert_eq!(sib.peek("k9", None), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn arc_lookups_share_the_url() -> Result<()> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let ep =
+            Siblings::deserialize(br#"{"default":"https://k9","in":"https://k9.in"}"#.to_vec())?;
+        crate::update(&sib.endpoints, |e| e.insert("k9", ep.clone()));
+
+        let first = sib.endpoint_arc("k9", Some("IN")).await?.unwrap();
+        let second = sib.endpoint_arc("k9", Some("in")).await?.unwrap();
+        assert_eq!(&*first, "https://k9.in");
+        assert!(std::sync::Arc::ptr_eq(&first, &second));
+        assert_eq!(
+            sib.endpoint_arc("k9", None).await?.as_deref(),
+            Some("https://k9")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn endpoints_round_trip_as_json() -> Result<()> {
+        let json = serde_json::json!({
+            "k9": {"default": "https://k9", "in": "https://k9.in"},
+            "credit": {"default": "https://credit", "us": "https://credit.us"},
+        });
+
+        let endpoints = serde_json::from_value::<crate::Endpoints>(json.clone())?;
+        assert_eq!(
+            endpoints.get("k9").and_then(|ep| ep.get_in(Some("IN"))),
+            Some("https://k9.in".to_string())
+        );
+        assert_eq!(serde_json::to_value(&endpoints)?, json);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn removed_endpoints_leave_memory() -> Result<()> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let ep = Siblings::deserialize(br#"{"default":"https://xchange"}"#.to_vec())?;
+        crate::update(&sib.endpoints, |e| e.insert("xchange", ep.clone()));
+        sib.set_endpoint("xchange", ep.clone());
+
+        assert!(sib.remove_endpoint("xchange", false).await?);
+        assert_eq!(sib.peek("xchange", None), None);
+        assert!(sib.overridden().is_empty());
+        assert!(!sib.remove_endpoint("xchange", false).await?);
+        // deleting needs a Redis connection
+        assert!(sib.remove_endpoint("xchange", true).await.is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,167 @@
+//! Rules a record should follow before it is published.
+//!
+//! [`check`] runs every rule on one record and returns what it found; `siblings-cli lint` runs it
+//! on each record of the siblings file. CI bots, editors and admin APIs validating a proposed
+//! change call the same function, so they all apply identical rules. Errors break lookups
+//! (a url consumers can't call); warnings are likely mistakes.
+
+use std::collections::HashMap;
+
+use crate::{error::check_url, RegionEndpoint, RegionValue, Regions};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintFinding {
+    pub severity: Severity,
+    /// Stable name of the rule, e.g. `invalid_url`
+    pub rule: &'static str,
+    /// Where in the record: `default`, `in`, `public_url.us`, `costs.jp`..
+    pub field: String,
+    pub message: String,
+}
+
+/// Every finding of every rule on `record`, errors first
+pub fn check(record: &RegionEndpoint) -> Vec<LintFinding> {
+    let mut findings = Vec::new();
+
+    url(&mut findings, "default".to_string(), &record.default);
+    for (region, url) in &record.regions {
+        self::url(&mut findings, region.clone(), url);
+        if Regions::parse(region).is_none() && !is_zone(region) {
+            findings.push(LintFinding {
+                severity: Severity::Warning,
+                rule: "unknown_region",
+                field: region.clone(),
+                message: format!("{region} is neither a known region nor a zone of one"),
+            });
+        }
+        if **url == *record.default {
+            findings.push(LintFinding {
+                severity: Severity::Warning,
+                rule: "same_as_default",
+                field: region.clone(),
+                message: "same url as default, the entry can go".to_string(),
+            });
+        }
+    }
+
+    for (field, value) in [
+        ("stream_url", &record.stream_url),
+        ("public_url", &record.public_url),
+        (
+            "rollout.next",
+            &record.rollout.as_ref().map(|r| r.next().clone()),
+        ),
+    ] {
+        if let Some(value) = value {
+            region_value(&mut findings, field, value);
+        }
+    }
+
+    for region in record.costs.keys() {
+        if region != "default" && !has_region(&record.regions, region) {
+            findings.push(LintFinding {
+                severity: Severity::Warning,
+                rule: "cost_without_url",
+                field: format!("costs.{region}"),
+                message: format!("cost noted for {region}, which has no url of its own"),
+            });
+        }
+    }
+
+    findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.field.cmp(&b.field)));
+    findings
+}
+
+fn region_value(findings: &mut Vec<LintFinding>, field: &str, value: &RegionValue) {
+    url(findings, field.to_string(), &value.default);
+    for (region, url) in &value.regions {
+        self::url(findings, format!("{field}.{region}"), url);
+    }
+}
+
+fn url(findings: &mut Vec<LintFinding>, field: String, url: &str) {
+    if check_url("", url.to_string()).is_err() {
+        findings.push(LintFinding {
+            severity: Severity::Error,
+            rule: "invalid_url",
+            message: format!("{url:?} has no scheme or host"),
+            field,
+        });
+        return;
+    }
+    if url.ends_with('/') {
+        findings.push(LintFinding {
+            severity: Severity::Warning,
+            rule: "trailing_slash",
+            message: format!("{url} ends with /, paths joined to it get //"),
+            field,
+        });
+    } else if url
+        .get(..7)
+        .is_some_and(|s| s.eq_ignore_ascii_case("http://"))
+    {
+        findings.push(LintFinding {
+            severity: Severity::Warning,
+            rule: "plain_http",
+            message: format!("{url} is plain http, upgraded to https in prod"),
+            field,
+        });
+    }
+}
+
+/// `in-a`: a known region, a dash and a zone name
+fn is_zone(code: &str) -> bool {
+    code.split_once('-')
+        .is_some_and(|(region, zone)| Regions::parse(region).is_some() && !zone.is_empty())
+}
+
+fn has_region<V>(regions: &HashMap<String, V>, region: &str) -> bool {
+    crate::lookup_region(regions, region).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Siblings;
+
+    #[test]
+    fn flags_broken_and_suspicious_records() -> anyhow::Result<()> {
+        let clean = Siblings::deserialize(
+            br#"{"default":"https://k9","in":"https://k9.in","in-a":"https://k9.in-a"}"#.to_vec(),
+        )?;
+        assert_eq!(check(&clean), []);
+
+        let record = Siblings::deserialize(
+            br#"{"default":"https://k9/","us":"k9.us","jp":"https://k9/","public_url":{"default":"http://k9.example.com"},"costs":{"eu":{"latency":"cross_region"}}}"#
+                .to_vec(),
+        )?;
+        let rules = check(&record)
+            .into_iter()
+            .map(|f| (f.severity, f.rule, f.field))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rules,
+            [
+                (Severity::Error, "invalid_url", "us".to_string()),
+                (
+                    Severity::Warning,
+                    "cost_without_url",
+                    "costs.eu".to_string()
+                ),
+                (Severity::Warning, "trailing_slash", "default".to_string()),
+                (Severity::Warning, "trailing_slash", "jp".to_string()),
+                (Severity::Warning, "unknown_region", "jp".to_string()),
+                (Severity::Warning, "same_as_default", "jp".to_string()),
+                (Severity::Warning, "plain_http", "public_url".to_string()),
+            ]
+        );
+
+        Ok(())
+    }
+}
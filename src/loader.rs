@@ -0,0 +1,276 @@
+//! Publishing a set of records in-process, what `siblings-cli` does with siblings.json.
+//!
+//! [`load_file`] and [`load_map`] publish every record that differs from the live one, [`diff`]
+//! reports what a load would change without writing, and [`prune`] removes live records the set
+//! no longer has. All of them act on the env of the [`Siblings`] they're given; [`clean`] empties
+//! a sandbox.
+//!
+//! [`select`] narrows a set down to the records a routine update actually touches, so the rest
+//! stay as they are in Redis. [`replicate`] loads one set into several Redis instances at once,
+//! e.g. one per region, listed in a targets file.
+
+use std::{collections::HashMap, fs::read_to_string, path::Path};
+
+use anyhow::{bail, Result};
+use serde_derive::Deserialize;
+
+use crate::{parse_siblings_file, Env, KeyScheme, RegionEndpoint, Siblings};
+
+/// How a set of records compares to what is live
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diff {
+    /// In the set, not published yet
+    pub added: Vec<String>,
+    /// Published with different contents
+    pub changed: Vec<String>,
+    /// Published as is
+    pub unchanged: Vec<String>,
+    /// Published but not in the set
+    pub removed: Vec<String>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// One Redis to load into, as listed in a targets file:
+/// `[{"name": "in", "url": "redis://10.0.0.5:6379"}, {"name": "us", "url": "..", "cluster": true}]`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    pub name: String,
+    pub url: String,
+    /// `url` is a seed node of a Redis Cluster, needs the `cluster` feature
+    #[serde(default)]
+    pub cluster: bool,
+}
+
+impl Target {
+    async fn connect(&self) -> Result<Siblings> {
+        if !self.cluster {
+            return Ok(Siblings::connect_url(&self.url, None).await?);
+        }
+
+        #[cfg(feature = "cluster")]
+        return Ok(Siblings::connect_cluster(&[&self.url], None).await?);
+        #[cfg(not(feature = "cluster"))]
+        bail!(
+            "{} is a Redis Cluster, build with the cluster feature",
+            self.name
+        )
+    }
+}
+
+/// Outcome of loading into one [`Target`]
+#[derive(Debug)]
+pub struct TargetReport {
+    pub target: String,
+    pub result: Result<Diff>,
+}
+
+/// Reads the targets file at `path`
+pub fn targets_file(path: impl AsRef<Path>) -> Result<Vec<Target>> {
+    Ok(serde_json::from_str(&read_to_string(path)?)?)
+}
+
+/// Loads `records` as the keys of `env`, laid out per `keys`, into every target concurrently.
+/// A failing target doesn't stop the others; check each report.
+pub async fn replicate(
+    targets: &[Target],
+    env: &Env,
+    keys: &KeyScheme,
+    records: &HashMap<String, RegionEndpoint>,
+) -> Vec<TargetReport> {
+    let loads = targets
+        .iter()
+        .map(|target| {
+            let (target, env, records) = (target.clone(), env.clone(), records.clone());
+            let keys = keys.clone();
+            tokio::spawn(async move {
+                let result = async {
+                    let siblings = target.connect().await?.with_env(env).with_key_scheme(keys);
+                    load_map(&siblings, records).await
+                }
+                .await;
+
+                TargetReport {
+                    target: target.name,
+                    result,
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut reports = Vec::with_capacity(loads.len());
+    for (load, target) in loads.into_iter().zip(targets) {
+        let report = load.await.unwrap_or_else(|e| TargetReport {
+            target: target.name.clone(),
+            result: Err(e.into()),
+        });
+        match &report.result {
+            Ok(diff) => info!(
+                "loader: target[{}] added {:?} changed {:?}",
+                report.target, diff.added, diff.changed
+            ),
+            Err(e) => warn!("loader: target[{}] failed: {e}", report.target),
+        }
+        reports.push(report);
+    }
+
+    reports
+}
+
+/// The records of `records` named in `only` (every record when empty) and not in `exclude`
+pub fn select(
+    mut records: HashMap<String, RegionEndpoint>,
+    only: &[&str],
+    exclude: &[&str],
+) -> HashMap<String, RegionEndpoint> {
+    for name in only.iter().chain(exclude) {
+        if !records.contains_key(*name) {
+            warn!("loader: sibling[{name}] is not in the records");
+        }
+    }
+
+    records.retain(|sibling, _| {
+        (only.is_empty() || only.contains(&sibling.as_str()))
+            && !exclude.contains(&sibling.as_str())
+    });
+    records
+}
+
+/// Parses the siblings file at `path` (with `_defaults` and env overrides) and loads it
+pub async fn load_file(siblings: &Siblings, path: impl AsRef<Path>) -> Result<Diff> {
+    let records = parse_siblings_file(&read_to_string(path)?, &siblings.env)?;
+    load_map(siblings, records).await
+}
+
+/// Publishes the added and changed records of `records`; returns the diff that was applied.
+/// Records missing from `records` stay live, see [`prune`].
+pub async fn load_map(
+    siblings: &Siblings,
+    records: HashMap<String, RegionEndpoint>,
+) -> Result<Diff> {
+    let diff = diff(siblings, &records).await?;
+
+    for (sibling, record) in records {
+        if !diff.added.contains(&sibling) && !diff.changed.contains(&sibling) {
+            continue;
+        }
+
+        let version = siblings.publish(&sibling, record).await?;
+        info!("loader: sibling[{sibling}] published at version[{version}]");
+    }
+
+    Ok(diff)
+}
+
+/// Compares `records` to what is live, without writing anything
+pub async fn diff(siblings: &Siblings, records: &HashMap<String, RegionEndpoint>) -> Result<Diff> {
+    let mut diff = Diff::default();
+
+    for (sibling, record) in records {
+        match siblings.live(sibling).await? {
+            None => diff.added.push(sibling.clone()),
+            Some(live) if live.same_as(record) => diff.unchanged.push(sibling.clone()),
+            Some(_) => diff.changed.push(sibling.clone()),
+        }
+    }
+
+    diff.removed = siblings
+        .published()
+        .await?
+        .into_iter()
+        .filter(|s| !records.contains_key(s))
+        .collect();
+
+    diff.added.sort();
+    diff.changed.sort();
+    diff.unchanged.sort();
+    diff.removed.sort();
+
+    Ok(diff)
+}
+
+/// Removes the live record of every sibling not in `records`; archived versions stay so pinned
+/// consumers keep resolving. Returns the siblings removed.
+pub async fn prune(
+    siblings: &Siblings,
+    records: &HashMap<String, RegionEndpoint>,
+) -> Result<Vec<String>> {
+    let mut removed = siblings
+        .published()
+        .await?
+        .into_iter()
+        .filter(|s| !records.contains_key(s))
+        .collect::<Vec<_>>();
+    removed.sort();
+
+    for sibling in &removed {
+        siblings.remove_endpoint(sibling, true).await?;
+        info!("loader: sibling[{sibling}] pruned");
+    }
+
+    Ok(removed)
+}
+
+/// Removes every live record of the sandbox `siblings` resolves in, once its preview is gone.
+/// Refuses to touch an env that isn't a sandbox.
+pub async fn clean(siblings: &Siblings) -> Result<Vec<String>> {
+    let Some(sandbox) = siblings.sandbox() else {
+        bail!(
+            "loader: {} is not a sandbox, not cleaning it",
+            siblings.env.name()
+        );
+    };
+
+    let removed = prune(siblings, &HashMap::new()).await?;
+    info!(
+        "loader: sandbox[{sandbox}] cleaned, {} records removed",
+        removed.len()
+    );
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::select;
+    use crate::{RegionEndpoint, Siblings};
+
+    #[test]
+    fn version_stamp_is_not_a_change() -> anyhow::Result<()> {
+        let live = Siblings::deserialize(br#"{"default":"https://k9","version":4}"#.to_vec())?;
+        let same = Siblings::deserialize(br#"{"default":"https://k9"}"#.to_vec())?;
+        let moved = Siblings::deserialize(br#"{"default":"https://k9-v2"}"#.to_vec())?;
+
+        assert!(live.same_as(&same));
+        assert!(!live.same_as(&moved));
+
+        Ok(())
+    }
+
+    #[test]
+    fn select_only_and_exclude() {
+        let records = ["k9", "matrix", "gst"]
+            .into_iter()
+            .map(|s| (s.to_string(), RegionEndpoint::default()))
+            .collect::<HashMap<_, _>>();
+
+        let mut only = select(records.clone(), &["k9", "matrix"], &[])
+            .into_keys()
+            .collect::<Vec<_>>();
+        only.sort();
+        assert_eq!(only, vec!["k9", "matrix"]);
+
+        let mut rest = select(records.clone(), &[], &["k9"])
+            .into_keys()
+            .collect::<Vec<_>>();
+        rest.sort();
+        assert_eq!(rest, vec!["gst", "matrix"]);
+
+        assert!(select(records, &["k9"], &["k9"]).is_empty());
+    }
+}
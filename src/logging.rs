@@ -0,0 +1,60 @@
+//! Log targets per subsystem, so one can be turned down without losing the others.
+//!
+//! Resolution logs go to `siblings::resolve`, background refresh and invalidation to
+//! `siblings::refresh`, and readiness, throttling and reporting to `siblings::health`. Filter them
+//! in the logger (`RUST_LOG=siblings::refresh=warn`) or with [`Siblings::with_log_level`], which
+//! applies before the logger sees anything. Everything else keeps the module path target.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{Level, LevelFilter};
+
+use crate::Siblings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTarget {
+    Resolve,
+    Refresh,
+    Health,
+}
+
+impl LogTarget {
+    pub const fn target(self) -> &'static str {
+        match self {
+            Self::Resolve => "siblings::resolve",
+            Self::Refresh => "siblings::refresh",
+            Self::Health => "siblings::health",
+        }
+    }
+
+    pub(crate) fn enabled(self, level: Level) -> bool {
+        level as usize <= LEVELS[self as usize].load(Ordering::Relaxed)
+    }
+}
+
+/// Most verbose level let through per [`LogTarget`], everything by default
+static LEVELS: [AtomicUsize; 3] = [const { AtomicUsize::new(LevelFilter::Trace as usize) }; 3];
+
+impl Siblings {
+    /// Drops logs of `target` more verbose than `level`, e.g. `(LogTarget::Refresh, Warn)` to
+    /// silence routine refreshes. Applies to every instance in the process, like log targets do.
+    pub fn with_log_level(self, target: LogTarget, level: LevelFilter) -> Self {
+        LEVELS[target as usize].store(level as usize, Ordering::Relaxed);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turned_down_target() {
+        let _sib = Siblings::sidecar("/nonexistent.sock", None)
+            .with_log_level(LogTarget::Health, LevelFilter::Warn);
+
+        assert!(LogTarget::Health.enabled(Level::Warn));
+        assert!(!LogTarget::Health.enabled(Level::Info));
+        assert!(LogTarget::Resolve.enabled(Level::Debug));
+    }
+}
@@ -1,41 +1,413 @@
-use std::{collections::HashMap, env, fs::read_to_string};
+use std::{
+    cmp::Reverse,
+    env,
+    fs::read_to_string,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Result;
 use log::info;
+use siblings::{lint, loader, parse_duration, parse_siblings_file, Env, KeyScheme, Siblings};
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
 
-    load().await.unwrap();
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["consumers", sibling, ..] => consumers(sibling).await.unwrap(),
+        ["unused", ..] => unused(&args).await.unwrap(),
+        ["load-all", file, "--envs", envs, ..] => load_all(file, envs).await.unwrap(),
+        ["load-all", file, ..] => load_all(file, "prod,dev").await.unwrap(),
+        ["diff", ..] => diff().await.unwrap(),
+        ["lint", ..] => lint().unwrap(),
+        ["diff-envs", a, b, ..] => diff_envs(a, b).await.unwrap(),
+        ["list", ..] => list().await.unwrap(),
+        ["health", "--publish", ..] => health(true).await.unwrap(),
+        ["health", ..] => health(false).await.unwrap(),
+        ["explain", sibling, region, ..] => explain(sibling, Some(region)).await.unwrap(),
+        ["explain", sibling, ..] => explain(sibling, None).await.unwrap(),
+        ["replicate", targets, ..] => replicate(targets).await.unwrap(),
+        ["sandbox", name, "load", file, ..] => sandbox_load(name, file).await.unwrap(),
+        ["sandbox", name, "clean", ..] => sandbox_clean(name).await.unwrap(),
+        ["disable", sibling, ..] => set_enabled(sibling, false).await.unwrap(),
+        ["enable", sibling, ..] => set_enabled(sibling, true).await.unwrap(),
+        ["remove", sibling, ..] => remove(sibling).await.unwrap(),
+        ["wait", ..] => wait(&args).await.unwrap(),
+        ["load", ..] => load(list_flag(&args, "--only"), list_flag(&args, "--exclude"))
+            .await
+            .unwrap(),
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    }
+}
+
+const USAGE: &str = "usage: siblings-cli <command>, with X_ENV naming the env (prod when unset)
+  load [--only a,b] [--exclude c]    publish the siblings file of X_ENV
+  load-all <file> [--envs prod,dev]  publish one file describing every env
+  diff | list | lint                 compare, list or check the records of X_ENV
+  diff-envs <a> <b>                  siblings and urls that differ between two envs
+  consumers <sibling>                services that reported resolving a sibling
+  unused [--since 30d]               records nobody resolved lately
+  explain <sibling> [region]         how a sibling resolves
+  health [--publish]                 check every published sibling is reachable
+  replicate <targets.json>           load into several Redis targets
+  sandbox <name> load <file> | sandbox <name> clean
+  disable <sibling> | enable <sibling> | remove <sibling>
+  wait --sibling <s> --version <v> [--timeout 2m] [--fresh 5m]";
+
+/// Value of `--flag value`
+fn flag<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
 }
 
-async fn load() -> Result<()> {
-    let isdev = env::var("X_ENV").map_or(false, |e| e == "dev");
+/// Comma separated values of `--flag a,b`
+fn list_flag<'a>(args: &'a [String], name: &str) -> Vec<&'a str> {
+    flag(args, name).map_or_else(Vec::new, |v| {
+        v.split(',').filter(|s| !s.trim().is_empty()).collect()
+    })
+}
+
+/// `X_ENV`, prod when unset
+fn env() -> Env {
+    env::var("X_ENV").map_or(Env::Prod, |e| Env::from_name(&e))
+}
 
-    info!("Loading data for {}", if isdev { "dev" } else { "prod" });
+/// Siblings reading and writing the keys of `env`, laid out per [`KeyScheme::from_env`]; every
+/// env but prod shares the dev Redis, unless `X_REDIS_CLUSTER` lists the seed nodes of a Redis
+/// Cluster
+async fn connect(env: Env) -> Result<Siblings> {
+    let keys = KeyScheme::from_env()?;
 
-    let db = db::Db::new().await?;
-    let data = serde_json::from_str::<HashMap<String, HashMap<String, String>>>(
-        read_to_string(if isdev {
-            "siblings-dev.json"
-        } else {
-            "siblings.json"
-        })?
-        .as_str(),
-    )?;
+    #[cfg(feature = "cluster")]
+    if let Ok(nodes) = env::var("X_REDIS_CLUSTER") {
+        let nodes = nodes.split(',').map(str::trim).collect::<Vec<_>>();
+        return Ok(Siblings::connect_cluster(&nodes, None)
+            .await?
+            .with_env(env)
+            .with_key_scheme(keys));
+    }
+
+    let db = Arc::new(db::Db::connect_redis(!env.is_prod()).await?);
+    Ok(Siblings::new(db, None)
+        .await
+        .with_env(env)
+        .with_key_scheme(keys))
+}
+
+fn siblings_file(env: &Env) -> String {
+    match env {
+        Env::Prod => "siblings.json".to_string(),
+        env => format!("siblings-{}.json", env.name()),
+    }
+}
 
-    for (k, v) in data.iter() {
-        let b = serde_json::to_vec(v)?;
+/// Prints every consumer that reported resolving `sibling`, most recent first
+async fn consumers(sibling: &str) -> Result<()> {
+    let siblings = connect(env()).await?;
+
+    let mut consumers = siblings
+        .consumers(sibling)
+        .await?
+        .into_iter()
+        .collect::<Vec<_>>();
+    consumers.sort_by_key(|(_, at)| Reverse(*at));
+
+    if consumers.is_empty() {
+        println!("no consumers reported for {sibling}");
+    }
+    for (me, at) in consumers {
+        let ago = SystemTime::now().duration_since(at).unwrap_or_default();
+        let at = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        println!("{me}\tlast seen {at} ({}h ago)", ago.as_secs() / 3600);
+    }
+    Ok(())
+}
+
+/// Prints the records in the siblings file nobody reported resolving in the last `--since`
+/// (default 30d; `--days 30` still works)
+async fn unused(args: &[String]) -> Result<()> {
+    let since = match (flag(args, "--since"), flag(args, "--days")) {
+        (Some(since), _) => since.to_owned(),
+        (None, Some(days)) => format!("{days}d"),
+        (None, None) => "30d".to_owned(),
+    };
+    let window = parse_duration("--since", &since)?;
+
+    let env = env();
+    let data = parse_siblings_file(&read_to_string(siblings_file(&env))?, &env)?;
+    let siblings = connect(env).await?;
+
+    let mut names = data.keys().map(String::as_str).collect::<Vec<_>>();
+    names.sort();
+
+    let unused = siblings.unused(&names, window).await?;
+    if unused.is_empty() {
+        println!("every sibling was resolved in the last {since}");
+    }
+    for sibling in unused {
+        println!("{sibling}");
+    }
+    Ok(())
+}
+
+/// Publishes the siblings file, or just the records picked by `--only` / `--exclude`
+async fn load(only: Vec<&str>, exclude: Vec<&str>) -> Result<()> {
+    let env = env();
+    let file = siblings_file(&env);
+
+    info!("Loading data for {} from {file}", env.name());
+
+    let records = parse_siblings_file(&read_to_string(&file)?, &env)?;
+    let records = loader::select(records, &only, &exclude);
+    let siblings = connect(env).await?;
+    let diff = loader::load_map(&siblings, records).await?;
+    info!("Loaded: added {:?} changed {:?}", diff.added, diff.changed);
+    Ok(())
+}
+
+/// Prints what loading the siblings file would change, without writing
+async fn diff() -> Result<()> {
+    let env = env();
+    let records = parse_siblings_file(&read_to_string(siblings_file(&env))?, &env)?;
+    let siblings = connect(env).await?;
+
+    let diff = loader::diff(&siblings, &records).await?;
+    for (change, siblings) in [("+", diff.added), ("~", diff.changed), ("-", diff.removed)] {
+        for sibling in siblings {
+            println!("{change} {sibling}");
+        }
+    }
+    Ok(())
+}
+
+/// Prints what the lint rules find in the siblings file, failing when any record has an error
+fn lint() -> Result<()> {
+    let env = env();
+    let records = parse_siblings_file(&read_to_string(siblings_file(&env))?, &env)?;
+
+    let mut names = records.keys().collect::<Vec<_>>();
+    names.sort();
+    let mut errors = 0;
+    for sibling in names {
+        for finding in lint::check(&records[sibling]) {
+            if finding.severity == lint::Severity::Error {
+                errors += 1;
+            }
+            println!(
+                "{sibling}\t{:?}\t{}\t{}: {}",
+                finding.severity, finding.rule, finding.field, finding.message
+            );
+        }
+    }
+
+    if errors > 0 {
+        anyhow::bail!("{errors} errors in the siblings file");
+    }
+    Ok(())
+}
+
+/// Prints every sibling published in `X_ENV` with its default url, version and latency class
+async fn list() -> Result<()> {
+    let siblings = connect(env()).await?;
+
+    for (sibling, record) in siblings.list_siblings().await? {
+        let version = record.version().map_or("-".to_string(), |v| v.to_string());
+        let latency = record
+            .cost(None)
+            .and_then(|c| c.latency)
+            .map_or("-", |l| l.name());
+        println!(
+            "{sibling}\t{}\tversion {version}\tlatency {latency}",
+            record.get_in(None).unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Probes every published sibling and prints how each fared; with `publish`, writes the snapshot
+/// to Redis for dashboards and consumers
+async fn health(publish: bool) -> Result<()> {
+    let siblings = connect(env()).await?;
+    let published = siblings.list_siblings().await?;
+    let names = published.keys().map(String::as_str).collect::<Vec<_>>();
+
+    let snapshot = siblings.check_health(&names).await;
+    for (sibling, health) in &snapshot.siblings {
+        println!(
+            "{sibling}\t{:?}\t{}ms\t{}",
+            health.status, health.took_ms, health.detail
+        );
+    }
+    if publish {
+        siblings.publish_health(&snapshot).await?;
+    }
+    Ok(())
+}
+
+/// Prints the url `sibling` resolves to in `region` and the latency and cost noted for it
+async fn explain(sibling: &str, region: Option<&str>) -> Result<()> {
+    let siblings = connect(env()).await?;
+    let explained = siblings.explain(sibling, region).await?;
+
+    println!(
+        "{} in {}: {}",
+        explained.sibling,
+        explained.region.as_deref().unwrap_or("default"),
+        explained.url.as_deref().unwrap_or("not published")
+    );
+    let cost = explained.cost.unwrap_or_default();
+    println!(
+        "latency {}\tcost {}",
+        cost.latency.map_or("-", |l| l.name()),
+        cost.cost.as_deref().unwrap_or("-")
+    );
+    Ok(())
+}
+
+/// Publishes the records in `file` to sandbox `name`, over dev
+async fn sandbox_load(name: &str, file: &str) -> Result<()> {
+    let siblings = connect(Env::Dev).await?.with_sandbox(name);
+    let diff = loader::load_file(&siblings, file).await?;
+    println!(
+        "sandbox {name}: added {:?} changed {:?}",
+        diff.added, diff.changed
+    );
+    Ok(())
+}
+
+/// Removes every record of sandbox `name`
+async fn sandbox_clean(name: &str) -> Result<()> {
+    let siblings = connect(Env::Dev).await?.with_sandbox(name);
+    let removed = loader::clean(&siblings).await?;
+    println!("sandbox {name}: removed {removed:?}");
+    Ok(())
+}
+
+/// Disables or re-enables `sibling` in `X_ENV`, keeping its record
+async fn set_enabled(sibling: &str, enabled: bool) -> Result<()> {
+    let siblings = connect(env()).await?;
+
+    let version = if enabled {
+        siblings.enable(sibling).await?
+    } else {
+        siblings.disable(sibling).await?
+    };
+    println!("{sibling}\tenabled {enabled}\tversion {version}");
+    Ok(())
+}
+
+/// Deletes the live record of `sibling` in `X_ENV`, for decommissioning it; archived versions stay
+async fn remove(sibling: &str) -> Result<()> {
+    let siblings = connect(env()).await?;
+
+    siblings.remove_endpoint(sibling, true).await?;
+    println!("{sibling}\tremoved from {}", env().name());
+    Ok(())
+}
+
+/// Prints the siblings only one of envs `a` and `b` publishes and the urls that differ
+async fn diff_envs(a: &str, b: &str) -> Result<()> {
+    let (a, b) = (Env::from_name(a), Env::from_name(b));
+    let diff = connect(a).await?.diff_with(&connect(b).await?).await?;
+
+    for sibling in &diff.only_in_a {
+        println!("only in {}\t{sibling}", diff.a);
+    }
+    for sibling in &diff.only_in_b {
+        println!("only in {}\t{sibling}", diff.b);
+    }
+    for url in &diff.urls {
+        println!(
+            "{}[{}]\t{}: {}\t{}: {}",
+            url.sibling,
+            url.region,
+            diff.a,
+            url.a.as_deref().unwrap_or("-"),
+            diff.b,
+            url.b.as_deref().unwrap_or("-")
+        );
+    }
+
+    if !diff.same_siblings() {
+        anyhow::bail!("{} and {} publish different siblings", diff.a, diff.b);
+    }
+    Ok(())
+}
+
+/// Loads the siblings file into every Redis listed in `targets`, in one run
+async fn replicate(targets: &str) -> Result<()> {
+    let env = env();
+    let records = parse_siblings_file(&read_to_string(siblings_file(&env))?, &env)?;
+    let targets = loader::targets_file(targets)?;
+
+    let mut failed = 0;
+    for report in loader::replicate(&targets, &env, &KeyScheme::from_env()?, &records).await {
+        match report.result {
+            Ok(diff) => println!(
+                "{}\tok\tadded {:?} changed {:?}",
+                report.target, diff.added, diff.changed
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("{}\tfailed\t{e}", report.target);
+            }
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} targets failed", targets.len());
+    }
+    Ok(())
+}
+
+/// Waits until every consumer reporting versions holds `--version` of `--sibling`, failing after
+/// `--timeout` (default 2m). Consumers that didn't report within `--fresh` (default 5m) are
+/// considered gone.
+async fn wait(args: &[String]) -> Result<()> {
+    let (Some(sibling), Some(version)) = (flag(args, "--sibling"), flag(args, "--version")) else {
+        anyhow::bail!("usage: wait --sibling k9 --version 42 [--timeout 2m] [--fresh 5m]");
+    };
+    let version = version.parse()?;
+    let timeout = parse_duration("--timeout", flag(args, "--timeout").unwrap_or("2m"))?;
+    let fresh = parse_duration("--fresh", flag(args, "--fresh").unwrap_or("5m"))?;
+
+    let siblings = connect(env()).await?;
+    let propagation = siblings
+        .wait_for_version(sibling, version, fresh, Duration::from_secs(5), timeout)
+        .await?;
+
+    for me in &propagation.updated {
+        println!("{me}\tversion >= {version}");
+    }
+    for (me, held) in &propagation.lagging {
+        println!("{me}\tversion {held}");
+    }
+    if !propagation.is_complete() {
+        anyhow::bail!(
+            "{} consumers still behind version {version} of {sibling}",
+            propagation.lagging.len()
+        );
+    }
+    Ok(())
+}
 
-        let key = if isdev {
-            format!("dev-ep-{k}")
-        } else {
-            format!("ep-{k}")
-        };
+/// Expands one file describing every env and publishes the records of each of `envs`
+/// (`prod,dev,staging`)
+async fn load_all(file: &str, envs: &str) -> Result<()> {
+    for env in envs.split(',').filter(|e| !e.trim().is_empty()) {
+        let env = Env::from_name(env);
+        info!("Loading data for {} from {file}", env.name());
 
-        info!("Setting: Key: {key} Value: {v:?}");
-        db.set_cache(&key, &b[..], None).await?;
+        let siblings = connect(env).await?;
+        let diff = loader::load_file(&siblings, file).await?;
+        info!("Loaded: added {:?} changed {:?}", diff.added, diff.changed);
     }
     Ok(())
 }
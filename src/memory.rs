@@ -0,0 +1,153 @@
+//! An in-memory [`EndpointSource`] for tests, behind the `test-util` feature.
+//!
+//! Downstream services unit-test code taking a [`Siblings`] without a Redis, building it with
+//! `MemorySource::new(Env::Dev).with_url("k9", "http://localhost:9000").siblings(None)`.
+//! Records put in the source after the `Siblings` was built show up once it re-reads them, e.g.
+//! after [`Siblings::flush`] or [`Siblings::refresh`]. [`Siblings::mock`] and
+//! [`Siblings::mock_json`] are the shortcut when the records are known upfront.
+//!
+//! These instances resolve from the source alone: `SIBLING_<NAME>_URL` overrides, `X_SIBLINGS_PINS`
+//! and `X_SANDBOX` set in the test's environment don't apply to them.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Result;
+
+use crate::{
+    parse_siblings_file, Backend, EndpointSource, Env, FetchFuture, RegionEndpoint, Siblings,
+};
+
+/// Records of one env by sibling, shared between clones
+#[derive(Debug, Clone)]
+pub struct MemorySource {
+    env: Env,
+    records: Arc<RwLock<HashMap<String, RegionEndpoint>>>,
+}
+
+impl MemorySource {
+    pub fn new(env: Env) -> Self {
+        Self {
+            env,
+            records: Arc::default(),
+        }
+    }
+
+    /// Serves `sibling` at `url` in every region
+    pub fn with_url(self, sibling: &str, url: &str) -> Self {
+        let record = RegionEndpoint {
+            default: url.into(),
+            ..Default::default()
+        };
+        self.with_record(sibling, record)
+    }
+
+    pub fn with_record(self, sibling: &str, record: RegionEndpoint) -> Self {
+        self.insert(sibling, record);
+        self
+    }
+
+    pub fn insert(&self, sibling: &str, record: RegionEndpoint) {
+        self.records
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(sibling.to_owned(), record);
+    }
+
+    /// `false` when `sibling` had no record
+    pub fn remove(&self, sibling: &str) -> bool {
+        self.records
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(sibling)
+            .is_some()
+    }
+
+    /// A [`Siblings`] of this source's env reading from it alone
+    pub fn siblings(&self, me: Option<&str>) -> Siblings {
+        Siblings::unlayered(
+            Backend::Source(Arc::new(self.clone())),
+            me,
+            self.env.clone(),
+        )
+    }
+}
+
+impl Siblings {
+    /// A dev instance resolving only from `records`, by sibling name
+    pub fn mock(records: HashMap<String, RegionEndpoint>) -> Self {
+        let source = MemorySource::new(Env::Dev);
+        for (sibling, record) in records {
+            source.insert(&sibling, record);
+        }
+        source.siblings(None)
+    }
+
+    /// [`Self::mock`] of records laid out as in siblings.json, `_defaults` and `_env` included
+    pub fn mock_json(json: &str) -> Result<Self> {
+        Ok(Self::mock(parse_siblings_file(json, &Env::Dev)?))
+    }
+}
+
+impl EndpointSource for MemorySource {
+    fn fetch<'a>(&'a self, env: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        let record = (*env == self.env && pinned.is_none())
+            .then(|| {
+                self.records
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get(sibling)
+                    .cloned()
+            })
+            .flatten();
+        Box::pin(async move { Ok(record) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_tests_without_redis() -> anyhow::Result<()> {
+        let source = MemorySource::new(Env::Staging).with_url("k9", "http://localhost:9000");
+        let siblings = source.siblings(Some("credit"));
+
+        assert_eq!(
+            siblings.try_sibling("k9", Some("IN")).await?.as_deref(),
+            Some("http://localhost:9000")
+        );
+        assert_eq!(siblings.try_sibling("matrix", None).await?, None);
+
+        source.insert(
+            "matrix",
+            Siblings::deserialize(br#"{"default":"http://localhost:9001"}"#.to_vec())?,
+        );
+        assert!(source.remove("k9"));
+        siblings.flush().await;
+        assert_eq!(
+            siblings.try_sibling("matrix", None).await?.as_deref(),
+            Some("http://localhost:9001")
+        );
+        assert_eq!(siblings.try_sibling("k9", None).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mocks_from_json() -> anyhow::Result<()> {
+        let siblings = Siblings::mock_json(
+            r#"{"_defaults": {"us": "https://us.example.com"}, "k9": {"default": "https://k9"}}"#,
+        )?;
+
+        assert_eq!(
+            siblings.try_sibling("k9", Some("US")).await?.as_deref(),
+            Some("https://us.example.com")
+        );
+        assert_eq!(siblings.try_sibling("matrix", None).await?, None);
+
+        Ok(())
+    }
+}
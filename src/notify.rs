@@ -0,0 +1,198 @@
+//! Change notifications for systems outside Redis.
+//!
+//! Every [`ChangeSink`] added with [`Siblings::with_change_sink`] is told about each record
+//! [`Siblings::publish`] writes and each one [`loader::prune`](crate::loader::prune) removes.
+//! A failing sink is logged and never fails the publish. [`WebhookSink`] (feature `notify`) POSTs
+//! the event as JSON; a GCP Pub/Sub topic is one `ChangeSink` away with the service's own
+//! Pub/Sub client, or reachable through a push bridge with a webhook.
+//!
+//! Every event is also PUBLISHed as JSON on the Redis channel `siblings:updated`, which
+//! [`Siblings::subscribe_updates`] follows where keyspace notifications are off.
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{cache, Siblings};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Published,
+    Removed,
+}
+
+/// What changed, as sinks receive it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub env: String,
+    pub sibling: String,
+    pub kind: ChangeKind,
+    /// Version now live, `None` for removals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+pub type NotifyFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+pub trait ChangeSink: Send + Sync {
+    fn notify<'a>(&'a self, event: &'a ChangeEvent) -> NotifyFuture<'a>;
+}
+
+impl Siblings {
+    /// Tells `sink` about every change this instance publishes
+    pub fn with_change_sink(mut self, sink: impl ChangeSink + 'static) -> Self {
+        self.sinks.push(Arc::new(sink));
+        self
+    }
+
+    pub(crate) async fn notify(&self, event: ChangeEvent) {
+        self.announce(&event).await;
+
+        for sink in &self.sinks {
+            if let Err(e) = sink.notify(&event).await {
+                warn!(
+                    "notify: sibling[{}] {:?} not delivered: {e}",
+                    event.sibling, event.kind
+                );
+            }
+        }
+    }
+
+    /// PUBLISHes `event` on [`KeyScheme::channel`](crate::KeyScheme::channel)
+    async fn announce(&self, event: &ChangeEvent) {
+        let Some(conn) = self.backend.conn() else {
+            return;
+        };
+
+        let sent = match serde_json::to_vec(event) {
+            Ok(message) => cache::publish(conn, &self.keys.channel(), &message).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = sent {
+            warn!("notify: sibling[{}] not announced: {e}", event.sibling);
+        }
+    }
+}
+
+/// How long a [`WebhookSink`] waits for the webhook to answer before giving up on an event
+#[cfg(feature = "notify")]
+pub const DEFAULT_WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// POSTs each event as JSON to a plain `http://` url, e.g. an internal relay. Publishing waits
+/// for the webhook, at most [`DEFAULT_WEBHOOK_TIMEOUT`] unless changed with `with_timeout`.
+#[cfg(feature = "notify")]
+#[derive(Debug, Clone)]
+pub struct WebhookSink {
+    url: hyper::Uri,
+    timeout: std::time::Duration,
+}
+
+#[cfg(feature = "notify")]
+impl WebhookSink {
+    pub fn new(url: &str) -> Result<Self> {
+        let url = url.parse::<hyper::Uri>()?;
+        if url.scheme_str() != Some("http") || url.host().is_none() {
+            anyhow::bail!("webhook sink needs an http:// url, got {url}");
+        }
+
+        Ok(Self {
+            url,
+            timeout: DEFAULT_WEBHOOK_TIMEOUT,
+        })
+    }
+
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn deliver(&self, event: &ChangeEvent) -> Result<()> {
+        tokio::time::timeout(self.timeout, self.post(event))
+            .await
+            .map_err(|_| anyhow::anyhow!("{} didn't answer within {:?}", self.url, self.timeout))?
+    }
+
+    async fn post(&self, event: &ChangeEvent) -> Result<()> {
+        use http_body_util::Full;
+        use hyper::{body::Bytes, header, Request};
+        use hyper_util::rt::TokioIo;
+
+        let host = self.url.host().unwrap_or_default();
+        let port = self.url.port_u16().unwrap_or(80);
+        let stream = tokio::net::TcpStream::connect((host, port)).await?;
+        let (mut sender, conn) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(conn);
+
+        let path = self.url.path_and_query().map_or("/", |p| p.as_str());
+        let req = Request::post(path)
+            .header(header::HOST, format!("{host}:{port}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(serde_json::to_vec(event)?)))?;
+        let status = sender.send_request(req).await?.status();
+        if !status.is_success() {
+            anyhow::bail!("{} answered {status}", self.url);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "notify")]
+impl ChangeSink for WebhookSink {
+    fn notify<'a>(&'a self, event: &'a ChangeEvent) -> NotifyFuture<'a> {
+        Box::pin(self.deliver(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_json() -> Result<()> {
+        let event = ChangeEvent {
+            env: "prod".to_string(),
+            sibling: "k9".to_string(),
+            kind: ChangeKind::Removed,
+            version: None,
+            checksum: None,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&event)?,
+            r#"{"env":"prod","sibling":"k9","kind":"removed"}"#
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "notify")]
+    #[tokio::test]
+    async fn slow_webhooks_time_out() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/siblings", listener.local_addr()?);
+        // accepts and never answers
+        tokio::spawn(async move {
+            let _held = listener.accept().await;
+            std::future::pending::<()>().await;
+        });
+
+        let sink = WebhookSink::new(&url)?.with_timeout(std::time::Duration::from_millis(50));
+        let event = ChangeEvent {
+            env: "prod".to_string(),
+            sibling: "k9".to_string(),
+            kind: ChangeKind::Published,
+            version: Some(2),
+            checksum: None,
+        };
+
+        let err = sink.notify(&event).await.unwrap_err();
+        assert!(err.to_string().contains("didn't answer"));
+        Ok(())
+    }
+}
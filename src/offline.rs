@@ -0,0 +1,59 @@
+//! Resolving without Redis.
+//!
+//! Batch jobs running where Redis isn't reachable build `Siblings` from `siblings.json` with
+//! [`Siblings::from_file`], or from records at hand with [`Siblings::from_records`]. Lookups
+//! answer from those records (and overrides) only and never touch the network; operations that
+//! need Redis (publishing, listing, webhooks) fail with [`SiblingsError::Unsupported`]. Building
+//! without the `db` feature leaves out the `db` crate's pool and [`Siblings::new`] altogether.
+//!
+//! [`SiblingsError::Unsupported`]: crate::SiblingsError::Unsupported
+
+use std::{collections::HashMap, fs::read_to_string, path::Path};
+
+use anyhow::Result;
+
+use crate::{parse_siblings_file, Backend, RegionEndpoint, Siblings, Source};
+
+impl Siblings {
+    /// Resolves from `records` only, by sibling name, never reading Redis
+    pub fn from_records(records: HashMap<String, RegionEndpoint>, me: Option<&str>) -> Self {
+        Self::with_backend(Backend::Static, me)
+            .with_precedence(&[Source::Overrides, Source::LocalFile])
+            .with_local_records(records.into_iter().collect())
+    }
+
+    /// [`Self::from_records`] of the siblings file at `path`: the records of `X_ENV`, with
+    /// `_defaults` and env overrides applied
+    pub fn from_file(path: impl AsRef<Path>, me: Option<&str>) -> Result<Self> {
+        let env = crate::Env::new_from_env();
+        let records = parse_siblings_file(&read_to_string(path)?, &env)?;
+
+        Ok(Self::from_records(records, me))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SiblingsError;
+
+    #[tokio::test]
+    async fn resolves_without_redis() -> anyhow::Result<()> {
+        let k9 =
+            Siblings::deserialize(br#"{"default":"https://k9","in":"https://k9.in"}"#.to_vec())?;
+        let sib = Siblings::from_records(HashMap::from([("k9".to_string(), k9)]), Some("k9"));
+
+        assert_eq!(
+            sib.try_sibling("k9", Some("IN")).await?.as_deref(),
+            Some("https://k9.in")
+        );
+        assert_eq!(sib.try_me(None).await?.as_deref(), Some("https://k9"));
+        assert_eq!(sib.try_sibling("matrix", None).await?, None);
+        assert!(matches!(
+            sib.list_siblings().await,
+            Err(SiblingsError::Unsupported { .. })
+        ));
+
+        Ok(())
+    }
+}
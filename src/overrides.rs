@@ -0,0 +1,138 @@
+//! Records set in memory by hand.
+//!
+//! [`Siblings::set_endpoint`] answers every lookup of a sibling with the record given, without
+//! writing Redis, for integration tests and for redirecting traffic in an emergency from an admin
+//! handler. An override never expires and outlives refreshes and [`Siblings::flush`], until
+//! [`Siblings::clear_endpoint`] lifts it. It only affects this process.
+//!
+//! A pod's routing can also be patched from its environment: `SIBLING_K9_URL=http://10.0.0.5:9000`
+//! overrides `k9` (`SIBLING_BANK_STATEMENT_URL` overrides `bank-statement`) from the moment the
+//! instance is built, ahead of Redis and of `svc.env`.
+
+use std::env;
+
+use crate::{update, RegionEndpoint, Siblings};
+
+impl Siblings {
+    /// Answers lookups of `sibling` with `ep` until cleared, whatever Redis holds
+    pub fn set_endpoint(&self, sibling: &str, ep: RegionEndpoint) {
+        log_to!(
+            Refresh,
+            Warn,
+            "override: sibling[{sibling}] now resolves to {}",
+            ep.get_arc(None)
+        );
+        update(&self.endpoints, |endpoints| {
+            endpoints.overrides.insert(sibling.to_owned(), ep.clone());
+        });
+        self.record_read(sibling, Some(&ep));
+    }
+
+    /// Lifts the override of `sibling`, back to the record loaded if any; `false` when there was
+    /// none
+    pub fn clear_endpoint(&self, sibling: &str) -> bool {
+        let mut cleared = false;
+        update(&self.endpoints, |endpoints| {
+            cleared = endpoints.overrides.remove(sibling).is_some();
+        });
+        if cleared {
+            log_to!(Refresh, Warn, "override: sibling[{sibling}] cleared");
+            self.record_read(sibling, self.endpoints.load().get(sibling));
+        }
+
+        cleared
+    }
+
+    /// Overrides from the `SIBLING_<NAME>_URL` variables in the environment
+    pub(crate) fn with_url_overrides_from_env(self) -> Self {
+        self.with_url_overrides(env::vars())
+    }
+
+    fn with_url_overrides(self, vars: impl Iterator<Item = (String, String)>) -> Self {
+        for (var, url) in vars {
+            let url = url.trim();
+            if let Some(sibling) = sibling_of_var(&var)
+                && !url.is_empty()
+            {
+                let ep = RegionEndpoint {
+                    default: url.into(),
+                    ..Default::default()
+                };
+                self.set_endpoint(&sibling, ep);
+            }
+        }
+        self
+    }
+
+    /// Siblings answered by an override
+    pub fn overridden(&self) -> Vec<String> {
+        let mut siblings = self
+            .endpoints
+            .load()
+            .overrides
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        siblings.sort();
+        siblings
+    }
+}
+
+/// `bank-statement` for `SIBLING_BANK_STATEMENT_URL`
+fn sibling_of_var(var: &str) -> Option<String> {
+    let name = var.strip_prefix("SIBLING_")?.strip_suffix("_URL")?;
+    (!name.is_empty()).then(|| name.to_lowercase().replace('_', "-"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn overrides_win_until_cleared() -> anyhow::Result<()> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let loaded = Siblings::deserialize(br#"{"default":"https://k9"}"#.to_vec())?;
+        let redirect = Siblings::deserialize(br#"{"default":"https://k9-dr"}"#.to_vec())?;
+        update(&sib.endpoints, |e| e.insert("k9", loaded.clone()));
+
+        sib.set_endpoint("k9", redirect.clone());
+        update(&sib.endpoints, |e| e.insert("k9", loaded.clone()));
+        sib.flush().await;
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("https://k9-dr")
+        );
+        assert_eq!(sib.overridden(), ["k9"]);
+
+        assert!(sib.clear_endpoint("k9"));
+        assert!(!sib.clear_endpoint("k9"));
+        assert_eq!(sib.peek("k9", None), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn url_variables_override() -> anyhow::Result<()> {
+        let vars = [
+            ("SIBLING_K9_URL", "http://10.0.0.5:9000"),
+            ("SIBLING_BANK_STATEMENT_URL", " http://10.0.0.6 "),
+            ("SIBLING_MATRIX_URL", ""),
+            ("SIBLING__URL", "http://nobody"),
+            ("X_ENV", "dev"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let sib = Siblings::sidecar("/nonexistent.sock", None).with_url_overrides(vars.into_iter());
+
+        assert_eq!(sib.overridden(), ["bank-statement", "k9"]);
+        assert_eq!(
+            sib.try_sibling("k9", Some("in")).await?.as_deref(),
+            Some("http://10.0.0.5:9000")
+        );
+        assert_eq!(
+            sib.peek("bank-statement", None).as_deref(),
+            Some("http://10.0.0.6")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+//! Per-consumer version pins.
+//!
+//! Every version [`Siblings::publish`] writes is also archived under `ep-{sibling}@{version}`;
+//! a pinned sibling resolves from that archive instead of the live record until the pin is lifted.
+//! Pins come from `X_SIBLINGS_PINS` (`k9=41,matrix=7`) or [`Siblings::pin`] at runtime.
+
+use std::{collections::HashMap, env};
+
+use crate::{update, Siblings};
+
+impl Siblings {
+    /// Pins `sibling` to `version`, dropping whatever is cached for it
+    pub async fn pin(&self, sibling: &str, version: u64) {
+        info!("pin: sibling[{sibling}] pinned to version[{version}]");
+        self.pins.write().await.insert(sibling.to_owned(), version);
+        update(&self.endpoints, |e| e.remove(sibling));
+    }
+
+    /// Lifts the pin on `sibling`, the next lookup reads the live record
+    pub async fn unpin(&self, sibling: &str) {
+        if self.pins.write().await.remove(sibling).is_some() {
+            info!("pin: sibling[{sibling}] unpinned");
+            update(&self.endpoints, |e| e.remove(sibling));
+        }
+    }
+
+    pub async fn pinned(&self, sibling: &str) -> Option<u64> {
+        self.pins.read().await.get(sibling).copied()
+    }
+}
+
+pub(crate) fn pins_from_env() -> HashMap<String, u64> {
+    let Ok(pins) = env::var("X_SIBLINGS_PINS") else {
+        return HashMap::new();
+    };
+
+    pins.split(',')
+        .filter(|p| !p.trim().is_empty())
+        .filter_map(|p| {
+            let pin = p
+                .split_once('=')
+                .and_then(|(s, v)| Some((s.trim().to_string(), v.trim().parse().ok()?)));
+            if pin.is_none() {
+                warn!("pin: ignoring invalid pin `{p}` in X_SIBLINGS_PINS");
+            }
+            pin
+        })
+        .collect()
+}
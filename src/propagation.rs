@@ -0,0 +1,152 @@
+//! Verifying that a published record reached the running consumers.
+//!
+//! With [`Siblings::report_usage`] on, every flush writes the version of each record a consumer
+//! holds into the hash `seen-{sibling}` as `{me} -> {version}@{unix seconds}`.
+//! [`Siblings::wait_for_version`] polls those reports until every consumer that reported recently
+//! holds the wanted version, which `siblings-cli wait` runs in deploy pipelines.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{budget, cache, Siblings, SiblingsError};
+
+/// Which consumers hold a wanted record version
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Propagation {
+    /// Consumers holding the wanted version or a later one
+    pub updated: Vec<String>,
+    /// Consumers still on an older version, with that version
+    pub lagging: Vec<(String, u64)>,
+}
+
+impl Propagation {
+    /// `true` when no recently reporting consumer is behind
+    pub fn is_complete(&self) -> bool {
+        self.lagging.is_empty()
+    }
+}
+
+impl Siblings {
+    /// Writes the version of every record in memory under `me`
+    pub(crate) async fn report_versions(&self, me: &str, at: u64) {
+        let held = self
+            .endpoints
+            .load()
+            .iter()
+            .filter_map(|(sibling, ep)| Some((sibling.to_owned(), ep.version()?)))
+            .collect::<Vec<_>>();
+
+        let Some(conn) = self.backend.conn() else {
+            return;
+        };
+        for (sibling, version) in held {
+            let key = self.cache_key(&self.keys.seen(&sibling));
+            if let Err(e) = cache::hset(conn, &key, me, &format!("{version}@{at}")).await {
+                log_to!(
+                    Health,
+                    Warn,
+                    "propagation: reporting sibling[{sibling}] for consumer[{me}] failed: {e}"
+                );
+            }
+        }
+    }
+
+    /// Versions of `sibling` consumers reported holding within the last `fresh`
+    pub async fn observed(
+        &self,
+        sibling: &str,
+        fresh: Duration,
+    ) -> Result<HashMap<String, u64>, SiblingsError> {
+        let key = self.cache_key(&self.keys.seen(sibling));
+        let conn = self.backend.redis("version reports")?;
+        budget::acquire(&key)?;
+        let reported = cache::hgetall(conn, &key)
+            .await
+            .map_err(SiblingsError::unreachable)?;
+
+        let cutoff = self.unix_now().saturating_sub(fresh.as_secs());
+        Ok(reported
+            .into_iter()
+            .filter_map(|(me, report)| {
+                let (version, at) = parse_report(&report)?;
+                (at >= cutoff).then_some((me, version))
+            })
+            .collect())
+    }
+
+    /// Polls the version reports of `sibling` every `poll` until every consumer that reported
+    /// within `fresh` holds `version` or later, or `timeout` passes. Returns the last poll;
+    /// check [`Propagation::is_complete`].
+    pub async fn wait_for_version(
+        &self,
+        sibling: &str,
+        version: u64,
+        fresh: Duration,
+        poll: Duration,
+        timeout: Duration,
+    ) -> Result<Propagation, SiblingsError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let propagation = propagation(self.observed(sibling, fresh).await?, version);
+            if propagation.is_complete() || Instant::now() + poll > deadline {
+                return Ok(propagation);
+            }
+
+            info!(
+                "propagation: sibling[{sibling}] version[{version}] lagging {:?}",
+                propagation.lagging
+            );
+            tokio::time::sleep(poll).await;
+        }
+    }
+}
+
+fn propagation(observed: HashMap<String, u64>, version: u64) -> Propagation {
+    let mut propagation = Propagation::default();
+    for (me, held) in observed {
+        if held >= version {
+            propagation.updated.push(me);
+        } else {
+            propagation.lagging.push((me, held));
+        }
+    }
+
+    propagation.updated.sort();
+    propagation.lagging.sort();
+    propagation
+}
+
+/// `{version}@{unix seconds}`
+fn parse_report(report: &str) -> Option<(u64, u64)> {
+    let (version, at) = report.split_once('@')?;
+    Some((version.parse().ok()?, at.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lagging_consumers() {
+        let observed = HashMap::from([
+            ("risk".to_string(), 42),
+            ("onboarding".to_string(), 41),
+            ("billing".to_string(), 43),
+        ]);
+
+        let p = propagation(observed, 42);
+        assert_eq!(p.updated, vec!["billing", "risk"]);
+        assert_eq!(p.lagging, vec![("onboarding".to_string(), 41)]);
+        assert!(!p.is_complete());
+        assert!(propagation(HashMap::new(), 42).is_complete());
+    }
+
+    #[test]
+    fn reports() {
+        assert_eq!(parse_report("42@1700000000"), Some((42, 1700000000)));
+        assert_eq!(parse_report("42"), None);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{fnv1a, update, ChangeEvent, ChangeKind, RegionEndpoint, Siblings, SiblingsError};
+
+impl Siblings {
+    /// Writes `record` as the live endpoint of `sibling` for the current env, stamped with the next
+    /// version and archived under that version so consumers can pin to it.
+    /// Returns the version now live; an unchanged record is not rewritten. A disabled sibling stays
+    /// disabled. Fails with [`SiblingsError::Conflict`] when someone else published `sibling` in
+    /// the meantime.
+    pub async fn publish(
+        &self,
+        sibling: &str,
+        mut record: RegionEndpoint,
+    ) -> Result<u64, SiblingsError> {
+        let current = self.live(sibling).await?;
+        let current_version = current.as_ref().and_then(|c| c.version).unwrap_or(0);
+
+        if let Some(current) = current {
+            // only `disable` / `enable` flip it, not a reload of the siblings file
+            record.enabled = current.enabled;
+            if current.same_as(&record) {
+                info!("publish: sibling[{sibling}] unchanged at version[{current_version}]");
+                return Ok(current_version);
+            }
+        }
+
+        self.write_next(sibling, current_version, record).await
+    }
+
+    /// Disables `sibling`: lookups fail with [`SiblingsError::Disabled`] until [`Self::enable`],
+    /// and the record stays as published. Returns the version now live.
+    pub async fn disable(&self, sibling: &str) -> Result<u64, SiblingsError> {
+        self.set_enabled(sibling, false).await
+    }
+
+    /// Undoes [`Self::disable`]
+    pub async fn enable(&self, sibling: &str) -> Result<u64, SiblingsError> {
+        self.set_enabled(sibling, true).await
+    }
+
+    /// Drops `sibling` from memory, override included, and with `delete` its live record in
+    /// Redis too, so it stops resolving for every consumer; archived versions stay for those
+    /// pinned to one. Returns whether memory held it.
+    pub async fn remove_endpoint(
+        &self,
+        sibling: &str,
+        delete: bool,
+    ) -> Result<bool, SiblingsError> {
+        if delete {
+            self.delete_record(sibling).await?;
+        }
+
+        let held = self.endpoints.load().get(sibling).is_some();
+        update(&self.endpoints, |endpoints| {
+            endpoints.overrides.remove(sibling);
+            if delete {
+                endpoints.missed(sibling);
+            } else {
+                endpoints.remove(sibling);
+            }
+        });
+        self.record_read(sibling, None);
+
+        if delete {
+            info!("publish: sibling[{sibling}] deleted");
+            self.notify(ChangeEvent {
+                env: self.env.name().to_string(),
+                sibling: sibling.to_owned(),
+                kind: ChangeKind::Removed,
+                version: None,
+                checksum: None,
+            })
+            .await;
+        }
+
+        Ok(held)
+    }
+
+    async fn set_enabled(&self, sibling: &str, enabled: bool) -> Result<u64, SiblingsError> {
+        let mut record = self
+            .live(sibling)
+            .await?
+            .ok_or_else(|| SiblingsError::NotConfigured(sibling.to_owned()))?;
+        let current_version = record.version.unwrap_or(0);
+
+        if record.is_enabled() == enabled {
+            info!("publish: sibling[{sibling}] already enabled[{enabled}]");
+            return Ok(current_version);
+        }
+
+        record.enabled = (!enabled).then_some(false);
+        self.write_next(sibling, current_version, record).await
+    }
+
+    /// Writes `record` at the version after `current_version` and tells the sinks
+    async fn write_next(
+        &self,
+        sibling: &str,
+        current_version: u64,
+        mut record: RegionEndpoint,
+    ) -> Result<u64, SiblingsError> {
+        let version = current_version + 1;
+        record.version = Some(version);
+        record.checksum = Some(record.content_checksum()?);
+        record.published_at = Some(self.unix_now());
+        let data = serde_json::to_vec(&record)?;
+
+        if let Some(found) = self
+            .write_record_if(sibling, current_version, version, &data)
+            .await?
+        {
+            warn!("publish: sibling[{sibling}] moved to version[{found}] since it was read");
+            return Err(SiblingsError::Conflict {
+                sibling: sibling.to_owned(),
+                expected: current_version,
+                found,
+            });
+        }
+        info!(
+            "publish: sibling[{sibling}] now at version[{version}] checksum[{}]",
+            record.checksum.as_deref().unwrap_or_default()
+        );
+        self.notify(ChangeEvent {
+            env: self.env.name().to_string(),
+            sibling: sibling.to_owned(),
+            kind: ChangeKind::Published,
+            version: Some(version),
+            checksum: record.checksum.clone(),
+        })
+        .await;
+
+        Ok(version)
+    }
+
+    /// The live record of `sibling` as published, without pins, rollout or in-memory caching
+    pub(crate) async fn live(
+        &self,
+        sibling: &str,
+    ) -> Result<Option<RegionEndpoint>, SiblingsError> {
+        let current = self.read_record(&self.env, sibling, None).await?;
+        if current.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self::deserialize(current)?))
+    }
+}
+
+impl RegionEndpoint {
+    /// Equal apart from the version and checksum stamps and the enabled flag
+    pub(crate) fn same_as(&self, other: &RegionEndpoint) -> bool {
+        let this = Self {
+            version: other.version,
+            checksum: other.checksum.clone(),
+            enabled: other.enabled,
+            published_at: other.published_at,
+            ..self.clone()
+        };
+
+        this == *other
+    }
+
+    /// Hex FNV-1a of the record without its stamps and enabled flag. Goes through
+    /// `serde_json::Value`, whose maps are sorted, so the same content hashes the same in every
+    /// process.
+    pub(crate) fn content_checksum(&self) -> Result<String, SiblingsError> {
+        let content = Self {
+            version: None,
+            checksum: None,
+            enabled: None,
+            published_at: None,
+            ..self.clone()
+        };
+        let data = serde_json::to_vec(&serde_json::to_value(content)?)?;
+
+        Ok(format!("{:016x}", fnv1a(&data)))
+    }
+}
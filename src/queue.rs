@@ -0,0 +1,132 @@
+//! Non-HTTP "endpoints" of a sibling: the Pub/Sub topics and Kafka topics it consumes or emits.
+//!
+//! Records list them under `queues`, keyed by a name the sibling picks:
+//! `"queues": {"events": {"kind": "pubsub", "topic": "projects/x/topics/k9-events"}}`
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{RegionEndpoint, RegionValue, Siblings};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum QueueEndpoint {
+    Pubsub {
+        topic: RegionValue,
+    },
+    Kafka {
+        /// comma separated `host:port` list
+        brokers: RegionValue,
+        topic: RegionValue,
+    },
+}
+
+/// Where to connect a Kafka producer/consumer for one region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaTarget {
+    pub bootstrap_servers: Vec<String>,
+    pub topic: String,
+}
+
+impl RegionEndpoint {
+    pub fn queue(&self, queue: &str) -> Option<&QueueEndpoint> {
+        self.queues.get(queue)
+    }
+
+    pub fn pubsub_topic(&self, queue: &str, region: Option<&str>) -> Option<String> {
+        match self.queue(queue)? {
+            QueueEndpoint::Pubsub { topic } => Some(topic.get(region).to_string()),
+            QueueEndpoint::Kafka { .. } => None,
+        }
+    }
+
+    pub fn kafka(&self, queue: &str, region: Option<&str>) -> Option<KafkaTarget> {
+        match self.queue(queue)? {
+            QueueEndpoint::Kafka { brokers, topic } => Some(KafkaTarget {
+                bootstrap_servers: brokers
+                    .get(region)
+                    .split(',')
+                    .map(|b| b.trim().to_string())
+                    .filter(|b| !b.is_empty())
+                    .collect(),
+                topic: topic.get(region).to_string(),
+            }),
+            QueueEndpoint::Pubsub { .. } => None,
+        }
+    }
+}
+
+impl Siblings {
+    /// Pub/Sub topic `queue` of `sibling`
+    pub async fn pubsub_topic(
+        &self,
+        sibling: &str,
+        queue: &str,
+        region: Option<&str>,
+    ) -> Option<String> {
+        let region = self.region(region);
+        let topic = self
+            .record(sibling, "queues")
+            .await?
+            .pubsub_topic(queue, region);
+        if topic.is_none() {
+            log_to!(
+                Resolve,
+                Warn,
+                "pubsub_topic: sibling[{sibling}] has no pubsub queue[{queue}]"
+            );
+        }
+
+        topic
+    }
+
+    /// Kafka brokers and topic for `queue` of `sibling`
+    pub async fn kafka(
+        &self,
+        sibling: &str,
+        queue: &str,
+        region: Option<&str>,
+    ) -> Option<KafkaTarget> {
+        let region = self.region(region);
+        let target = self.record(sibling, "queues").await?.kafka(queue, region);
+        if target.is_none() {
+            log_to!(
+                Resolve,
+                Warn,
+                "kafka: sibling[{sibling}] has no kafka queue[{queue}]"
+            );
+        }
+
+        target
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Siblings;
+
+    #[test]
+    fn typed_queues() -> anyhow::Result<()> {
+        let ep = Siblings::deserialize(
+            br#"{
+                "default": "https://k9.example.com",
+                "queues": {
+                    "events": {"kind": "pubsub", "topic": {"default": "projects/p/topics/k9", "us": "projects/p-us/topics/k9"}},
+                    "audit": {"kind": "kafka", "brokers": "b1:9092, b2:9092", "topic": "k9-audit"}
+                }
+            }"#
+            .to_vec(),
+        )?;
+
+        assert_eq!(
+            ep.pubsub_topic("events", Some("us")).as_deref(),
+            Some("projects/p-us/topics/k9")
+        );
+        assert_eq!(ep.pubsub_topic("audit", None), None);
+
+        let kafka = ep.kafka("audit", Some("IN")).unwrap();
+        assert_eq!(kafka.bootstrap_servers, vec!["b1:9092", "b2:9092"]);
+        assert_eq!(kafka.topic, "k9-audit");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! Keeping records in memory current without lookups paying for it.
+//!
+//! [`Siblings::spawn_refresher`] re-reads every record in memory on an interval, in one pipelined
+//! round trip, and swaps in the ones that changed. Each wait is stretched or shortened by up to [`JITTER`] of the interval so
+//! pods started by the same deploy don't all read Redis in the same second.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::task::JoinHandle;
+
+use crate::{fnv1a, Siblings};
+
+/// Largest share of the interval a wait is moved by, either way
+const JITTER: f64 = 0.1;
+
+impl Siblings {
+    /// Re-reads every record in memory about every `every` in the background, swapping in those
+    /// that changed. A failed read keeps the record held. Abort the handle to stop it.
+    pub fn spawn_refresher(&self, every: Duration) -> JoinHandle<()> {
+        let slf = self.clone();
+        let seed = format!("{:?}-{}", self.me, std::process::id());
+
+        tokio::spawn(async move {
+            for round in 0_u64.. {
+                tokio::time::sleep(jittered(every, &format!("{seed}-{round}-{}", nanos()))).await;
+
+                let held = slf
+                    .endpoints
+                    .load()
+                    .iter()
+                    .map(|(sibling, ep)| (sibling.to_owned(), ep.clone()))
+                    .collect::<Vec<_>>();
+                let names = held.iter().map(|(s, _)| s.clone()).collect::<Vec<_>>();
+                let mut fetched = match slf.fetch_each(&names).await {
+                    Ok(fetched) => fetched,
+                    Err(e) => {
+                        log_to!(Refresh, Warn, "refresher: every record kept: {e}");
+                        continue;
+                    }
+                };
+
+                let mut changed = 0;
+                for (sibling, old) in held {
+                    let Some(ep) = fetched.remove(&sibling) else {
+                        continue;
+                    };
+                    let ep = match ep {
+                        Ok(ep) => ep,
+                        Err(e) => {
+                            log_to!(
+                                Refresh,
+                                Warn,
+                                "refresher: sibling[{sibling}] keeps its record: {e}"
+                            );
+                            continue;
+                        }
+                    };
+
+                    if ep.as_ref() != Some(&old) {
+                        changed += 1;
+                    }
+                    // unchanged records go back in too, restarting their ttl
+                    slf.replace(&sibling, ep.as_ref());
+                }
+                log_to!(
+                    Refresh,
+                    Debug,
+                    "refresher: swapped {changed} records in round[{round}]"
+                );
+            }
+        })
+    }
+}
+
+/// `every` moved by up to [`JITTER`] of it either way, picked from `seed`
+fn jittered(every: Duration, seed: &str) -> Duration {
+    // in [-1, 1]
+    let unit = (fnv1a(seed.as_bytes()) % 2001) as f64 / 1000.0 - 1.0;
+    every.mul_f64(1.0 + unit * JITTER)
+}
+
+fn nanos() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let every = Duration::from_secs(60);
+        let waits = (0..100)
+            .map(|i| jittered(every, &format!("risk-{i}")))
+            .collect::<Vec<_>>();
+
+        assert!(waits
+            .iter()
+            .all(|w| *w >= Duration::from_secs(54) && *w <= Duration::from_secs(66)));
+        assert!(waits.iter().any(|w| *w != waits[0]));
+    }
+}
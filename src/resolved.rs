@@ -0,0 +1,144 @@
+//! Which config generation a process is serving from.
+//!
+//! Every record [`Siblings::publish`] writes carries its version and a checksum of its content.
+//! [`Siblings::resolve`] returns both with the url, and [`Siblings::metrics`] lists them for every
+//! record in memory, so operators can check that all pods serve the same records.
+//! [`Siblings::iter_cached`] hands out the records themselves for debug pages and exporters.
+
+use std::{fmt::Write, time::Duration};
+
+use serde_derive::Serialize;
+
+use crate::{RegionEndpoint, Siblings, SiblingsError};
+
+/// A resolved url with the stamps of the record it came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResolvedEndpoint {
+    pub sibling: String,
+    pub url: String,
+    /// `None` for records published before versioning
+    pub version: Option<u64>,
+    /// `None` for records published before checksums
+    pub checksum: Option<String>,
+}
+
+/// How a record came to be in memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheMeta {
+    /// Time since it was fetched or last refreshed
+    pub age: Duration,
+    /// What [`Siblings::with_ttl`] or [`Siblings::with_sibling_ttl`] set for it
+    pub ttl: Option<Duration>,
+}
+
+impl CacheMeta {
+    /// `true` once the next lookup re-fetches the record
+    pub fn is_expired(&self) -> bool {
+        self.ttl.is_some_and(|ttl| self.age >= ttl)
+    }
+}
+
+impl Siblings {
+    /// Every record in memory right now, by sibling name, with how long it's been there. A
+    /// snapshot: lookups and refreshes after the call don't show up in it.
+    pub async fn iter_cached(
+        &self,
+    ) -> impl Iterator<Item = (String, RegionEndpoint, CacheMeta)> + use<> {
+        let endpoints = self.endpoints.load();
+        let mut cached = endpoints
+            .iter()
+            .map(|(sibling, ep)| {
+                let meta = CacheMeta {
+                    age: endpoints.age(sibling).unwrap_or_default(),
+                    ttl: self.ttl_of(sibling),
+                };
+                (sibling.to_owned(), ep.clone(), meta)
+            })
+            .collect::<Vec<_>>();
+        cached.sort_by(|a, b| a.0.cmp(&b.0));
+
+        cached.into_iter()
+    }
+
+    /// Like [`Self::try_sibling`] but also says which version of the record the url came from
+    pub async fn resolve(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+    ) -> Result<Option<ResolvedEndpoint>, SiblingsError> {
+        let lookup = self.before_lookup(sibling, region)?;
+        let Some(ep) = self.endpoint(&lookup.sibling).await? else {
+            return Ok(None);
+        };
+        let url = Some(self.nearest(&ep, lookup.region.as_deref()).to_string());
+        let url = self.after_lookup(&lookup, url)?;
+
+        Ok(url.map(|url| ResolvedEndpoint {
+            sibling: lookup.sibling.into_owned(),
+            url,
+            version: ep.version(),
+            checksum: ep.checksum().map(str::to_string),
+        }))
+    }
+
+    /// Version and checksum of every record in memory, in the Prometheus text format:
+    /// `siblings_record_version{sibling="k9",checksum="..."} 4`
+    pub async fn metrics(&self) -> String {
+        let endpoints = self.endpoints.load();
+        let mut records = endpoints.iter().collect::<Vec<_>>();
+        records.sort_by_key(|(sibling, _)| *sibling);
+
+        let mut out = String::from("# TYPE siblings_record_version gauge\n");
+        for (sibling, ep) in records {
+            let _ = writeln!(
+                out,
+                "siblings_record_version{{sibling=\"{sibling}\",checksum=\"{}\"}} {}",
+                ep.checksum().unwrap_or_default(),
+                ep.version().unwrap_or(0)
+            );
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Siblings;
+
+    #[test]
+    fn checksum_ignores_stamps_and_key_order() -> anyhow::Result<()> {
+        let a = Siblings::deserialize(br#"{"default":"https://k9","in":"a","us":"b"}"#.to_vec())?;
+        let b = Siblings::deserialize(
+            br#"{"us":"b","in":"a","default":"https://k9","version":7,"checksum":"x"}"#.to_vec(),
+        )?;
+        let moved = Siblings::deserialize(br#"{"default":"https://k9","in":"c"}"#.to_vec())?;
+
+        assert_eq!(a.content_checksum()?, b.content_checksum()?);
+        assert_ne!(a.content_checksum()?, moved.content_checksum()?);
+        assert_eq!(b.checksum(), Some("x"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cached_records_with_meta() {
+        let sib = Siblings::sidecar("/nonexistent.sock", None)
+            .with_sibling_ttl("k9", std::time::Duration::ZERO);
+        let ep = crate::RegionEndpoint {
+            default: "https://k9".into(),
+            ..Default::default()
+        };
+        crate::update(&sib.endpoints, |e| {
+            e.insert("matrix", ep.clone());
+            e.insert("k9", ep.clone());
+        });
+
+        let cached = sib.iter_cached().await.collect::<Vec<_>>();
+        assert_eq!(cached.len(), 2);
+        assert_eq!((cached[0].0.as_str(), &cached[0].1), ("k9", &ep));
+        assert!(cached[0].2.is_expired());
+        assert_eq!(cached[1].0, "matrix");
+        assert!(!cached[1].2.is_expired());
+    }
+}
@@ -0,0 +1,97 @@
+//! Gradual rollout of a new endpoint across consumers.
+//!
+//! A record's own urls are the current endpoint; `rollout` carries the next one and the share of
+//! consumers that should already use it. Each consumer (`me`) lands in a stable bucket per sibling,
+//! so raising `percent` only ever moves more consumers over, never back.
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{fnv1a, RegionEndpoint, RegionValue};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Rollout {
+    next: RegionValue,
+    /// 0..=100
+    percent: u8,
+}
+
+impl Rollout {
+    pub fn next(&self) -> &RegionValue {
+        &self.next
+    }
+
+    pub fn percent(&self) -> u8 {
+        self.percent
+    }
+
+    /// Whether consumer `me` of `sibling` falls inside the rolled out share
+    pub fn includes(&self, sibling: &str, me: &str) -> bool {
+        bucket(sibling, me) < self.percent.min(100)
+    }
+}
+
+impl RegionEndpoint {
+    pub fn rollout(&self) -> Option<&Rollout> {
+        self.rollout.as_ref()
+    }
+
+    /// The record as consumer `me` should see it: the `next` urls if it is part of the rollout,
+    /// the current ones otherwise. Anonymous consumers always stay on current.
+    pub(crate) fn for_consumer(mut self, sibling: &str, me: Option<&str>) -> Self {
+        let Some(rollout) = &self.rollout else {
+            return self;
+        };
+
+        if let Some(me) = me
+            && rollout.includes(sibling, me)
+        {
+            let next = rollout.next.clone();
+            log_to!(
+                Resolve,
+                Debug,
+                "rollout: consumer[{me}] gets next endpoint of sibling[{sibling}]"
+            );
+            self.default = next.default.into();
+            self.regions = next
+                .regions
+                .into_iter()
+                .map(|(region, url)| (region, url.into()))
+                .collect();
+        }
+
+        self
+    }
+}
+
+fn bucket(sibling: &str, me: &str) -> u8 {
+    (fnv1a(format!("{sibling}/{me}").as_bytes()) % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollout_is_stable_and_monotonic() {
+        let rollout = |percent| Rollout {
+            next: RegionValue::new("https://next"),
+            percent,
+        };
+
+        let consumers = (0..200).map(|i| format!("svc-{i}")).collect::<Vec<_>>();
+        let at = |percent| {
+            consumers
+                .iter()
+                .filter(|c| rollout(percent).includes("k9", c))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        assert!(at(0).is_empty());
+        assert_eq!(at(100).len(), consumers.len());
+
+        let ten = at(10);
+        assert!(!ten.is_empty() && ten.len() < 60);
+        assert!(ten.iter().all(|c| at(50).contains(c)));
+    }
+}
@@ -0,0 +1,51 @@
+//! Short-lived sandboxes layered over dev, e.g. one per pull request.
+//!
+//! A sandbox is an env of its own, `pr-1234`, whose keys (`pr-1234-ep-k9`) live in the dev Redis.
+//! [`Siblings::with_sandbox`] resolves each sibling from the sandbox when it publishes one and
+//! from dev otherwise, so a preview deploy only publishes the siblings it replaces. Publishing and
+//! the loader write to the sandbox; [`loader::clean`](crate::loader::clean) removes its records
+//! once the preview is torn down. Bulk reads ([`Siblings::hydrate_all`]) only see the sandbox's
+//! own records, the rest resolve one by one.
+
+use std::env;
+
+use crate::{Env, Siblings};
+
+impl Siblings {
+    /// Resolves in sandbox `name` over dev: records published in the sandbox win, every other
+    /// sibling resolves from dev
+    pub fn with_sandbox(mut self, name: &str) -> Self {
+        self.env = Env::from_name(name);
+        self.base_env = Some(Env::Dev);
+        self
+    }
+
+    /// [`Self::with_sandbox`] named by `X_SANDBOX`, when set
+    pub(crate) fn with_sandbox_from_env(self) -> Self {
+        match env::var("X_SANDBOX") {
+            Ok(name) if !name.trim().is_empty() => self.with_sandbox(&name),
+            _ => self,
+        }
+    }
+
+    /// The sandbox this instance resolves in, `None` outside one
+    pub fn sandbox(&self) -> Option<&str> {
+        self.base_env.as_ref().map(|_| self.env.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sandbox_keys_sit_beside_dev() {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        assert_eq!(sib.sandbox(), None);
+
+        let sib = sib.with_sandbox("PR-1234");
+        assert_eq!(sib.sandbox(), Some("pr-1234"));
+        assert_eq!(sib.cache_key(&sib.keys.endpoint("k9")), "pr-1234-ep-k9");
+        assert_eq!(sib.base_env, Some(Env::Dev));
+    }
+}
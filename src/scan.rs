@@ -0,0 +1,121 @@
+//! Walking keys without blocking Redis.
+//!
+//! Everything that lists what is published (`list`, pruning and sandbox cleanup, env diffs,
+//! hydrating every record) walks the keyspace with SCAN and env hashes with HSCAN, never KEYS or
+//! HKEYS, which hold Redis for as long as they take. Pages ask for [`SCAN_BATCH`] entries, wait
+//! [`SCAN_PAUSE`] between them and wait out the Redis ops budget rather than fail on it, so an
+//! inventory run against prod stays a trickle. SCAN may return a key more than once; results are
+//! deduplicated.
+
+use std::{collections::BTreeSet, future::Future, time::Duration};
+
+use crate::{budget, cache, Siblings, SiblingsError};
+
+/// Entries asked for per SCAN or HSCAN page
+pub const SCAN_BATCH: usize = 500;
+/// Wait between two pages of one walk
+pub const SCAN_PAUSE: Duration = Duration::from_millis(5);
+
+impl Siblings {
+    /// Keys matching `pattern` in the current env, with the env prefix stripped
+    pub(crate) async fn scan_cache(
+        &self,
+        pattern: &str,
+    ) -> Result<BTreeSet<String>, SiblingsError> {
+        let prefix = self.keys.prefix(&self.env);
+        let pattern = self.cache_key(pattern);
+        let conn = self.backend.redis("listing keys")?;
+
+        let mut keys = BTreeSet::new();
+        for conn in cache::scan_targets(conn)
+            .await
+            .map_err(SiblingsError::unreachable)?
+        {
+            keys.extend(
+                walk(&pattern, |cursor| {
+                    cache::scan(conn, &pattern, cursor, SCAN_BATCH)
+                })
+                .await?,
+            );
+        }
+
+        Ok(keys
+            .into_iter()
+            .filter_map(|k| k.strip_prefix(&prefix).map(str::to_string))
+            .collect())
+    }
+
+    /// Fields of the hash at `key`, already prefixed
+    pub(crate) async fn scan_hash(&self, key: &str) -> Result<BTreeSet<String>, SiblingsError> {
+        let conn = self.backend.redis("listing keys")?;
+
+        walk(key, |cursor| cache::hscan(conn, key, cursor, SCAN_BATCH)).await
+    }
+}
+
+/// Every entry of the pages `page` returns from cursor 0 until Redis hands back cursor 0
+async fn walk<F, Fut>(what: &str, mut page: F) -> Result<BTreeSet<String>, SiblingsError>
+where
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = anyhow::Result<(u64, Vec<String>)>>,
+{
+    let mut found = BTreeSet::new();
+    let mut cursor = 0;
+    for pages in 1_u64.. {
+        while let Err(e) = budget::acquire(what) {
+            let SiblingsError::Throttled(wait) = e else {
+                return Err(e);
+            };
+            tokio::time::sleep(wait).await;
+        }
+        let (next, batch) = page(cursor).await.map_err(SiblingsError::unreachable)?;
+        found.extend(batch);
+
+        if next == 0 {
+            log_to!(
+                Resolve,
+                Debug,
+                "scan: {what} walked in {pages} pages, {} entries",
+                found.len()
+            );
+            break;
+        }
+        cursor = next;
+        tokio::time::sleep(SCAN_PAUSE).await;
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn walks_every_page_once() -> Result<(), SiblingsError> {
+        let pages = HashMap::from([
+            (0, (7, vec!["ep-k9", "ep-credit"])),
+            (7, (3, vec![])),
+            // SCAN may hand a key back twice while the keyspace rehashes
+            (3, (0, vec!["ep-k9", "ep-matrix"])),
+        ]);
+        let mut cursors = Vec::new();
+
+        let found = walk("dev-ep-*", |cursor| {
+            cursors.push(cursor);
+            let (next, keys) = pages[&cursor].clone();
+            async move { Ok((next, keys.into_iter().map(String::from).collect())) }
+        })
+        .await?;
+
+        assert_eq!(cursors, [0, 7, 3]);
+        assert_eq!(
+            found.into_iter().collect::<Vec<_>>(),
+            ["ep-credit", "ep-k9", "ep-matrix"]
+        );
+
+        Ok(())
+    }
+}
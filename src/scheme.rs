@@ -0,0 +1,106 @@
+//! Upgrading plain http urls to https.
+//!
+//! In prod every `http://` url of a fetched record (default, regions, public url) is rewritten to
+//! `https://` before the record is kept in memory, so a record published with plain http doesn't
+//! send traffic unencrypted. Internal-only services without TLS opt out per sibling with
+//! [`Siblings::with_plain_http`]; [`Siblings::with_https_upgrade`] turns it on or off in any env.
+
+use crate::{RegionEndpoint, RegionValue, Siblings};
+
+impl Siblings {
+    /// Upgrades `http://` urls to `https://` when `upgrade`, whatever the env; prod only unless set
+    pub fn with_https_upgrade(mut self, upgrade: bool) -> Self {
+        self.https_upgrade = Some(upgrade);
+        self
+    }
+
+    /// Leaves the `http://` urls of `sibling` alone, for internal services that don't speak TLS
+    pub fn with_plain_http(mut self, sibling: &str) -> Self {
+        self.plain_http.insert(sibling.to_owned());
+        self
+    }
+
+    /// `ep` with its urls upgraded to https, unless the upgrade is off or `sibling` opted out
+    pub(crate) fn upgraded(&self, sibling: &str, ep: RegionEndpoint) -> RegionEndpoint {
+        let upgrade = self.https_upgrade.unwrap_or_else(|| self.env.is_prod());
+        if !upgrade || self.plain_http.contains(sibling) {
+            return ep;
+        }
+
+        let upgraded = ep.clone().with_https();
+        if upgraded != ep {
+            log_to!(
+                Resolve,
+                Debug,
+                "scheme: sibling[{sibling}] upgraded to https"
+            );
+        }
+        upgraded
+    }
+}
+
+impl RegionEndpoint {
+    fn with_https(mut self) -> Self {
+        upgrade(&mut self.default);
+        self.regions.values_mut().for_each(upgrade);
+        if let Some(public_url) = &mut self.public_url {
+            public_url.upgrade();
+        }
+        self
+    }
+}
+
+impl RegionValue {
+    fn upgrade(&mut self) {
+        upgrade(&mut self.default);
+        self.regions.values_mut().for_each(upgrade);
+    }
+}
+
+fn upgrade<S: AsRef<str> + From<String>>(url: &mut S) {
+    let plain = url.as_ref();
+    if plain
+        .get(..7)
+        .is_some_and(|s| s.eq_ignore_ascii_case("http://"))
+    {
+        *url = format!("https://{}", &plain[7..]).into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Env, Siblings};
+
+    #[test]
+    fn prod_urls_upgrade_unless_opted_out() -> anyhow::Result<()> {
+        let ep = Siblings::deserialize(
+            br#"{"default":"http://k9","in":"HTTP://k9.in","us":"https://k9.us","public_url":"http://k9.example.com"}"#
+                .to_vec(),
+        )?;
+        let prod = Siblings::sidecar("/nonexistent.sock", None)
+            .with_env(Env::Prod)
+            .with_plain_http("matrix");
+
+        let upgraded = prod.upgraded("k9", ep.clone());
+        assert_eq!(upgraded.get_in(None).as_deref(), Some("https://k9"));
+        assert_eq!(
+            upgraded.get_in(Some("in")).as_deref(),
+            Some("https://k9.in")
+        );
+        assert_eq!(
+            upgraded.get_in(Some("us")).as_deref(),
+            Some("https://k9.us")
+        );
+        assert_eq!(
+            upgraded.public_url(None).as_deref(),
+            Some("https://k9.example.com")
+        );
+
+        assert_eq!(prod.upgraded("matrix", ep.clone()), ep);
+        let dev = Siblings::sidecar("/nonexistent.sock", None).with_env(Env::Dev);
+        assert_eq!(dev.upgraded("k9", ep.clone()), ep);
+        assert_ne!(dev.with_https_upgrade(true).upgraded("k9", ep.clone()), ep);
+
+        Ok(())
+    }
+}
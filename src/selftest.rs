@@ -0,0 +1,201 @@
+//! A bounded health check a service can run from its `/debug/selftest` handler.
+//!
+//! [`Siblings::self_test`] pings Redis, reads and deserializes one record and compares Redis'
+//! clock with the local one, which ttls and version reports rely on. Every check gets at most
+//! [`CHECK_TIMEOUT`]; checks that can't run through the backend are skipped, not failed.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_derive::Serialize;
+
+use crate::{cache, Siblings};
+
+/// Longest a single check may take
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Largest difference between Redis' clock and the local one that passes
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub took_ms: u64,
+}
+
+/// Outcome of [`Siblings::self_test`], serializable as the handler's response
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub checks: Vec<Check>,
+}
+
+impl SelfTestReport {
+    /// `true` when no check failed
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Fail)
+    }
+
+    fn skip(&mut self, name: &'static str, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            status: CheckStatus::Skip,
+            detail: detail.into(),
+            took_ms: 0,
+        });
+    }
+
+    /// Runs `check` within [`CHECK_TIMEOUT`], recording what it returns
+    async fn run<T>(
+        &mut self,
+        name: &'static str,
+        check: impl Future<Output = Result<(T, String), String>>,
+    ) -> Option<T> {
+        let started = Instant::now();
+        let outcome = tokio::time::timeout(CHECK_TIMEOUT, check)
+            .await
+            .unwrap_or_else(|_| Err(format!("timed out after {CHECK_TIMEOUT:?}")));
+
+        let (status, detail, value) = match outcome {
+            Ok((value, detail)) => (CheckStatus::Pass, detail, Some(value)),
+            Err(detail) => (CheckStatus::Fail, detail, None),
+        };
+        self.checks.push(Check {
+            name,
+            status,
+            detail,
+            took_ms: started.elapsed().as_millis() as u64,
+        });
+
+        value
+    }
+}
+
+impl Siblings {
+    /// Pings Redis, reads the record of `me` (or of any sibling in memory) and deserializes it,
+    /// and checks Redis' clock against the local one
+    pub async fn self_test(&self) -> SelfTestReport {
+        let mut report = SelfTestReport::default();
+
+        match self.backend.conn() {
+            Some(conn) => {
+                report
+                    .run("ping", async {
+                        cache::ping(conn).await.map_err(|e| e.to_string())?;
+                        Ok(((), "PONG".to_string()))
+                    })
+                    .await;
+            }
+            None => report.skip(
+                "ping",
+                format!("no redis behind the {}", self.backend.name()),
+            ),
+        }
+
+        let sibling = match &self.me {
+            Some(me) => Some(me.clone()),
+            None => self
+                .endpoints
+                .load()
+                .iter()
+                .next()
+                .map(|(sibling, _)| sibling.to_owned()),
+        };
+        let data = match &sibling {
+            Some(sibling) => {
+                report
+                    .run("read", async {
+                        let data = self
+                            .read_record(&self.env, sibling, None)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        let detail = format!("{} bytes of sibling[{sibling}]", data.len());
+                        Ok((data, detail))
+                    })
+                    .await
+            }
+            None => {
+                report.skip("read", "no `me` and nothing in memory to read");
+                None
+            }
+        };
+
+        match data {
+            Some(data) if !data.is_empty() => {
+                report
+                    .run("deserialize", async {
+                        let ep = Self::deserialize(data).map_err(|e| e.to_string())?;
+                        Ok(((), format!("version[{:?}]", ep.version())))
+                    })
+                    .await;
+            }
+            _ => report.skip("deserialize", "no record read"),
+        }
+
+        match self.backend.conn() {
+            Some(conn) => {
+                report
+                    .run("clock", async {
+                        let (secs, micros) = cache::time(conn).await.map_err(|e| e.to_string())?;
+                        let skew = clock_skew(Duration::new(secs, micros as u32 * 1000));
+                        if skew > MAX_CLOCK_SKEW {
+                            return Err(format!("clock {skew:?} off from redis"));
+                        }
+                        Ok(((), format!("{skew:?} off from redis")))
+                    })
+                    .await;
+            }
+            None => report.skip(
+                "clock",
+                format!("no redis behind the {}", self.backend.name()),
+            ),
+        }
+
+        report
+    }
+}
+
+/// How far the local clock is from `remote`, given as time since the unix epoch
+fn clock_skew(remote: Duration) -> Duration {
+    let local = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    local.abs_diff(remote)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sidecar_skips_redis_checks() {
+        let sib = Siblings::sidecar("/nonexistent.sock", Some("risk"));
+        let report = sib.self_test().await;
+
+        let statuses = report
+            .checks
+            .iter()
+            .map(|c| (c.name, c.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                ("ping", CheckStatus::Skip),
+                ("read", CheckStatus::Fail),
+                ("deserialize", CheckStatus::Skip),
+                ("clock", CheckStatus::Skip),
+            ]
+        );
+        assert!(!report.is_ok());
+    }
+}
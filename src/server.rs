@@ -0,0 +1,265 @@
+//! HTTP resolution server for clients that can't link this crate.
+//!
+//! `GET /siblings/{name}` returns the whole record, `GET /siblings/{name}?region=IN` the url
+//! [`Siblings::try_sibling`] resolves. Record responses carry an `ETag` derived from the record's
+//! version and content checksum, region responses one derived from the url, so clients can
+//! revalidate with `If-None-Match` instead of re-downloading, and a `Cache-Control: max-age`
+//! matching the server's own refresh horizon. `GET /metrics` reports the version and checksum of
+//! every record the server holds, `GET /debug/siblings` dumps its memory as [`Siblings::snapshot`]
+//! does.
+//!
+//! [`serve_status`] serves the status page of the whole env instead, for `siblings-status`.
+
+use std::{convert::Infallible, future::Future, net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use http_body_util::Full;
+use hyper::{
+    body::{Bytes, Incoming},
+    header,
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use tokio::net::TcpListener;
+
+use crate::{fnv1a, RegionEndpoint, Siblings, SiblingsError};
+
+pub async fn serve(siblings: Siblings, addr: SocketAddr, max_age: Duration) -> Result<()> {
+    listen(siblings, addr, move |siblings, req| async move {
+        handle(&siblings, req, max_age).await
+    })
+    .await
+}
+
+/// The status page of [`Siblings::platform_status`] at `GET /`, reloading every `refresh`, and
+/// its data at `GET /status.json`
+pub async fn serve_status(siblings: Siblings, addr: SocketAddr, refresh: Duration) -> Result<()> {
+    listen(siblings, addr, move |siblings, req| async move {
+        status(&siblings, req, refresh).await
+    })
+    .await
+}
+
+/// Answers every request on `addr` with `handler`
+async fn listen<F, Fut>(siblings: Siblings, addr: SocketAddr, handler: F) -> Result<()>
+where
+    F: Fn(Siblings, Request<Incoming>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response<Full<Bytes>>> + Send,
+{
+    let listener = TcpListener::bind(addr).await?;
+    info!("server: listening on {addr}");
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let siblings = siblings.clone();
+        let handler = handler.clone();
+
+        tokio::spawn(async move {
+            let svc = service_fn(move |req| {
+                let response = handler(siblings.clone(), req);
+                async move { Ok::<_, Infallible>(response.await) }
+            });
+
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), svc)
+                .await
+            {
+                warn!("server: connection error: {e}");
+            }
+        });
+    }
+}
+
+async fn handle(
+    siblings: &Siblings,
+    req: Request<Incoming>,
+    max_age: Duration,
+) -> Response<Full<Bytes>> {
+    if req.method() != Method::GET {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, json!({"error": "only GET"}));
+    }
+
+    if req.uri().path() == "/metrics" {
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Full::new(Bytes::from(siblings.metrics().await)))
+            .unwrap();
+    }
+
+    if req.uri().path() == "/debug/siblings" {
+        return respond(StatusCode::OK, json!(siblings.snapshot().await));
+    }
+
+    let Some(sibling) = req.uri().path().strip_prefix("/siblings/") else {
+        return respond(StatusCode::NOT_FOUND, json!({"error": "not found"}));
+    };
+    let region = match req.uri().query().map(|q| {
+        q.split('&')
+            .find_map(|kv| kv.strip_prefix("region="))
+            .map(percent_decode)
+    }) {
+        Some(Some(None)) => {
+            return respond(
+                StatusCode::BAD_REQUEST,
+                json!({"error": "malformed region"}),
+            )
+        }
+        region => region.flatten().flatten(),
+    };
+
+    let (body, tag) = match region {
+        Some(region) => match siblings.try_sibling(sibling, Some(&region)).await {
+            Ok(url) => {
+                let tag = format!(
+                    "\"{:016x}\"",
+                    fnv1a(url.as_deref().unwrap_or_default().as_bytes())
+                );
+                let body = json!({
+                    "sibling": sibling,
+                    "region": region,
+                    "endpoint": url,
+                });
+                (body, tag)
+            }
+            Err(e) => return failed(&e),
+        },
+        None => match siblings.endpoint(sibling).await {
+            Ok(Some(ep)) => match etag(&ep) {
+                Ok(tag) => (json!(ep), tag),
+                Err(e) => return failed(&e),
+            },
+            Ok(None) => {
+                return respond(
+                    StatusCode::NOT_FOUND,
+                    json!({"error": format!("sibling {sibling} not configured")}),
+                )
+            }
+            Err(e) => return failed(&e),
+        },
+    };
+    let cache_control = format!("max-age={}", max_age.as_secs());
+
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|t| t.trim() == tag || t.trim() == "*"));
+    if not_modified {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, &tag)
+            .header(header::CACHE_CONTROL, &cache_control)
+            .body(Full::default())
+            .unwrap();
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ETAG, &tag)
+        .header(header::CACHE_CONTROL, &cache_control)
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+async fn status(
+    siblings: &Siblings,
+    req: Request<Incoming>,
+    refresh: Duration,
+) -> Response<Full<Bytes>> {
+    if req.method() != Method::GET {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, json!({"error": "only GET"}));
+    }
+
+    let status = match siblings.platform_status().await {
+        Ok(status) => status,
+        Err(e) => {
+            let code = StatusCode::from_u16(e.status_code()).unwrap_or(StatusCode::BAD_GATEWAY);
+            return respond(code, json!({"error": e.report(), "kind": e.label()}));
+        }
+    };
+
+    match req.uri().path() {
+        "/" => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Full::new(Bytes::from(status.to_html(refresh))))
+            .unwrap(),
+        "/status.json" => respond(StatusCode::OK, json!(status)),
+        _ => respond(StatusCode::NOT_FOUND, json!({"error": "not found"})),
+    }
+}
+
+fn respond(status: StatusCode, body: serde_json::Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+fn failed(e: &SiblingsError) -> Response<Full<Bytes>> {
+    let status = StatusCode::from_u16(e.status_code()).unwrap_or(StatusCode::BAD_GATEWAY);
+    respond(
+        status,
+        json!({"error": e.to_string(), "kind": e.label(), "help": e.help()}),
+    )
+}
+
+/// Strong ETag from the record's version and content checksum, the same in every process
+fn etag(ep: &RegionEndpoint) -> Result<String, SiblingsError> {
+    let version = ep.version().unwrap_or_default();
+
+    Ok(format!("\"{version}-{}\"", ep.content_checksum()?))
+}
+
+/// `value` of a query parameter with `%XX` escapes and `+` decoded; `None` when malformed
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.bytes();
+    while let Some(b) = rest.next() {
+        match b {
+            b'%' => {
+                let hex = [rest.next()?, rest.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'+' => bytes.push(b' '),
+            b => bytes.push(b),
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etag_ignores_region_order() -> Result<()> {
+        let a =
+            br#"{"default":"https://k9","version":3,"IN":"https://in.k9","US":"https://us.k9"}"#;
+        let b =
+            br#"{"default":"https://k9","version":3,"US":"https://us.k9","IN":"https://in.k9"}"#;
+        let a = etag(&Siblings::deserialize(a.to_vec())?)?;
+
+        assert_eq!(a, etag(&Siblings::deserialize(b.to_vec())?)?);
+        assert!(a.starts_with("\"3-"));
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_the_region() {
+        assert_eq!(percent_decode("IN").as_deref(), Some("IN"));
+        assert_eq!(percent_decode("us%2Deast+1").as_deref(), Some("us-east 1"));
+        assert_eq!(percent_decode("%E0%A4%AD").as_deref(), Some("\u{92d}"));
+        assert_eq!(percent_decode("IN%2"), None);
+    }
+}
@@ -0,0 +1,102 @@
+//! Resolution as a `tower::Service`, behind the `tower` feature.
+//!
+//! [`SiblingsResolver`] answers a [`ResolveRequest`] like [`Siblings::try_sibling`], so the
+//! consuming service wraps lookups in whatever tower middleware it already runs (timeout, retry,
+//! rate limit, metrics) instead of this crate hard-coding them. It is always ready: lookups don't
+//! queue, whatever backpressure there is comes from the layers around it.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tower_service::Service;
+
+use crate::{Siblings, SiblingsError};
+
+/// A lookup of `sibling`, in `region` when set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveRequest {
+    pub sibling: String,
+    pub region: Option<String>,
+}
+
+impl ResolveRequest {
+    pub fn new(sibling: impl Into<String>) -> Self {
+        Self {
+            sibling: sibling.into(),
+            region: None,
+        }
+    }
+
+    pub fn in_region(mut self, region: impl Into<String>) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+}
+
+pub type ResolveFuture =
+    Pin<Box<dyn Future<Output = Result<Option<String>, SiblingsError>> + Send>>;
+
+/// [`Siblings::try_sibling`] as a `tower::Service<ResolveRequest>`
+#[derive(Clone)]
+pub struct SiblingsResolver {
+    siblings: Siblings,
+}
+
+impl SiblingsResolver {
+    pub fn new(siblings: Siblings) -> Self {
+        Self { siblings }
+    }
+}
+
+impl Siblings {
+    /// This instance as a `tower::Service`, to wrap lookups in middleware
+    pub fn resolver(&self) -> SiblingsResolver {
+        SiblingsResolver::new(self.clone())
+    }
+}
+
+impl Service<ResolveRequest> for SiblingsResolver {
+    type Response = Option<String>;
+    type Error = SiblingsError;
+    type Future = ResolveFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: ResolveRequest) -> Self::Future {
+        let siblings = self.siblings.clone();
+        Box::pin(async move {
+            siblings
+                .try_sibling(&req.sibling, req.region.as_deref())
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn resolves_as_a_service() -> Result<(), SiblingsError> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let ep =
+            Siblings::deserialize(br#"{"default":"https://k9","in":"https://k9.in"}"#.to_vec())?;
+        crate::update(&sib.endpoints, |e| e.insert("k9", ep.clone()));
+
+        let mut resolver = sib.resolver();
+        poll_fn(|cx| resolver.poll_ready(cx)).await?;
+        let url = resolver
+            .call(ResolveRequest::new("k9").in_region("IN"))
+            .await?;
+        assert_eq!(url.as_deref(), Some("https://k9.in"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Host-local endpoint cache shared through a file.
+//!
+//! One refresher process owns the Redis traffic and publishes whole generations to a file
+//! (ideally under `/dev/shm`, so it never touches disk); every worker on the host reads that file
+//! instead of keeping its own Redis connections and cache.
+
+use std::{
+    collections::HashMap,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use serde_derive::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinHandle,
+};
+
+use crate::{Generation, GenerationalCache, RegionEndpoint, Siblings};
+
+#[derive(Serialize, Deserialize)]
+struct SharedFile {
+    generation: u64,
+    endpoints: HashMap<String, RegionEndpoint>,
+}
+
+impl Siblings {
+    /// Keeps `siblings` published to the shared file at `path`, reloading every `refresh_every`.
+    /// Only one process per host should run this.
+    pub async fn publish_shared_file(
+        &self,
+        path: impl AsRef<Path>,
+        siblings: &[&str],
+        refresh_every: Duration,
+    ) -> Result<JoinHandle<()>> {
+        let path = path.as_ref().to_path_buf();
+        let names = siblings.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let mut current = self.load_generation(&names, None).await;
+        write_shared_file(&path, &current).await?;
+
+        let slf = self.clone();
+        Ok(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(refresh_every);
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                let next = slf.load_generation(&names, Some(&current)).await;
+                if next.endpoints == current.endpoints {
+                    continue;
+                }
+
+                match write_shared_file(&path, &next).await {
+                    Ok(()) => {
+                        log_to!(
+                            Refresh,
+                            Info,
+                            "shared-file: published generation[{}]",
+                            next.generation
+                        );
+                        current = next;
+                    }
+                    Err(e) => log_to!(
+                        Refresh,
+                        Error,
+                        "shared-file: publishing to {path:?} failed: {e}"
+                    ),
+                }
+            }
+        }))
+    }
+}
+
+impl GenerationalCache {
+    /// Reads the file maintained by [`Siblings::publish_shared_file`], picking up new generations
+    /// every `poll_every`. No Redis connection is needed in the reading process.
+    pub async fn from_shared_file(path: impl AsRef<Path>, poll_every: Duration) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (first, ino) = read_shared_file(&path).await?;
+
+        let current = Arc::new(ArcSwap::from_pointee(first));
+        let weak = Arc::downgrade(&current);
+        tokio::spawn(async move { poll_shared_file(weak, path, ino, poll_every).await });
+
+        Ok(Self { current })
+    }
+}
+
+/// Writes to a temp file and renames it over `path` so readers never see a partial write
+async fn write_shared_file(path: &Path, generation: &Generation) -> Result<()> {
+    let data = serde_json::to_vec(&SharedFile {
+        generation: generation.generation,
+        endpoints: generation.endpoints.clone(),
+    })?;
+
+    let tmp = tmp_path(path);
+    let mut f = File::create(&tmp).await?;
+    f.write_all(&data).await?;
+    f.sync_all().await?;
+    fs::rename(&tmp, path).await?;
+
+    Ok(())
+}
+
+async fn read_shared_file(path: &Path) -> Result<(Generation, u64)> {
+    // inode and content from one open file, so a rename in between can't mix generations
+    let mut f = File::open(path).await?;
+    let ino = f.metadata().await?.ino();
+    let mut data = Vec::new();
+    f.read_to_end(&mut data).await?;
+    let file: SharedFile = serde_json::from_slice(&data)?;
+
+    Ok((
+        Generation {
+            generation: file.generation,
+            loaded_at: SystemTime::now(),
+            endpoints: file.endpoints,
+        },
+        ino,
+    ))
+}
+
+async fn poll_shared_file(
+    current: Weak<ArcSwap<Generation>>,
+    path: PathBuf,
+    mut ino: u64,
+    poll_every: Duration,
+) {
+    let mut ticker = tokio::time::interval(poll_every);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let Some(current) = current.upgrade() else {
+            return;
+        };
+
+        // a new generation is always a new file
+        match fs::metadata(&path).await {
+            Ok(m) if m.ino() == ino => continue,
+            Ok(_) => {}
+            Err(e) => {
+                log_to!(Refresh, Warn, "shared-file: stat {path:?} failed: {e}");
+                continue;
+            }
+        }
+
+        match read_shared_file(&path).await {
+            Ok((next, next_ino)) => {
+                log_to!(
+                    Refresh,
+                    Info,
+                    "shared-file: read generation[{}]",
+                    next.generation
+                );
+                ino = next_ino;
+                current.store(Arc::new(next));
+            }
+            Err(e) => log_to!(Refresh, Warn, "shared-file: reading {path:?} failed: {e}"),
+        }
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generation(generation: u64, url: &str) -> Generation {
+        let endpoints =
+            serde_json::from_str(&format!(r#"{{"k9": {{"default": "{url}"}}}}"#)).expect("records");
+        Generation {
+            generation,
+            loaded_at: SystemTime::now(),
+            endpoints,
+        }
+    }
+
+    #[tokio::test]
+    async fn readers_swap_in_published_generations() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("siblings-{}.json", std::process::id()));
+        write_shared_file(&path, &generation(1, "http://k9.one")).await?;
+
+        let cache = GenerationalCache::from_shared_file(&path, Duration::from_millis(10)).await?;
+        assert_eq!(cache.get("k9", None)?.as_deref(), Some("http://k9.one"));
+
+        write_shared_file(&path, &generation(2, "http://k9.two")).await?;
+        for _ in 0..100 {
+            if cache.load().generation() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(cache.load().generation(), 2);
+        assert_eq!(cache.get("k9", None)?.as_deref(), Some("http://k9.two"));
+
+        fs::remove_file(&path).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+//! Live pod addresses from Kubernetes EndpointSlices, behind the `kube-watch` feature.
+//!
+//! [`EndpointSliceSource`] is an [`EndpointSource`] answering each sibling with a ready pod of
+//! the service of the same name, as the EndpointSlices of its namespace list them.
+//! [`Siblings::watch_endpoint_slices`] lists the slices once, then follows the API's watch and
+//! refreshes a sibling in memory as soon as its pods change, so scale-ups, restarts and failing
+//! readiness probes show up in seconds. Of several ready pods the first by address answers; pods
+//! not ready, or terminating, never do.
+//!
+//! The API is reached over plain `http://`, as a `kubectl proxy` sidecar serves it; the
+//! service account token is sent as a bearer token when set with
+//! [`EndpointSliceSource::with_token`].
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use http_body_util::{BodyExt, Empty};
+use hyper::{
+    body::{Bytes, Incoming},
+    header, Request, Response, Uri,
+};
+use hyper_util::rt::TokioIo;
+use serde_derive::Deserialize;
+use tokio::task::JoinHandle;
+
+use crate::{EndpointSource, Env, FetchFuture, RegionEndpoint, Siblings, SiblingsError};
+
+/// Wait before listing and watching again after the watch ended
+const REWATCH_AFTER: Duration = Duration::from_secs(5);
+
+/// Label naming the service a slice belongs to
+const SERVICE_LABEL: &str = "kubernetes.io/service-name";
+
+/// Service and ready pod urls of a slice
+type Ready = (String, Vec<String>);
+
+/// EndpointSlices of a namespace, as last listed or watched
+#[derive(Debug, Clone)]
+pub struct EndpointSliceSource {
+    api: Uri,
+    namespace: String,
+    token: Option<String>,
+    scheme: String,
+    port_name: Option<String>,
+    services: BTreeSet<String>,
+    /// slice name -> (service, ready urls)
+    slices: Arc<RwLock<HashMap<String, Ready>>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SliceList {
+    metadata: ListMeta,
+    #[serde(default)]
+    items: Vec<Slice>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ListMeta {
+    #[serde(rename = "resourceVersion", default)]
+    resource_version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SliceEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    object: Slice,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Slice {
+    #[serde(default)]
+    metadata: SliceMeta,
+    #[serde(default)]
+    ports: Option<Vec<SlicePort>>,
+    #[serde(default)]
+    endpoints: Option<Vec<SliceEndpoint>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SliceMeta {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlicePort {
+    name: Option<String>,
+    port: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SliceEndpoint {
+    #[serde(default)]
+    addresses: Vec<String>,
+    #[serde(default)]
+    conditions: Conditions,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Conditions {
+    ready: Option<bool>,
+    terminating: Option<bool>,
+}
+
+impl Slice {
+    fn service(&self) -> Option<&str> {
+        self.metadata.labels.get(SERVICE_LABEL).map(String::as_str)
+    }
+}
+
+impl EndpointSliceSource {
+    /// Slices of `namespace`, read from the API at `api`, e.g. `http://127.0.0.1:8001`
+    pub fn new(api: &str, namespace: impl Into<String>) -> Result<Self> {
+        let api = api.parse::<Uri>()?;
+        if api.scheme_str() != Some("http") || api.host().is_none() {
+            bail!("the kubernetes api needs an http:// address, got {api}");
+        }
+
+        Ok(Self {
+            api,
+            namespace: namespace.into(),
+            token: None,
+            scheme: "http".to_string(),
+            port_name: None,
+            services: BTreeSet::new(),
+            slices: Default::default(),
+        })
+    }
+
+    /// Sent as `Authorization: Bearer` with every request
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Scheme of the urls built from pods, `http` unless set
+    pub fn with_scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.scheme = scheme.into();
+        self
+    }
+
+    /// Uses the slice port named `name`, e.g. `http`, instead of the first
+    pub fn with_port_name(mut self, name: impl Into<String>) -> Self {
+        self.port_name = Some(name.into());
+        self
+    }
+
+    /// Only follows the slices of `service`; every service of the namespace if never called
+    pub fn with_service(mut self, service: impl Into<String>) -> Self {
+        self.services.insert(service.into());
+        self
+    }
+
+    /// Ready pod urls of `service`, sorted
+    pub fn ready(&self, service: &str) -> Vec<String> {
+        let slices = self.slices.read().unwrap_or_else(|e| e.into_inner());
+        let urls = slices
+            .values()
+            .filter(|(s, _)| s == service)
+            .flat_map(|(_, urls)| urls.iter().cloned())
+            .collect::<BTreeSet<_>>();
+        urls.into_iter().collect()
+    }
+
+    fn record(&self, sibling: &str) -> Option<RegionEndpoint> {
+        let url = self.ready(sibling).into_iter().next()?;
+
+        Some(RegionEndpoint {
+            default: url.into(),
+            ..Default::default()
+        })
+    }
+
+    /// Replaces every slice with `list`'s, returning its resource version and the services
+    /// that were or are listed
+    fn apply_list(&self, list: SliceList) -> (String, BTreeSet<String>) {
+        let fresh = list
+            .items
+            .iter()
+            .filter_map(|slice| self.ready_urls(slice))
+            .collect::<HashMap<_, _>>();
+        let mut slices = self.slices.write().unwrap_or_else(|e| e.into_inner());
+        let services = slices
+            .values()
+            .chain(fresh.values())
+            .map(|(service, _)| service.clone())
+            .collect();
+        *slices = fresh;
+
+        (list.metadata.resource_version, services)
+    }
+
+    /// Applies a watch event, returning the service it changed
+    fn apply_event(&self, event: SliceEvent) -> Option<String> {
+        let mut slices = self.slices.write().unwrap_or_else(|e| e.into_inner());
+        match event.kind.as_str() {
+            "ADDED" | "MODIFIED" => {
+                let (name, entry) = self.ready_urls(&event.object)?;
+                let service = entry.0.clone();
+                slices.insert(name, entry);
+                Some(service)
+            }
+            "DELETED" => slices
+                .remove(&event.object.metadata.name)
+                .map(|(service, _)| service),
+            _ => None,
+        }
+    }
+
+    /// Name, service and ready urls of `slice`; `None` for slices of services not followed
+    fn ready_urls(&self, slice: &Slice) -> Option<(String, Ready)> {
+        let service = slice.service()?;
+        if !self.services.is_empty() && !self.services.contains(service) {
+            return None;
+        }
+
+        let ports = slice.ports.as_deref().unwrap_or_default();
+        let port = match &self.port_name {
+            Some(name) => ports.iter().find(|p| p.name.as_ref() == Some(name)),
+            None => ports.first(),
+        }
+        .and_then(|p| p.port);
+        let urls = match port {
+            Some(port) => slice
+                .endpoints
+                .iter()
+                .flatten()
+                .filter(|ep| ep.conditions.ready != Some(false))
+                .filter(|ep| ep.conditions.terminating != Some(true))
+                .flat_map(|ep| ep.addresses.iter())
+                .map(|address| match address.contains(':') {
+                    true => format!("{}://[{address}]:{port}", self.scheme),
+                    false => format!("{}://{address}:{port}", self.scheme),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Some((slice.metadata.name.clone(), (service.to_owned(), urls)))
+    }
+
+    fn path(&self, query: &str) -> String {
+        let mut path = format!(
+            "/apis/discovery.k8s.io/v1/namespaces/{}/endpointslices?{query}",
+            self.namespace
+        );
+        if !self.services.is_empty() {
+            let names = self.services.iter().cloned().collect::<Vec<_>>().join(",");
+            path.push_str(&format!("&labelSelector={SERVICE_LABEL}%20in%20({names})"));
+        }
+        path
+    }
+
+    /// Lists every slice, returning the resource version to watch from
+    async fn list(&self) -> Result<(String, BTreeSet<String>)> {
+        let body = self.get(&self.path("limit=500")).await?.into_body();
+        let list = serde_json::from_slice(&body.collect().await?.to_bytes())?;
+        Ok(self.apply_list(list))
+    }
+
+    /// Applies the changes after `version`, refreshing the siblings they concern in `siblings`,
+    /// until the watch ends
+    async fn watch(&self, siblings: &Siblings, version: &str) -> Result<()> {
+        let query = format!("watch=1&allowWatchBookmarks=false&resourceVersion={version}");
+        let mut body = self.get(&self.path(&query)).await?.into_body();
+
+        let mut buf = Vec::new();
+        while let Some(frame) = body.frame().await {
+            let Ok(data) = frame?.into_data() else {
+                continue;
+            };
+            buf.extend_from_slice(&data);
+            while let Some(end) = buf.iter().position(|b| *b == b'\n') {
+                let line = buf.drain(..=end).collect::<Vec<_>>();
+                let event = serde_json::from_slice::<SliceEvent>(&line)?;
+                if event.kind == "ERROR" {
+                    // most often 410 Gone, the version is too old to watch from
+                    bail!("watch error, listing again");
+                }
+                if let Some(service) = self.apply_event(event) {
+                    log_to!(Refresh, Debug, "slices: sibling[{service}] pods changed");
+                    siblings.refresh_held(&[service]).await;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get(&self, path: &str) -> Result<Response<Incoming>> {
+        let host = self.api.host().unwrap_or_default();
+        let port = self.api.port_u16().unwrap_or(80);
+        let stream = tokio::net::TcpStream::connect((host, port)).await?;
+        let (mut sender, conn) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(conn);
+
+        let mut req = Request::get(path)
+            .header(header::HOST, format!("{host}:{port}"))
+            .header(header::ACCEPT, "application/json");
+        if let Some(token) = &self.token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let resp = sender
+            .send_request(req.body(Empty::<Bytes>::new())?)
+            .await?;
+        if !resp.status().is_success() {
+            bail!("kubernetes api answered {} for {path}", resp.status());
+        }
+
+        Ok(resp)
+    }
+}
+
+impl EndpointSource for EndpointSliceSource {
+    fn fetch<'a>(&'a self, _: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        let record = pinned.is_none().then(|| self.record(sibling)).flatten();
+        Box::pin(async move { Ok(record) })
+    }
+}
+
+impl Siblings {
+    /// Lists the EndpointSlices `slices` follows and watches them, refreshing a sibling in memory
+    /// as its ready pods change. A watch that ends or errors is followed by a fresh list and watch
+    /// every [`REWATCH_AFTER`], refreshing the siblings of every slice listed.
+    pub async fn watch_endpoint_slices(
+        &self,
+        slices: &EndpointSliceSource,
+    ) -> Result<JoinHandle<()>, SiblingsError> {
+        let (mut version, _) = slices.list().await.map_err(SiblingsError::unreachable)?;
+        log_to!(
+            Refresh,
+            Info,
+            "slices: watching namespace {}",
+            slices.namespace
+        );
+
+        let (slf, slices) = (self.clone(), slices.clone());
+        Ok(tokio::spawn(async move {
+            loop {
+                match slices.watch(&slf, &version).await {
+                    Ok(()) => log_to!(Refresh, Info, "slices: watch ended, watching again"),
+                    Err(e) => log_to!(Refresh, Warn, "slices: watch failed: {e}"),
+                }
+
+                version = loop {
+                    match slices.list().await {
+                        Ok((version, services)) => {
+                            slf.refresh_held(&services.into_iter().collect::<Vec<_>>())
+                                .await;
+                            break version;
+                        }
+                        Err(e) => log_to!(Refresh, Warn, "slices: listing failed: {e}"),
+                    }
+                    tokio::time::sleep(REWATCH_AFTER).await;
+                };
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolves_ready_pods() -> anyhow::Result<()> {
+        let slices = EndpointSliceSource::new("http://127.0.0.1:8001", "platform")?
+            .with_port_name("http")
+            .with_service("k9");
+        let list = serde_json::from_str(
+            r#"{"metadata":{"resourceVersion":"41"},"items":[
+                {"metadata":{"name":"k9-abc","labels":{"kubernetes.io/service-name":"k9"}},
+                 "ports":[{"name":"metrics","port":9100},{"name":"http","port":9000}],
+                 "endpoints":[
+                    {"addresses":["10.0.0.9"],"conditions":{"ready":true}},
+                    {"addresses":["10.0.0.3"],"conditions":{"ready":false}},
+                    {"addresses":["10.0.0.5"],"conditions":{}}]},
+                {"metadata":{"name":"matrix-xyz","labels":{"kubernetes.io/service-name":"matrix"}},
+                 "ports":[{"name":"http","port":80}],
+                 "endpoints":[{"addresses":["10.0.1.1"]}]}]}"#,
+        )?;
+        assert_eq!(slices.apply_list(list).0, "41");
+        assert_eq!(
+            slices.ready("k9"),
+            ["http://10.0.0.5:9000", "http://10.0.0.9:9000"]
+        );
+
+        let sib = Siblings::with_source(slices.clone(), None).with_env(Env::Dev);
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("http://10.0.0.5:9000")
+        );
+        assert_eq!(sib.try_sibling("matrix", None).await?, None);
+
+        let event = serde_json::from_str(
+            r#"{"type":"MODIFIED","object":{"metadata":{"name":"k9-abc","labels":{"kubernetes.io/service-name":"k9"}},
+                "ports":[{"name":"http","port":9000}],
+                "endpoints":[{"addresses":["10.0.0.5"],"conditions":{"ready":true,"terminating":true}},
+                             {"addresses":["10.0.0.7"],"conditions":{"ready":true}}]}}"#,
+        )?;
+        assert_eq!(slices.apply_event(event).as_deref(), Some("k9"));
+        sib.refresh_held(&["k9".to_string()]).await;
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("http://10.0.0.7:9000")
+        );
+
+        Ok(())
+    }
+}
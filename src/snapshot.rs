@@ -0,0 +1,123 @@
+//! A serializable copy of everything an instance holds in memory.
+//!
+//! [`Siblings::snapshot`] copies the records, built-in and dynamic siblings alike, with how long
+//! each has been held, the siblings last found unpublished, registered webhooks and service
+//! descriptors. Services expose it from a debug route, the resolution server at
+//! `GET /debug/siblings`.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{Endpoints, RegionEndpoint, RegionValue, ServiceDescriptor, Siblings, Source};
+
+/// What [`Siblings::snapshot`] found in memory, sorted by name
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EndpointsSnapshot {
+    /// Unix seconds the snapshot was taken at
+    pub taken_at: u64,
+    pub env: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<String>,
+    pub records: BTreeMap<String, HeldRecord>,
+    /// Seconds since each sibling was last found unpublished
+    pub missing: BTreeMap<String, u64>,
+    /// Registered webhook urls by cache key
+    pub webhooks: BTreeMap<String, RegionValue>,
+    pub descriptors: BTreeMap<String, ServiceDescriptor>,
+}
+
+/// A record in memory
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HeldRecord {
+    pub record: RegionEndpoint,
+    /// Seconds since it was put in memory
+    pub age: u64,
+    /// Set with [`Siblings::set_endpoint`] rather than read from Redis
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub overridden: bool,
+    /// The source answering with it
+    pub source: Source,
+}
+
+impl Siblings {
+    /// The records in memory, sharing the copy lookups read rather than cloning it; serializes
+    /// as siblings.json lays records out
+    pub fn held_endpoints(&self) -> Arc<Endpoints> {
+        self.endpoints.load_full()
+    }
+
+    /// An owned copy of everything in memory, e.g. for a `/debug/siblings` route
+    pub async fn snapshot(&self) -> EndpointsSnapshot {
+        let endpoints = self.endpoints.load();
+        let now = self.clock.now();
+
+        EndpointsSnapshot {
+            taken_at: self.unix_now(),
+            env: self.env.name().to_owned(),
+            sandbox: self.sandbox().map(str::to_owned),
+            records: endpoints
+                .iter()
+                .map(|(sibling, ep)| {
+                    let age = endpoints.age(sibling).unwrap_or_default().as_secs();
+                    let held = HeldRecord {
+                        record: ep.clone(),
+                        age,
+                        overridden: endpoints.overrides.contains_key(sibling),
+                        source: endpoints.source_of(sibling).unwrap_or(Source::Redis),
+                    };
+                    (sibling.to_owned(), held)
+                })
+                .collect(),
+            missing: endpoints
+                .missing
+                .iter()
+                .map(|(sibling, at)| {
+                    let ago = now.saturating_duration_since(*at).as_secs();
+                    (sibling.clone(), ago)
+                })
+                .collect(),
+            webhooks: endpoints.webhooks.clone().into_iter().collect(),
+            descriptors: endpoints.descriptors.clone().into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::MockClock;
+
+    #[tokio::test]
+    async fn snapshot_copies_what_is_held() -> anyhow::Result<()> {
+        let clock = MockClock::new();
+        let sib = Siblings::sidecar("/nonexistent.sock", None).with_clock(clock.clone());
+        let ep = Siblings::deserialize(br#"{"default":"https://acme.k9"}"#.to_vec())?;
+        crate::update(&sib.endpoints, |e| {
+            e.insert("k9", ep.clone());
+            e.insert("k9-acme", ep.clone());
+            e.missed("credit");
+        });
+        clock.advance(Duration::from_secs(5));
+
+        let snapshot = sib.snapshot().await;
+        assert_eq!(
+            snapshot.records.keys().collect::<Vec<_>>(),
+            ["k9", "k9-acme"]
+        );
+        assert_eq!(snapshot.records["k9-acme"].record, ep);
+        assert_eq!(snapshot.records["k9-acme"].age, 5);
+        assert_eq!(snapshot.missing["credit"], 5);
+
+        let json = serde_json::to_value(&snapshot)?;
+        assert_eq!(
+            json["records"]["k9"]["record"]["default"],
+            "https://acme.k9"
+        );
+        assert_eq!(snapshot.to_legacy_map()["k9"]["default"], "https://acme.k9");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Where records come from, and which wins.
+//!
+//! A lookup walks three sources in order of precedence, by default:
+//! 1. [`Source::Overrides`]: records set with [`Siblings::set_endpoint`] and the
+//!    `SIBLING_<NAME>_URL` variables
+//! 2. [`Source::LocalFile`]: a `svc.env` of `sibling=port` lines, read with
+//!    [`Siblings::with_local_file`] (or `X_LOCAL=TRUE`), each resolving to `http://localhost:{port}`
+//! 3. [`Source::Redis`]: the published records
+//!
+//! The first source holding a record answers, so a local file only takes over the siblings it
+//! names and everything else falls through to Redis. [`Siblings::with_precedence`] reorders the
+//! sources or leaves some out: sources ranked after Redis answer only for siblings Redis has
+//! nothing published for, and without Redis lookups never read it.
+
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{update, RegionEndpoint, Siblings};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Overrides,
+    LocalFile,
+    #[default]
+    Redis,
+}
+
+/// Sources in the order they answer; see [`Siblings::with_precedence`]
+pub const DEFAULT_PRECEDENCE: [Source; 3] = [Source::Overrides, Source::LocalFile, Source::Redis];
+
+/// The order sources answer lookups in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Precedence(Vec<Source>);
+
+impl Default for Precedence {
+    fn default() -> Self {
+        Self(DEFAULT_PRECEDENCE.to_vec())
+    }
+}
+
+impl Precedence {
+    pub(crate) fn sources(&self) -> &[Source] {
+        &self.0
+    }
+
+    /// Sources answering ahead of a Redis read, Redis included; all of them without Redis
+    pub(crate) fn held(&self) -> &[Source] {
+        match self.redis_rank() {
+            Some(rank) => &self.0[..=rank],
+            None => &self.0,
+        }
+    }
+
+    /// Sources answering only when Redis has nothing published
+    pub(crate) fn fallback(&self) -> &[Source] {
+        match self.redis_rank() {
+            Some(rank) => &self.0[rank + 1..],
+            None => &[],
+        }
+    }
+
+    pub(crate) fn reads_redis(&self) -> bool {
+        self.redis_rank().is_some()
+    }
+
+    fn redis_rank(&self) -> Option<usize> {
+        self.0.iter().position(|s| *s == Source::Redis)
+    }
+}
+
+impl Siblings {
+    /// Walks `sources` in the order given, the first holding a record answering; sources left out
+    /// never answer. Listing a source twice keeps its first rank.
+    pub fn with_precedence(self, sources: &[Source]) -> Self {
+        let mut precedence = Vec::new();
+        for source in sources {
+            if !precedence.contains(source) {
+                precedence.push(*source);
+            }
+        }
+        log_to!(Refresh, Info, "sources: precedence {precedence:?}");
+        update(&self.endpoints, |endpoints| {
+            endpoints.precedence = Precedence(precedence.clone());
+        });
+        self
+    }
+
+    /// The sources lookups walk, in order
+    pub fn precedence(&self) -> Vec<Source> {
+        self.endpoints.load().precedence.sources().to_vec()
+    }
+
+    /// Answers lookups of the siblings `path` names (`bank_statement=8081` for `bank-statement`)
+    /// with `http://localhost:{port}`, as [`Source::LocalFile`]. A file that can't be read is
+    /// logged and changes nothing.
+    pub fn with_local_file(self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match dotenvy::from_filename_iter(path) {
+            Ok(lines) => {
+                let entries = lines.filter_map(|line| {
+                    line.inspect_err(|e| {
+                        log_to!(Refresh, Warn, "sources: skipping a line of {path:?}: {e}")
+                    })
+                    .ok()
+                });
+                self.with_local_entries(entries)
+            }
+            Err(e) => {
+                log_to!(Refresh, Warn, "sources: local file {path:?} not read: {e}");
+                self
+            }
+        }
+    }
+
+    fn with_local_entries(self, entries: impl Iterator<Item = (String, String)>) -> Self {
+        let records = entries
+            .map(|(key, port)| {
+                let ep = RegionEndpoint {
+                    default: format!("http://localhost:{}", port.trim()).into(),
+                    ..Default::default()
+                };
+                // svc.env names siblings with underscores, `bank_statement` for `bank-statement`
+                (key.replace('_', "-"), ep)
+            })
+            .collect::<Vec<_>>();
+        self.with_local_records(records)
+    }
+
+    /// Answers lookups of the siblings in `records` with their record, as [`Source::LocalFile`]
+    pub(crate) fn with_local_records(self, records: Vec<(String, RegionEndpoint)>) -> Self {
+        update(&self.endpoints, |endpoints| {
+            endpoints.local.extend(records.iter().cloned());
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(sib: Siblings) -> Siblings {
+        let entries = [("k9", "8080"), ("bank_statement", "8081")]
+            .map(|(k, v)| (k.to_string(), v.to_string()));
+        sib.with_local_entries(entries.into_iter())
+    }
+
+    #[tokio::test]
+    async fn local_file_takes_over_only_what_it_names() -> anyhow::Result<()> {
+        let redis = Siblings::deserialize(br#"{"default":"https://from-redis"}"#.to_vec())?;
+        let sib = local(Siblings::sidecar("/nonexistent.sock", None));
+        crate::update(&sib.endpoints, |e| {
+            e.insert("k9", redis.clone());
+            e.insert("matrix", redis.clone());
+        });
+
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("http://localhost:8080")
+        );
+        assert_eq!(
+            sib.try_sibling("bank-statement", None).await?.as_deref(),
+            Some("http://localhost:8081")
+        );
+        assert_eq!(
+            sib.try_sibling("matrix", None).await?.as_deref(),
+            Some("https://from-redis")
+        );
+
+        sib.set_endpoint(
+            "k9",
+            Siblings::deserialize(br#"{"default":"https://dr"}"#.to_vec())?,
+        );
+        sib.flush().await;
+        assert_eq!(sib.peek("k9", None).as_deref(), Some("https://dr"));
+        assert_eq!(
+            sib.peek("bank-statement", None).as_deref(),
+            Some("http://localhost:8081")
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sources_after_redis_answer_its_misses() -> anyhow::Result<()> {
+        let redis = Siblings::deserialize(br#"{"default":"https://from-redis"}"#.to_vec())?;
+        let sib = local(Siblings::sidecar("/nonexistent.sock", None)).with_precedence(&[
+            Source::Redis,
+            Source::LocalFile,
+            Source::Redis,
+        ]);
+        assert_eq!(sib.precedence(), [Source::Redis, Source::LocalFile]);
+        crate::update(&sib.endpoints, |e| {
+            e.insert("k9", redis.clone());
+            e.missed("bank-statement");
+        });
+
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("https://from-redis")
+        );
+        assert_eq!(
+            sib.try_sibling("bank-statement", None).await?.as_deref(),
+            Some("http://localhost:8081")
+        );
+
+        let offline = local(Siblings::sidecar("/nonexistent.sock", None))
+            .with_precedence(&[Source::LocalFile]);
+        assert_eq!(
+            offline.try_sibling("k9", None).await?.as_deref(),
+            Some("http://localhost:8080")
+        );
+        assert_eq!(offline.try_sibling("matrix", None).await?, None);
+
+        Ok(())
+    }
+}
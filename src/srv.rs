@@ -0,0 +1,252 @@
+//! Resolving siblings through DNS SRV records, behind the `srv` feature.
+//!
+//! [`SrvSource`] is an [`EndpointSource`] mapping each sibling to an SRV name, `_k9._tcp.internal`
+//! from the template `_{sibling}._tcp.internal`, and answering with the target of its best record:
+//! the lowest priority, then the highest weight, then the first target by name. A region with its
+//! own template ([`SrvSource::with_region`]) resolves through its own names.
+//!
+//! Answers are cached by the resolver for as long as their DNS TTL allows and looked up again
+//! after, so setting [`Siblings::with_ttl`](crate::Siblings::with_ttl) to a few seconds makes
+//! record changes reach lookups about as fast as DNS serves them, without a query per lookup.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+};
+
+use anyhow::Result;
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::ResolveErrorKind,
+    proto::rr::rdata::SRV,
+    TokioAsyncResolver,
+};
+
+use crate::{EndpointSource, Env, FetchFuture, RegionEndpoint, Regions};
+
+/// Placeholder of the sibling in name templates
+const SIBLING: &str = "{sibling}";
+
+/// SRV names of siblings, and the resolver looking them up
+#[derive(Clone)]
+pub struct SrvSource {
+    resolver: TokioAsyncResolver,
+    template: String,
+    /// sibling -> SRV name, overriding the template
+    names: HashMap<String, String>,
+    /// region code -> template
+    regions: BTreeMap<String, String>,
+    scheme: String,
+}
+
+impl SrvSource {
+    /// Looks up the name `template` gives each sibling, `{sibling}` replaced by its name, with the
+    /// system's resolver configuration
+    pub fn new(template: impl Into<String>) -> Result<Self> {
+        Ok(Self {
+            resolver: TokioAsyncResolver::tokio_from_system_conf()?,
+            template: template.into(),
+            names: HashMap::new(),
+            regions: BTreeMap::new(),
+            scheme: "http".to_string(),
+        })
+    }
+
+    /// Asks the DNS server at `addr` instead of the system's
+    pub fn with_nameserver(mut self, addr: SocketAddr) -> Self {
+        let servers = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+        let config = ResolverConfig::from_parts(None, vec![], servers);
+        self.resolver = TokioAsyncResolver::tokio(config, ResolverOpts::default());
+        self
+    }
+
+    /// Looks up `name` for `sibling` instead of the name the template gives it
+    pub fn with_name(mut self, sibling: &str, name: impl Into<String>) -> Self {
+        self.names.insert(sibling.to_owned(), name.into());
+        self
+    }
+
+    /// Resolves lookups in `region` through the names `template` gives siblings
+    pub fn with_region(mut self, region: Regions, template: impl Into<String>) -> Self {
+        self.regions
+            .insert(region.code().to_owned(), template.into());
+        self
+    }
+
+    /// Scheme of the urls built from targets, `http` unless set
+    pub fn with_scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.scheme = scheme.into();
+        self
+    }
+
+    async fn record(&self, sibling: &str) -> Result<Option<RegionEndpoint>> {
+        let name = match self.names.get(sibling) {
+            Some(name) => name.clone(),
+            None => self.template.replace(SIBLING, sibling),
+        };
+        let Some(default) = self.target(&name).await? else {
+            log_to!(
+                Resolve,
+                Debug,
+                "srv: sibling[{sibling}] has no target at {name}"
+            );
+            return Ok(None);
+        };
+
+        let mut record = RegionEndpoint {
+            default: default.into(),
+            ..Default::default()
+        };
+        for (region, template) in &self.regions {
+            if let Some(url) = self.target(&template.replace(SIBLING, sibling)).await? {
+                record.regions.insert(region.clone(), url.into());
+            }
+        }
+
+        Ok(Some(record))
+    }
+
+    /// Url of the best target of `name`, `None` when it has no SRV records
+    async fn target(&self, name: &str) -> Result<Option<String>> {
+        let lookup = match self.resolver.srv_lookup(name).await {
+            Ok(lookup) => lookup,
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(best(lookup.iter()).map(|srv| {
+            let target = srv.target().to_utf8();
+            format!(
+                "{}://{}:{}",
+                self.scheme,
+                target.trim_end_matches('.'),
+                srv.port()
+            )
+        }))
+    }
+}
+
+/// The record lookups go to: lowest priority, highest weight, first target. A target of `.`
+/// means the service is not offered there and never answers.
+fn best<'a>(records: impl Iterator<Item = &'a SRV>) -> Option<&'a SRV> {
+    records
+        .filter(|srv| !srv.target().is_root())
+        .min_by_key(|srv| {
+            (
+                srv.priority(),
+                Reverse(srv.weight()),
+                srv.target().to_utf8(),
+            )
+        })
+}
+
+impl EndpointSource for SrvSource {
+    fn fetch<'a>(&'a self, _: &'a Env, sibling: &'a str, pinned: Option<u64>) -> FetchFuture<'a> {
+        match pinned {
+            Some(_) => Box::pin(async { Ok(None) }),
+            None => Box::pin(self.record(sibling)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use hickory_resolver::proto::{
+        op::{Message, MessageType, ResponseCode},
+        rr::{Name, RData, Record},
+        serialize::binary::BinEncodable,
+    };
+    use tokio::net::UdpSocket;
+
+    use super::*;
+    use crate::Siblings;
+
+    /// Priority, weight, port and target of the SRV records of a name
+    type Zone = &'static [(&'static str, &'static [(u16, u16, u16, &'static str)])];
+
+    /// A DNS server answering SRV queries from `zone`, by name
+    async fn fake_dns(zone: Zone) -> Result<SocketAddr> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = socket.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+                let Ok(query) = Message::from_vec(&buf[..n]) else {
+                    continue;
+                };
+                let mut resp = Message::new();
+                resp.set_id(query.id())
+                    .set_message_type(MessageType::Response)
+                    .set_recursion_available(true);
+                for q in query.queries() {
+                    resp.add_query(q.clone());
+                    let name = q.name().to_utf8();
+                    match zone.iter().find(|(n, _)| name.trim_end_matches('.') == *n) {
+                        Some((_, records)) => {
+                            for (priority, weight, port, target) in *records {
+                                let srv = SRV::new(
+                                    *priority,
+                                    *weight,
+                                    *port,
+                                    Name::from_str(target).unwrap(),
+                                );
+                                resp.add_answer(Record::from_rdata(
+                                    q.name().clone(),
+                                    30,
+                                    RData::SRV(srv),
+                                ));
+                            }
+                        }
+                        None => {
+                            resp.set_response_code(ResponseCode::NXDomain);
+                        }
+                    }
+                }
+                if let Ok(bytes) = resp.to_bytes() {
+                    let _ = socket.send_to(&bytes, from).await;
+                }
+            }
+        });
+
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn resolves_the_best_target() -> anyhow::Result<()> {
+        let dns = fake_dns(&[
+            (
+                "_k9._tcp.internal",
+                &[
+                    (20, 100, 9000, "k9-backup.internal."),
+                    (10, 5, 9000, "k9-b.internal."),
+                    (10, 50, 9000, "k9-a.internal."),
+                ],
+            ),
+            ("_k9._tcp.us.internal", &[(10, 0, 9443, "k9.us.internal.")]),
+            ("_matrix._tcp.internal", &[(0, 0, 0, ".")]),
+        ])
+        .await?;
+        let srv = SrvSource::new("_{sibling}._tcp.internal")?
+            .with_nameserver(dns)
+            .with_region(Regions::US, "_{sibling}._tcp.us.internal");
+        let sib = Siblings::with_source(srv, None).with_env(Env::Dev);
+
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("http://k9-a.internal:9000")
+        );
+        assert_eq!(
+            sib.try_sibling("k9", Some("US")).await?.as_deref(),
+            Some("http://k9.us.internal:9443")
+        );
+        assert_eq!(sib.try_sibling("matrix", None).await?, None);
+        assert_eq!(sib.try_sibling("bureau", None).await?, None);
+
+        Ok(())
+    }
+}
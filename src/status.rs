@@ -0,0 +1,215 @@
+//! One page showing the state of every sibling of an env.
+//!
+//! [`Siblings::platform_status`] gathers what the other reports keep separately: the published
+//! records with their version and age, the health snapshot last published with
+//! [`Siblings::publish_health`], and the usage and version reports consumers write with
+//! [`Siblings::report_usage`]. [`PlatformStatus::to_html`] renders it as a self-refreshing page,
+//! which the `siblings-status` binary serves (or writes to a file with `--out`).
+
+use std::time::Duration;
+
+use serde_derive::Serialize;
+
+use crate::{HealthStatus, SiblingHealth, Siblings, SiblingsError};
+
+/// How recent a version report must be for its consumer to count as running
+pub const STATUS_FRESH: Duration = Duration::from_secs(300);
+
+/// Everything [`Siblings::platform_status`] found, by sibling
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct PlatformStatus {
+    pub env: String,
+    /// Unix seconds the status was gathered at
+    pub taken_at: u64,
+    /// Unix seconds the health snapshot was taken at, `None` when none is published
+    pub health_taken_at: Option<u64>,
+    pub siblings: Vec<SiblingStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct SiblingStatus {
+    pub sibling: String,
+    pub url: String,
+    pub version: Option<u64>,
+    pub enabled: bool,
+    /// Seconds since this version was published
+    pub age: Option<u64>,
+    pub health: Option<SiblingHealth>,
+    /// Consumers that ever reported resolving it
+    pub consumers: usize,
+    /// Seconds since a consumer last reported resolving it
+    pub last_used: Option<u64>,
+    /// Running consumers on an older version, with that version
+    pub lagging: Vec<(String, u64)>,
+}
+
+impl Siblings {
+    /// Records, health and consumer reports of every sibling published in this env
+    pub async fn platform_status(&self) -> Result<PlatformStatus, SiblingsError> {
+        let now = self.unix_now();
+        let health = self.published_health().await?;
+
+        let mut siblings = Vec::new();
+        for (sibling, record) in self.list_siblings().await? {
+            let consumers = self.consumers(&sibling).await?;
+            let last_used = consumers
+                .values()
+                .max()
+                .and_then(|at| self.clock.system_now().duration_since(*at).ok())
+                .map(|ago| ago.as_secs());
+
+            let mut lagging = match record.version() {
+                Some(version) => self
+                    .observed(&sibling, STATUS_FRESH)
+                    .await?
+                    .into_iter()
+                    .filter(|(_, held)| *held < version)
+                    .collect(),
+                None => Vec::new(),
+            };
+            lagging.sort();
+
+            siblings.push(SiblingStatus {
+                url: record.get_arc(None).to_string(),
+                version: record.version(),
+                enabled: record.is_enabled(),
+                age: record.published_at().map(|at| now.saturating_sub(at)),
+                health: health
+                    .as_ref()
+                    .and_then(|h| h.siblings.get(&sibling).cloned()),
+                consumers: consumers.len(),
+                last_used,
+                lagging,
+                sibling,
+            });
+        }
+
+        Ok(PlatformStatus {
+            env: self.env.name().to_owned(),
+            taken_at: now,
+            health_taken_at: health.map(|h| h.taken_at),
+            siblings,
+        })
+    }
+}
+
+impl PlatformStatus {
+    /// A standalone page reloading itself every `refresh`
+    pub fn to_html(&self, refresh: Duration) -> String {
+        let mut rows = String::new();
+        for s in &self.siblings {
+            let (class, health) = match &s.health {
+                _ if !s.enabled => ("disabled", "disabled".to_string()),
+                Some(h) => (
+                    status_class(h),
+                    escape(&format!("{:?}: {}", h.status, h.detail)),
+                ),
+                None => ("unknown", "not probed".to_string()),
+            };
+            let lagging = s
+                .lagging
+                .iter()
+                .map(|(consumer, version)| format!("{} (v{version})", escape(consumer)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            rows.push_str(&format!(
+                "<tr class=\"{class}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{health}</td>\
+                 <td>{}</td><td>{}</td><td>{lagging}</td></tr>\n",
+                escape(&s.sibling),
+                escape(&s.url),
+                s.version.map_or("-".to_string(), |v| format!("v{v}")),
+                s.age.map_or("-".to_string(), ago),
+                s.consumers,
+                s.last_used.map_or("never".to_string(), ago),
+            ));
+        }
+
+        let health = match self.health_taken_at {
+            Some(at) => format!("health from {} ago", ago(self.taken_at.saturating_sub(at))),
+            None => "no health snapshot published".to_string(),
+        };
+        format!(
+            r#"<!doctype html>
+<html><head><meta charset="utf-8"><meta http-equiv="refresh" content="{refresh}">
+<title>siblings: {env}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+td, th {{ padding: .3em .8em; border-bottom: 1px solid #ddd; text-align: left; }}
+.up td {{ background: #eaf7ea; }} .down td {{ background: #fbe3e3; }}
+.unknown td {{ background: #f4f4f4; }} .disabled td {{ color: #999; }}
+</style></head>
+<body><h1>siblings in {env}</h1>
+<p>{count} siblings, {health}, reloading every {refresh}s</p>
+<table><tr><th>Sibling</th><th>Url</th><th>Version</th><th>Published</th><th>Health</th><th>Consumers</th><th>Last used</th><th>Lagging</th></tr>
+{rows}</table></body></html>
+"#,
+            refresh = refresh.as_secs().max(1),
+            env = escape(&self.env),
+            count = self.siblings.len(),
+        )
+    }
+}
+
+fn status_class(health: &SiblingHealth) -> &'static str {
+    match health.status {
+        HealthStatus::Up => "up",
+        HealthStatus::Down => "down",
+        HealthStatus::Unknown => "unknown",
+    }
+}
+
+/// `42s`, `5m`, `3h`, `2d`
+fn ago(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_a_row_per_sibling() {
+        let status = PlatformStatus {
+            env: "dev".to_string(),
+            taken_at: 1_000,
+            health_taken_at: Some(940),
+            siblings: vec![SiblingStatus {
+                sibling: "k9".to_string(),
+                url: "https://k9/?a=1&b=<2>".to_string(),
+                version: Some(7),
+                enabled: true,
+                age: Some(7_200),
+                health: Some(SiblingHealth {
+                    status: HealthStatus::Down,
+                    url: None,
+                    detail: "refused".to_string(),
+                    took_ms: 3,
+                }),
+                consumers: 2,
+                last_used: None,
+                lagging: vec![("credit".to_string(), 6)],
+            }],
+        };
+
+        let html = status.to_html(Duration::from_secs(30));
+        assert!(html.contains(r#"<meta http-equiv="refresh" content="30">"#));
+        assert!(html.contains("health from 1m ago"));
+        assert!(html.contains(
+            "<tr class=\"down\"><td>k9</td><td>https://k9/?a=1&amp;b=&lt;2&gt;</td><td>v7</td>\
+             <td>2h</td><td>Down: refused</td><td>2</td><td>never</td><td>credit (v6)</td></tr>"
+        ));
+    }
+}
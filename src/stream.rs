@@ -0,0 +1,58 @@
+//! Streaming (websocket) endpoints of siblings that expose them alongside REST.
+
+use crate::{RegionEndpoint, Siblings};
+
+impl RegionEndpoint {
+    /// The websocket url for `region`: the record's `stream_url` if it has one, otherwise the REST
+    /// url with its scheme switched to `ws`/`wss`
+    pub fn ws_url(&self, region: Option<&str>) -> Option<String> {
+        if let Some(stream) = &self.stream_url {
+            return Some(stream.get(region).to_string());
+        }
+
+        let url = self.get_in(region)?;
+        if url.starts_with("ws://") || url.starts_with("wss://") {
+            return Some(url);
+        }
+
+        if let Some(rest) = url.strip_prefix("https://") {
+            Some(format!("wss://{rest}"))
+        } else {
+            url.strip_prefix("http://")
+                .map(|rest| format!("ws://{rest}"))
+        }
+    }
+}
+
+impl Siblings {
+    pub async fn ws_url(&self, sibling: &str, region: Option<&str>) -> Option<String> {
+        let region = self.region(region);
+        self.record(sibling, "ws_url").await?.ws_url(region)
+    }
+
+    /// Opens a websocket to `path` on the sibling's streaming endpoint
+    #[cfg(feature = "ws")]
+    pub async fn connect_ws(
+        &self,
+        sibling: &str,
+        region: Option<&str>,
+        path: &str,
+    ) -> anyhow::Result<
+        tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    > {
+        let base = self
+            .ws_url(sibling, region)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("no streaming endpoint for sibling {sibling}"))?;
+        let url = format!(
+            "{}/{}",
+            base.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        );
+
+        let (ws, _) = tokio_tungstenite::connect_async(url).await?;
+        Ok(ws)
+    }
+}
@@ -0,0 +1,98 @@
+//! Preferring endpoints close to the caller.
+//!
+//! Records may publish zonal endpoints next to regional ones, keyed by zone code:
+//! `{"default": "https://k9", "in": "https://k9.in", "in-a": "https://k9.in-a"}`. A deployment
+//! that declares where it runs with [`Siblings::with_topology`] (or `X_REGION` and `X_ZONE`)
+//! resolves lookups in its own region to its zone's endpoint first, then the region's, then the
+//! record's `default`. Lookups in any other region never pick a zonal endpoint.
+
+use std::{env, sync::Arc};
+
+use crate::{lookup_region, RegionEndpoint, Regions, Siblings};
+
+impl Siblings {
+    /// Declares the region and zone this deployment runs in: lookups passing no region resolve in
+    /// `region`, and lookups in `region` prefer the endpoint of `zone`
+    pub fn with_topology(mut self, region: Regions, zone: &str) -> Self {
+        self.zone = Some(zone.trim().to_lowercase());
+        self.with_default_region(region)
+    }
+
+    /// The zone set with `X_ZONE`, when set
+    pub(crate) fn with_zone_from_env(mut self) -> Self {
+        if let Ok(zone) = env::var("X_ZONE")
+            && !zone.trim().is_empty()
+        {
+            self.zone = Some(zone.trim().to_lowercase());
+        }
+        self
+    }
+
+    pub fn zone(&self) -> Option<&str> {
+        self.zone.as_deref()
+    }
+
+    /// The url of `ep` in `region` (default region applied), from this deployment's zone when
+    /// `region` is its own and the record has one
+    pub(crate) fn nearest(&self, ep: &RegionEndpoint, region: Option<&str>) -> Arc<str> {
+        let own = region.is_none_or(|r| {
+            self.default_region
+                .is_some_and(|own| Regions::parse(r).is_some_and(|r| r.code() == own.code()))
+        });
+        let zonal = self
+            .zone
+            .as_deref()
+            .filter(|_| own)
+            .and_then(|zone| lookup_region(&ep.regions, zone));
+
+        match zonal {
+            Some(url) => url.clone(),
+            None => ep.get_arc(region),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn zone_then_region_then_default() -> anyhow::Result<()> {
+        let k9 = Siblings::deserialize(
+            br#"{"default":"https://k9","in":"https://k9.in","in-a":"https://k9.in-a","us":"https://k9.us"}"#
+                .to_vec(),
+        )?;
+        let matrix = Siblings::deserialize(
+            br#"{"default":"https://matrix","in":"https://matrix.in"}"#.to_vec(),
+        )?;
+        let sib = Siblings::sidecar("/nonexistent.sock", None).with_topology(Regions::IN, "IN-A");
+        crate::update(&sib.endpoints, |e| {
+            e.insert("k9", k9.clone());
+            e.insert("matrix", matrix.clone());
+        });
+
+        assert_eq!(
+            sib.try_sibling("k9", None).await?.as_deref(),
+            Some("https://k9.in-a")
+        );
+        assert_eq!(
+            sib.try_sibling("k9", Some("IND")).await?.as_deref(),
+            Some("https://k9.in-a")
+        );
+        assert_eq!(
+            sib.try_sibling("k9", Some("us")).await?.as_deref(),
+            Some("https://k9.us")
+        );
+        assert_eq!(
+            sib.try_sibling("matrix", None).await?.as_deref(),
+            Some("https://matrix.in")
+        );
+        assert_eq!(sib.peek("k9", None).as_deref(), Some("https://k9.in-a"));
+        assert_eq!(
+            sib.endpoint_arc("k9", None).await?.as_deref(),
+            Some("https://k9.in-a")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,15 @@
+//! Accessors for callers that already hold a [`Regions`]: same lookups as the `Option<&str>`
+//! ones, without stringifying the region at every call site. The deprecated `Option` returning
+//! ones are in `compat`.
+
+use crate::{Regions, Siblings, SiblingsError};
+
+impl Siblings {
+    pub async fn try_sibling_in(
+        &self,
+        sibling: &str,
+        region: Regions,
+    ) -> Result<Option<String>, SiblingsError> {
+        self.try_sibling(sibling, Some(region.code())).await
+    }
+}
@@ -0,0 +1,185 @@
+//! Consumer usage reporting.
+//!
+//! With reporting on, every sibling a `Siblings` resolves is noted in memory and flushed on an
+//! interval into the hash `usage-{sibling}` as `{me} -> unix seconds of the last interval it was
+//! resolved in`, so `siblings-cli consumers k9` can tell who actually calls `k9` and
+//! `siblings-cli unused --days 30` which records nobody calls anymore.
+//!
+//! Each flush also reports the version of every record the consumer holds, see
+//! [`Siblings::wait_for_version`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::task::JoinHandle;
+
+use crate::{budget, cache, Siblings, SiblingsError};
+
+/// Siblings resolved since the last flush
+#[derive(Debug, Default)]
+pub(crate) struct Usage {
+    enabled: AtomicBool,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl Usage {
+    pub(crate) fn record(&self, sibling: &str) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        if !seen.contains(sibling) {
+            seen.insert(sibling.to_owned());
+        }
+    }
+
+    fn drain(&self) -> Vec<String> {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        seen.drain().collect()
+    }
+}
+
+impl Siblings {
+    /// Starts reporting which siblings this consumer resolved, flushed to Redis every `every`.
+    /// Needs `me`; anonymous instances have nothing to report under and return `None`.
+    pub fn report_usage(&self, every: Duration) -> Option<JoinHandle<()>> {
+        let Some(me) = self.me.clone() else {
+            log_to!(Health, Warn, "usage: reporting needs `me`, not starting");
+            return None;
+        };
+
+        self.usage.enabled.store(true, Ordering::Relaxed);
+
+        let slf = self.clone();
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(every);
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                let now = slf.unix_now();
+                slf.report_versions(&me, now).await;
+
+                let used = slf.usage.drain();
+                if used.is_empty() {
+                    continue;
+                }
+
+                for sibling in &used {
+                    if let Err(e) = slf.report(sibling, &me, now).await {
+                        log_to!(
+                            Health,
+                            Warn,
+                            "usage: reporting sibling[{sibling}] for consumer[{me}] failed: {e}"
+                        );
+                    }
+                }
+                log_to!(
+                    Health,
+                    Debug,
+                    "usage: reported {} siblings for consumer[{me}]",
+                    used.len()
+                );
+            }
+        }))
+    }
+
+    async fn report(&self, sibling: &str, me: &str, at: u64) -> Result<(), SiblingsError> {
+        let key = self.cache_key(&self.keys.usage(sibling));
+        let conn = self.backend.redis("usage reporting")?;
+        cache::hset(conn, &key, me, &at.to_string())
+            .await
+            .map_err(SiblingsError::unreachable)
+    }
+
+    /// Consumers that reported resolving `sibling`, with when they last did
+    pub async fn consumers(
+        &self,
+        sibling: &str,
+    ) -> Result<HashMap<String, SystemTime>, SiblingsError> {
+        let key = self.cache_key(&self.keys.usage(sibling));
+        let conn = self.backend.redis("usage reports")?;
+        budget::acquire(&key)?;
+        let reported = cache::hgetall(conn, &key)
+            .await
+            .map_err(SiblingsError::unreachable)?;
+
+        Ok(reported
+            .into_iter()
+            .filter_map(|(me, at)| {
+                let at = at.parse().ok()?;
+                Some((me, UNIX_EPOCH + Duration::from_secs(at)))
+            })
+            .collect())
+    }
+
+    /// Those of `siblings` no consumer reported resolving within the last `window`, in order
+    pub async fn unused(
+        &self,
+        siblings: &[&str],
+        window: Duration,
+    ) -> Result<Vec<String>, SiblingsError> {
+        let cutoff = self
+            .clock
+            .system_now()
+            .checked_sub(window)
+            .unwrap_or(UNIX_EPOCH);
+
+        let mut unused = Vec::new();
+        for &sibling in siblings {
+            let consumers = self.consumers(sibling).await?;
+            if !last_used(&consumers).is_some_and(|at| at >= cutoff) {
+                unused.push(sibling.to_owned());
+            }
+        }
+
+        Ok(unused)
+    }
+}
+
+/// Most recent report across all consumers
+fn last_used(consumers: &HashMap<String, SystemTime>) -> Option<SystemTime> {
+    consumers.values().max().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_only_when_enabled() {
+        let usage = Usage::default();
+        usage.record("k9");
+        assert!(usage.drain().is_empty());
+
+        usage.enabled.store(true, Ordering::Relaxed);
+        usage.record("k9");
+        usage.record("k9");
+        usage.record("matrix");
+
+        let mut used = usage.drain();
+        used.sort();
+        assert_eq!(used, vec!["k9", "matrix"]);
+        assert!(usage.drain().is_empty());
+    }
+
+    #[test]
+    fn last_used_is_latest_report() {
+        let old = UNIX_EPOCH + Duration::from_secs(100);
+        let new = UNIX_EPOCH + Duration::from_secs(200);
+
+        assert_eq!(last_used(&HashMap::new()), None);
+        assert_eq!(
+            last_used(&HashMap::from([("a".into(), old), ("b".into(), new)])),
+            Some(new)
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use tokio::task::JoinHandle;
+
+use crate::{RegionEndpoint, Siblings, SiblingsError};
+
+/// First wait of [`Siblings::retry_missing`], doubled after every round something is still missing
+pub const MIN_WARM_RETRY: Duration = Duration::from_secs(1);
+pub const MAX_WARM_RETRY: Duration = Duration::from_secs(60);
+
+/// Outcome of [`Siblings::warm_up`], one entry per requested sibling
+#[derive(Debug, Clone, Default)]
+pub struct WarmUpReport {
+    /// Siblings fetched and cached
+    pub resolved: Vec<String>,
+    /// Siblings with no endpoint published for this env
+    pub missing: Vec<String>,
+    /// Siblings whose fetch failed, with the error
+    pub errored: Vec<(String, String)>,
+    /// Time spent fetching each sibling, filled by [`Siblings::warm_up`]
+    pub latency: HashMap<String, Duration>,
+    /// Time the one batched read of [`Siblings::warm`] took for all the siblings together
+    pub batch_latency: Option<Duration>,
+}
+
+impl WarmUpReport {
+    /// `true` when every requested sibling resolved
+    pub fn is_ready(&self) -> bool {
+        self.missing.is_empty() && self.errored.is_empty()
+    }
+
+    /// `true` when `sibling` was resolved during warm up
+    pub fn is_resolved(&self, sibling: &str) -> bool {
+        self.resolved.iter().any(|s| s == sibling)
+    }
+
+    /// Siblings missing or errored, for [`Siblings::retry_missing`]
+    pub fn unresolved(&self) -> Vec<String> {
+        self.missing
+            .iter()
+            .chain(self.errored.iter().map(|(sibling, _)| sibling))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Siblings {
+    /// Fetches and caches each of `siblings` concurrently, one read each, reporting which ones
+    /// are actually available and how long each took so the caller can decide whether to
+    /// proceed, wait or bail out.
+    pub async fn warm_up(&self, siblings: &[&str]) -> WarmUpReport {
+        let fetches = siblings
+            .iter()
+            .map(|&sibling| {
+                let (slf, sibling) = (self.clone(), sibling.to_owned());
+                tokio::spawn(async move {
+                    let start = Instant::now();
+                    let fetched = slf.fetch(&sibling).await;
+                    (fetched, start.elapsed())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut report = WarmUpReport::default();
+        for (fetch, &sibling) in fetches.into_iter().zip(siblings) {
+            let (fetched, latency) = match fetch.await {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    report.errored.push((sibling.to_owned(), e.to_string()));
+                    continue;
+                }
+            };
+            report.latency.insert(sibling.to_owned(), latency);
+            self.warmed(&mut report, sibling, fetched);
+        }
+
+        log_warmed(&report);
+        report
+    }
+
+    /// Fetches and caches `siblings` with one pipelined MGET, so the first request to each after
+    /// a deploy doesn't pay the Redis round trip. Same report as [`Self::warm_up`], except the
+    /// time taken is the whole read's, in `batch_latency`.
+    pub async fn warm(&self, siblings: &[&str]) -> WarmUpReport {
+        let names = siblings.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let start = Instant::now();
+        let fetched = self.fetch_each(&names).await;
+
+        let mut report = WarmUpReport {
+            batch_latency: Some(start.elapsed()),
+            ..WarmUpReport::default()
+        };
+        let mut fetched = match fetched {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                report.errored = names.into_iter().map(|s| (s, e.to_string())).collect();
+                HashMap::new()
+            }
+        };
+        for &sibling in siblings {
+            if let Some(fetched) = fetched.remove(sibling) {
+                self.warmed(&mut report, sibling, fetched);
+            }
+        }
+
+        log_warmed(&report);
+        report
+    }
+
+    fn warmed(
+        &self,
+        report: &mut WarmUpReport,
+        sibling: &str,
+        fetched: Result<Option<RegionEndpoint>, SiblingsError>,
+    ) {
+        match fetched {
+            Ok(Some(ep)) => {
+                self.replace(sibling, Some(&ep));
+                report.resolved.push(sibling.to_owned());
+            }
+            Ok(None) => report.missing.push(sibling.to_owned()),
+            Err(e) => report.errored.push((sibling.to_owned(), e.to_string())),
+        }
+    }
+
+    /// Keeps fetching `siblings` in the background, after [`MIN_WARM_RETRY`] and doubling up to
+    /// [`MAX_WARM_RETRY`], until every one is published; each resolves as soon as it appears,
+    /// without a restart. For siblings a loader still races to publish at startup:
+    /// `siblings.retry_missing(siblings.warm_up(&required).await.unresolved())`
+    pub fn retry_missing(&self, siblings: Vec<String>) -> JoinHandle<()> {
+        let slf = self.clone();
+        tokio::spawn(async move {
+            let mut pending = siblings;
+            let mut backoff = MIN_WARM_RETRY;
+            while !pending.is_empty() {
+                tokio::time::sleep(backoff).await;
+
+                let mut fetched = match slf.fetch_each(&pending).await {
+                    Ok(fetched) => fetched,
+                    Err(e) => {
+                        log_to!(Health, Debug, "warm_up: retry failed: {e}");
+                        backoff = (backoff * 2).min(MAX_WARM_RETRY);
+                        continue;
+                    }
+                };
+
+                let mut still = Vec::new();
+                for sibling in pending {
+                    match fetched.remove(&sibling).unwrap_or(Ok(None)) {
+                        Ok(Some(ep)) => {
+                            slf.replace(&sibling, Some(&ep));
+                            log_to!(Health, Info, "warm_up: sibling[{sibling}] now resolvable")
+                        }
+                        Ok(None) => still.push(sibling),
+                        Err(e) => {
+                            log_to!(
+                                Health,
+                                Debug,
+                                "warm_up: sibling[{sibling}] retry failed: {e}"
+                            );
+                            still.push(sibling);
+                        }
+                    }
+                }
+                pending = still;
+                backoff = (backoff * 2).min(MAX_WARM_RETRY);
+            }
+        })
+    }
+}
+
+fn log_warmed(report: &WarmUpReport) {
+    log_to!(
+        Health,
+        Info,
+        "warm_up: resolved[{}] missing[{:?}] errored[{:?}]",
+        report.resolved.len(),
+        report.missing,
+        report.errored
+    );
+}
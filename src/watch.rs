@@ -0,0 +1,159 @@
+//! Pushing endpoint changes to long-lived components instead of having them poll.
+//!
+//! [`Siblings::watch`] hands out a `tokio::sync::watch` receiver per sibling and
+//! [`Siblings::on_change`] registers callbacks. Every record read from Redis into memory is
+//! passed on when it differs from the last one: lookups after a ttl, [`Siblings::refresh`],
+//! [`Siblings::spawn_refresher`], keyspace and announcement subscriptions, hydration and warm-up.
+//! A prune through this instance sends `None` to receivers. Flushes and pins don't, the record
+//! only changes once it's read again.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+
+use tokio::sync::watch;
+
+use crate::{RegionEndpoint, Siblings, SiblingsError};
+
+/// Called with the sibling and its new record
+pub type ChangeCallback = Box<dyn Fn(&str, &RegionEndpoint) + Send + Sync>;
+
+type SharedCallback = Arc<dyn Fn(&str, &RegionEndpoint) + Send + Sync>;
+
+#[derive(Default)]
+pub(crate) struct Observers {
+    watchers: HashMap<String, watch::Sender<Option<RegionEndpoint>>>,
+    callbacks: Vec<SharedCallback>,
+    /// Last record read per sibling, kept while there are callbacks
+    seen: HashMap<String, RegionEndpoint>,
+}
+
+impl Siblings {
+    /// The record of `sibling` now, then every change to it. Keep something refreshing it
+    /// (a ttl, [`Self::spawn_refresher`], [`Self::watch_keyspace`]..) or it never changes.
+    pub async fn watch(
+        &self,
+        sibling: &str,
+    ) -> Result<watch::Receiver<Option<RegionEndpoint>>, SiblingsError> {
+        let current = self.endpoint(sibling).await?;
+
+        let mut observers = self.observers.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(match observers.watchers.entry(sibling.to_owned()) {
+            // kept current by every read since it was created
+            Entry::Occupied(tx) => tx.get().subscribe(),
+            Entry::Vacant(slot) => slot.insert(watch::channel(current).0).subscribe(),
+        })
+    }
+
+    /// Calls `callback` whenever a record read into memory differs from the one read before it,
+    /// e.g. to rebuild an HTTP client. Not called for first reads or removals. Shared by every
+    /// clone of this instance; keep it short, the read that found the change waits for it.
+    pub fn on_change(&self, callback: ChangeCallback) {
+        let mut observers = self.observers.lock().unwrap_or_else(|e| e.into_inner());
+        observers.callbacks.push(Arc::from(callback));
+    }
+
+    /// Passes the record of `sibling` just read into memory on to watchers and callbacks
+    pub(crate) fn record_read(&self, sibling: &str, ep: Option<&RegionEndpoint>) {
+        let callbacks = {
+            let mut observers = self.observers.lock().unwrap_or_else(|e| e.into_inner());
+            observers.feed_watchers(sibling, ep);
+            observers.changed(sibling, ep)
+        };
+
+        if let Some(ep) = ep {
+            for callback in callbacks {
+                callback(sibling, ep);
+            }
+        }
+    }
+}
+
+impl Observers {
+    fn feed_watchers(&mut self, sibling: &str, ep: Option<&RegionEndpoint>) {
+        let Some(tx) = self.watchers.get(sibling) else {
+            return;
+        };
+        if tx.receiver_count() == 0 {
+            self.watchers.remove(sibling);
+            return;
+        }
+
+        tx.send_if_modified(|held| {
+            if held.as_ref() == ep {
+                return false;
+            }
+            *held = ep.cloned();
+            true
+        });
+    }
+
+    /// The callbacks to call if `ep` changed the record of `sibling`
+    fn changed(&mut self, sibling: &str, ep: Option<&RegionEndpoint>) -> Vec<SharedCallback> {
+        if self.callbacks.is_empty() {
+            return Vec::new();
+        }
+
+        let Some(ep) = ep else {
+            self.seen.remove(sibling);
+            return Vec::new();
+        };
+        match self.seen.insert(sibling.to_owned(), ep.clone()) {
+            Some(prev) if prev != *ep => self.callbacks.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::{RegionEndpoint, Siblings};
+
+    fn record(url: &str) -> RegionEndpoint {
+        RegionEndpoint {
+            default: url.into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn watchers_see_changes_only() -> anyhow::Result<()> {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let ep = record("https://k9");
+        crate::update(&sib.endpoints, |e| e.insert("k9", ep.clone()));
+
+        let mut rx = sib.watch("k9").await?;
+        assert_eq!(*rx.borrow_and_update(), Some(ep.clone()));
+
+        sib.record_read("k9", Some(&ep));
+        assert!(!rx.has_changed()?);
+
+        sib.record_read("k9", None);
+        assert!(rx.has_changed()?);
+        assert_eq!(*rx.borrow_and_update(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn callbacks_on_changes() {
+        let sib = Siblings::sidecar("/nonexistent.sock", None);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        sib.on_change(Box::new(move |sibling, ep| {
+            assert_eq!((sibling, &*ep.default), ("k9", "https://k9.new"));
+            counted.fetch_add(1, Ordering::Relaxed);
+        }));
+
+        sib.record_read("k9", Some(&record("https://k9")));
+        sib.record_read("k9", Some(&record("https://k9")));
+        sib.record_read("k9", Some(&record("https://k9.new")));
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,76 @@
+//! Registry of the inbound webhook urls siblings receive callbacks on (e.g. `xchange` callback
+//! receivers), published next to endpoints as `wh-{sibling}-{hook}` and cached the same way.
+
+use crate::{update, RegionValue, Siblings, SiblingsError};
+
+impl Siblings {
+    /// Publishes the urls `sibling` receives `hook` callbacks on, for the current env
+    pub async fn register_webhook(
+        &self,
+        sibling: &str,
+        hook: &str,
+        urls: RegionValue,
+    ) -> Result<(), SiblingsError> {
+        let data = serde_json::to_vec(&urls)?;
+        self.set_cache(&self.keys.webhook(sibling, hook), &data)
+            .await?;
+
+        info!("register_webhook: sibling[{sibling}] hook[{hook}] registered");
+        let key = self.keys.webhook(sibling, hook);
+        update(&self.endpoints, |e| {
+            e.webhooks.insert(key.clone(), urls.clone());
+        });
+
+        Ok(())
+    }
+
+    /// The url `sibling` receives `hook` callbacks on
+    pub async fn webhook(&self, sibling: &str, hook: &str, region: Option<&str>) -> Option<String> {
+        match self.try_webhook(sibling, hook, region).await {
+            Ok(Some(url)) => Some(url),
+            Ok(None) => {
+                log_to!(
+                    Resolve,
+                    Warn,
+                    "webhook: sibling[{sibling}] has no hook[{hook}] registered"
+                );
+                None
+            }
+            Err(e) => {
+                log_to!(
+                    Resolve,
+                    Warn,
+                    "webhook: sibling[{sibling}] hook[{hook}] was not fetched: {e}"
+                );
+                None
+            }
+        }
+    }
+
+    pub async fn try_webhook(
+        &self,
+        sibling: &str,
+        hook: &str,
+        region: Option<&str>,
+    ) -> Result<Option<String>, SiblingsError> {
+        let region = self.region(region);
+        let key = self.keys.webhook(sibling, hook);
+
+        if let Some(urls) = self.endpoints.load().webhooks.get(&key) {
+            return Ok(Some(urls.get(region).to_string()));
+        }
+
+        let data = self.get_cache(&key).await?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        let urls: RegionValue = serde_json::from_slice(&data)?;
+        let url = urls.get(region).to_string();
+        update(&self.endpoints, |e| {
+            e.webhooks.insert(key.clone(), urls.clone());
+        });
+
+        Ok(Some(url))
+    }
+}